@@ -0,0 +1,20 @@
+#!/usr/bin/env python3
+"""qwicket hook, configure it with `format = "json"`
+
+request(pre hook) or response(post hook) is given as a single json line on stdin
+and the modified one should be written to stdout as json
+"""
+import json
+import sys
+
+
+def transform(data, args):
+    """data is the request or response, args are the hook arguments given after `--`"""
+    # ex: data["headers"]["x-debug"] = "1"
+    return data
+
+
+if __name__ == "__main__":
+    data = json.loads(sys.stdin.readline())
+    # anything written to stderr is logged by qwicket
+    sys.stdout.write(json.dumps(transform(data, sys.argv[1:])))
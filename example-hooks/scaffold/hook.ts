@@ -0,0 +1,8 @@
+// qwicket hook, configure it with `js = "<path to this file>"`
+// request(pre hook) or response(post hook) is given to transform and the modified one should be returned
+
+// deno-lint-ignore no-explicit-any
+export function transform(data: any, args: string[]) {
+    // ex: data.headers["x-debug"] = "1";
+    return data;
+}
@@ -1,11 +1,22 @@
 use core::str;
-use std::{collections::HashMap, io::Read, ops::DerefMut, str::FromStr};
+use indexmap::IndexMap;
+use std::{collections::HashMap, io::Read, str::FromStr};
 
 use miette::{Context, IntoDiagnostic};
 use serde::{Deserialize, Serialize};
 use tracing::{debug, info, trace, warn};
 use yansi::Paint;
 
+mod checksum;
+mod encoding;
+mod expect;
+mod fault;
+mod files;
+mod rewrite;
+mod template;
+
+pub use fault::parse_rate;
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(rename_all = "snake_case")]
 enum HttpVersion {
@@ -16,6 +27,7 @@ enum HttpVersion {
     Http3,
 }
 
+#[allow(clippy::derivable_impls)]
 impl Default for HttpVersion {
     fn default() -> Self {
         Self::Http11
@@ -36,11 +48,48 @@ pub struct Environment {
     port: Option<u16>,
     prefix: Option<String>,
     #[serde(default)]
-    headers: HashMap<String, String>,
+    headers: IndexMap<String, String>,
     #[serde(default)]
     store: HashMap<String, String>,
     #[serde(default)]
     args: Vec<(String, String)>,
+    /// which ip family to use while connecting to the host
+    ip_family: Option<IpFamily>,
+    /// variables read from secret stores while executing the query
+    #[serde(default)]
+    secrets: HashMap<String, crate::secret::Secret>,
+    /// enable/disable hooks of the queries in this environment
+    #[serde(default)]
+    hooks: crate::hook::Settings,
+    /// directory of the relative `--output` files
+    output_dir: Option<std::path::PathBuf>,
+    /// archive responses of the queries in this environment
+    archive: Option<bool>,
+}
+
+/// ip family used while connecting, useful when one of the family is broken for a dual stack host
+#[derive(Debug, Deserialize, PartialEq, Eq, Clone, Copy, Serialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum IpFamily {
+    V4,
+    V6,
+    #[default]
+    Auto,
+}
+
+impl IpFamily {
+    /// binding to unspecified local address of a family makes client to connect only to that family
+    pub fn configure(self, builder: reqwest::ClientBuilder) -> reqwest::ClientBuilder {
+        match self {
+            IpFamily::V4 => {
+                builder.local_address(std::net::IpAddr::from(std::net::Ipv4Addr::UNSPECIFIED))
+            }
+            IpFamily::V6 => {
+                builder.local_address(std::net::IpAddr::from(std::net::Ipv6Addr::UNSPECIFIED))
+            }
+            IpFamily::Auto => builder,
+        }
+    }
 }
 
 impl Environment {
@@ -63,6 +112,101 @@ impl Environment {
         if !other.args.is_empty() {
             self.args.extend(other.args.clone());
         }
+        if let Some(parent_ip_family) = other.ip_family {
+            self.ip_family.get_or_insert(parent_ip_family);
+        }
+        if let Some(parent_output_dir) = &other.output_dir {
+            self.output_dir
+                .get_or_insert_with(|| parent_output_dir.clone());
+        }
+        if let Some(parent_archive) = other.archive {
+            self.archive.get_or_insert(parent_archive);
+        }
+        if let Some(parent_hooks_enabled) = other.hooks.enabled {
+            self.hooks.enabled.get_or_insert(parent_hooks_enabled);
+        }
+        for (name, secret) in &other.secrets {
+            self.secrets
+                .entry(name.clone())
+                .or_insert_with(|| secret.clone());
+        }
+    }
+
+    /// record values set by this environment, mirrors `apply`
+    /// values of inherited(parent group) environment fill only the missing ones, except scheme which is never inherited
+    pub fn explain(&self, origin: &str, inherited: bool, explain: &mut crate::explain::Explain) {
+        if let Some(scheme) = self.scheme.as_ref().filter(|_| !inherited) {
+            explain.fill("env", "scheme", scheme.clone(), origin);
+        }
+        if let Some(host) = &self.host {
+            explain.fill("env", "host", host.clone(), origin);
+        }
+        if let Some(port) = self.port {
+            explain.fill("env", "port", port.to_string(), origin);
+        }
+        if let Some(prefix) = &self.prefix {
+            explain.fill("env", "prefix", prefix.clone(), origin);
+        }
+        if let Some(ip_family) = self.ip_family {
+            explain.fill("env", "ip_family", format!("{ip_family:?}"), origin);
+        }
+        if let Some(output_dir) = &self.output_dir {
+            explain.fill(
+                "env",
+                "output_dir",
+                output_dir.display().to_string(),
+                origin,
+            );
+        }
+        if let Some(archive) = self.archive {
+            explain.fill("env", "archive", archive.to_string(), origin);
+        }
+        if let Some(hooks_enabled) = self.hooks.enabled {
+            explain.fill("env", "hooks.enabled", hooks_enabled.to_string(), origin);
+        }
+        // maps are extended with parent values, so parent overrides
+        for (name, value) in &self.headers {
+            explain.set("header", name, value.clone(), origin);
+        }
+        for (name, value) in &self.store {
+            explain.set("store", name, value.clone(), origin);
+        }
+        for (name, value) in &self.args {
+            explain.set("arg", name, value.clone(), origin);
+        }
+        for name in self.secrets.keys() {
+            explain.fill("secret", name, crate::redact::MASK.to_string(), origin);
+        }
+    }
+
+    pub fn ip_family(&self) -> IpFamily {
+        self.ip_family.unwrap_or_default()
+    }
+
+    /// headers of the environment and project defaults with variables substituted, as a query of this environment gets them
+    pub fn substituted_headers(
+        &self,
+        store: &HashMap<String, String>,
+        config: &crate::parser::Config,
+    ) -> miette::Result<Vec<(String, String)>> {
+        let mut headers = self.headers.clone();
+        for (name, value) in &config.defaults.headers {
+            if !headers.keys().any(|key| key.eq_ignore_ascii_case(name)) {
+                headers.insert(name.clone(), value.clone());
+            }
+        }
+        let mut local_store = store.clone();
+        local_store.extend(self.store.clone());
+        let vars = crate::secret::Variables::new(local_store, &self.secrets, &config.provider);
+        headers
+            .into_iter()
+            .map(|(name, value)| {
+                let value = crate::variable::substitute(&value, &vars)
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Couldn't substitute environment header {name}"))?;
+                Ok((name.to_ascii_lowercase(), value))
+            })
+            .collect()
     }
 
     /// Gives columns presennt in this structure
@@ -71,6 +215,42 @@ impl Environment {
         &["scheme", "host", "port"]
     }
 
+    /// url formed by scheme, host and port
+    fn origin(&self) -> miette::Result<reqwest::Url> {
+        let host = self.host.as_ref().ok_or(miette::miette!("Host is empty"))?;
+        let scheme = self
+            .scheme
+            .as_ref()
+            .ok_or(miette::miette!("Scheme is empty"))?;
+        let url_str = if let Some(port) = self.port {
+            format!("{scheme}://{host}:{port}",)
+        } else {
+            format!("{scheme}://{host}")
+        };
+
+        reqwest::Url::parse(&url_str)
+            .into_diagnostic()
+            .wrap_err("Couldn't parse given url")
+    }
+
+    /// url of the given path(can contain query string) under this environment's prefix
+    pub fn url_for(&self, path: &str) -> miette::Result<reqwest::Url> {
+        let path = join_url_path(self.prefix.as_deref().unwrap_or_default(), path);
+        self.origin()?
+            .join(&path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Couldn't construct url for {path}"))
+    }
+
+    /// url formed by scheme, host, port and prefix, missing parts are left empty
+    pub fn base_url(&self) -> String {
+        let scheme = self.scheme.as_deref().unwrap_or_default();
+        let host = self.host.as_deref().unwrap_or_default();
+        let port = self.port.map(|p| format!(":{p}")).unwrap_or_default();
+        let prefix = self.prefix.as_deref().unwrap_or_default();
+        format!("{scheme}://{host}{port}{prefix}")
+    }
+
     pub fn to_row(&self) -> Vec<String> {
         let scheme = self.scheme.clone().unwrap_or_default();
         let host = self.host.clone().unwrap_or_default();
@@ -79,6 +259,50 @@ impl Environment {
     }
 }
 
+impl HttpVersion {
+    /// client which speaks this version of http
+    /// cleartext http2 is used with prior knowledge since there is no ALPN to negotiate it
+    fn client(
+        &self,
+        url: &reqwest::Url,
+        ip_family: IpFamily,
+        trace_transport: bool,
+    ) -> miette::Result<reqwest::Client> {
+        let builder = ip_family.configure(
+            reqwest::Client::builder()
+                .user_agent(APP_USER_AGENT)
+                .tls_info(trace_transport)
+                .connection_verbose(trace_transport),
+        );
+        let builder = match self {
+            HttpVersion::Http09 | HttpVersion::Http10 | HttpVersion::Http11 => builder.http1_only(),
+            HttpVersion::Http2 if url.scheme() == "http" => builder.http2_prior_knowledge(),
+            HttpVersion::Http2 => builder,
+            HttpVersion::Http3 => http3_client_builder(builder)?,
+        };
+        builder
+            .build()
+            .into_diagnostic()
+            .wrap_err("Couldn't build client")
+    }
+}
+
+#[cfg(feature = "http3")]
+fn http3_client_builder(builder: reqwest::ClientBuilder) -> miette::Result<reqwest::ClientBuilder> {
+    Ok(builder.http3_prior_knowledge())
+}
+
+#[cfg(not(feature = "http3"))]
+fn http3_client_builder(
+    _builder: reqwest::ClientBuilder,
+) -> miette::Result<reqwest::ClientBuilder> {
+    miette::bail!(
+        help = "rebuild with `--features http3` and RUSTFLAGS=\"--cfg reqwest_unstable\"",
+        "{} is built without http3 support",
+        env!("CARGO_PKG_NAME")
+    )
+}
+
 impl From<HttpVersion> for reqwest::Version {
     fn from(value: HttpVersion) -> Self {
         match value {
@@ -104,7 +328,7 @@ impl TryFrom<reqwest::Version> for HttpVersion {
     }
 }
 
-static APP_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"),);
+pub static APP_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"),);
 
 #[derive(Debug, Deserialize, Clone, Serialize)]
 struct BasicAuth {
@@ -113,13 +337,15 @@ struct BasicAuth {
 }
 
 impl BasicAuth {
-    fn substitute(self, vars: &HashMap<String, String>) -> Result<Self, subst::Error> {
+    fn substitute(self, vars: &crate::secret::Variables) -> Result<Self, subst::Error> {
         let Self {
             user_name,
             password,
         } = self;
-        let user_name = subst::substitute(&user_name, vars)?;
-        let password = password.map(|p| subst::substitute(&p, vars)).transpose()?;
+        let user_name = crate::variable::substitute(&user_name, vars)?;
+        let password = password
+            .map(|p| crate::variable::substitute(&p, vars))
+            .transpose()?;
         Ok(Self {
             user_name,
             password,
@@ -135,22 +361,150 @@ impl BasicAuth {
 enum StdinBody {
     #[serde(rename = "body")]
     Tagged(TaggedBody),
-    Form(HashMap<String, String>),
-    Multipart(HashMap<String, Part>),
+    Form(IndexMap<String, String>),
+    Multipart(IndexMap<String, Part>),
+}
+
+/// http protocol, queries are sent with reqwest
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Http;
+
+impl super::Agent for Http {
+    type Query = Query;
+    type Environment = Environment;
+
+    fn query_columns() -> &'static [&'static str] {
+        Query::headers()
+    }
+
+    fn query_row(query: &Query) -> Vec<String> {
+        query.to_row()
+    }
+
+    fn environment_columns() -> &'static [&'static str] {
+        Environment::headers()
+    }
+
+    fn environment_row(environment: &Environment) -> Vec<String> {
+        environment.to_row()
+    }
+
+    fn is_hidden(query: &Query) -> bool {
+        query.is_hidden()
+    }
+
+    fn is_deprecated(query: &Query) -> bool {
+        query.is_deprecated()
+    }
+
+    fn has_tags(query: &Query, tags: &[String]) -> bool {
+        query.has_tags(tags)
+    }
+
+    fn matching_field<'e>(
+        query: &Query,
+        pattern: &str,
+        environments: impl Iterator<Item = &'e Environment>,
+    ) -> Option<&'static str> {
+        query.matching_field(pattern, environments)
+    }
+
+    fn lint(
+        query: &Query,
+        file: Option<&std::path::Path>,
+        key: &[String],
+        lints: &mut crate::lint::Lints,
+    ) {
+        query.lint(file, key, lints)
+    }
+
+    fn inherit(environment: &mut Environment, parent: &Environment) {
+        environment.apply(parent)
+    }
+
+    async fn execute(
+        query: Query,
+        name: &str,
+        environment: Environment,
+        store: &mut crate::store::Store,
+        args: &crate::Arguments,
+        config: &crate::parser::Config,
+        stdin: Option<&[u8]>,
+    ) -> miette::Result<Option<crate::output::QueryResponse>> {
+        query
+            .execute(name, environment, store, args, config, stdin)
+            .await
+    }
 }
 
 #[derive(Debug, Deserialize, Clone, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct Query {
+    /// can be multiple paragraphs, wrapped while listing
     description: Option<String>,
+    /// link to the api documentation
+    docs_url: Option<String>,
+    /// example invocations shown while listing
+    #[serde(default)]
+    examples: Vec<String>,
+    /// reason/alternative for deprecation, warns when the query is executed
+    deprecated: Option<String>,
+    /// hidden queries are not listed but they can still be executed
+    #[serde(default)]
+    hidden: bool,
+    /// labels used to filter queries while listing or running groups
+    #[serde(default)]
+    tags: Vec<String>,
+    /// send validators(etag/last-modified) of the previous response as conditional headers
+    #[serde(default)]
+    use_etag: bool,
+    /// send a HEAD/OPTIONS request before the actual request, actual request is sent only if it succeeds
+    preflight: Option<Preflight>,
+    /// health check queries are run with `--health`
+    #[serde(default)]
+    kind: QueryKind,
+    /// thresholds of the health check query
+    #[serde(default)]
+    health: HealthCheck,
+    /// variables used in the query
+    #[serde(default)]
+    variables: IndexMap<String, crate::variable::Variable>,
+    /// changes applied to the request after substitution
+    #[serde(default)]
+    rewrite: Vec<rewrite::Rule>,
+    /// changes applied to the response before it is shown
+    #[serde(default)]
+    response_rewrite: Vec<rewrite::ResponseRule>,
+    /// keep a copy of every response in the project cache
+    archive: Option<bool>,
+    /// send the body as it is, without substituting variables in it
+    #[serde(default)]
+    raw_body: bool,
+    /// render the body with given template engine instead of substitution
+    template: Option<template::Template>,
+    /// decode responses of this encoding to json
+    response_encoding: Option<encoding::Encoding>,
+    /// send digest of the final body in a header
+    checksum: Option<checksum::Checksum>,
+    /// send `Idempotency-Key` header, key is reused until the request gets a final response
+    idempotency_key: Option<IdempotencyKey>,
+    /// wait and resend when 429/503 response carries `Retry-After`
+    #[serde(default)]
+    respect_retry_after: bool,
+    /// give the response body to post hook as a file in the workspace(`body_file`) instead of inline bytes
+    #[serde(default)]
+    post_hook_body_file: bool,
+    /// query fails when the response doesn't meet these
+    #[serde(default)]
+    expect: expect::Expect,
     path: String,
     method: String,
     #[serde(default)]
-    headers: HashMap<String, String>,
+    headers: IndexMap<String, String>,
     #[serde(default)]
     args: Vec<(String, String)>,
-    #[serde(default = "default_timeout")]
-    timeout: std::time::Duration,
+    /// falls back to project default timeout, then 30 seconds
+    timeout: Option<std::time::Duration>,
     #[serde(default)]
     version: HttpVersion,
     basic_auth: Option<BasicAuth>,
@@ -158,8 +512,8 @@ pub struct Query {
     pre_hook: Option<crate::hook::Hook>,
     post_hook: Option<crate::hook::Hook>,
     body: Option<TaggedBody>,
-    form: Option<HashMap<String, String>>,
-    multipart: Option<HashMap<String, Part>>,
+    form: Option<IndexMap<String, String>>,
+    multipart: Option<IndexMap<String, Part>>,
 }
 
 impl Query {
@@ -174,56 +528,240 @@ impl Query {
         vec![self.method.clone(), self.path.clone()]
     }
 
+    pub fn is_hidden(&self) -> bool {
+        self.hidden
+    }
+
+    pub fn is_deprecated(&self) -> bool {
+        self.deprecated.is_some()
+    }
+
+    /// which field of the query contains the pattern, pattern should be in lower case
+    /// url is matched against all the given environments
+    pub fn matching_field<'e>(
+        &self,
+        pattern: &str,
+        mut environments: impl Iterator<Item = &'e Environment>,
+    ) -> Option<&'static str> {
+        let contains = |s: &str| s.to_lowercase().contains(pattern);
+        if contains(&self.path) {
+            Some("path")
+        } else if self.description.as_deref().is_some_and(contains) {
+            Some("description")
+        } else if environments.any(|env| contains(&format!("{}{}", env.base_url(), self.path))) {
+            Some("url")
+        } else {
+            None
+        }
+    }
+
+    /// check if query contains all the given tags
+    pub fn has_tags(&self, tags: &[String]) -> bool {
+        tags.iter().all(|tag| self.tags.contains(tag))
+    }
+
+    /// thresholds if this is a health check query
+    pub fn health_check(&self) -> Option<&HealthCheck> {
+        match self.kind {
+            QueryKind::Healthcheck => Some(&self.health),
+            QueryKind::Request => None,
+        }
+    }
+
+    /// report common mistakes of the query, key is the path of the query in its file
+    pub fn lint(
+        &self,
+        file: Option<&std::path::Path>,
+        key: &[String],
+        lints: &mut crate::lint::Lints,
+    ) {
+        let key_of = |parts: &[&str]| {
+            key.iter()
+                .cloned()
+                .chain(parts.iter().map(ToString::to_string))
+                .collect::<Vec<_>>()
+        };
+        if self.description.is_none() {
+            lints.add(
+                file,
+                key.to_vec(),
+                "query has no description",
+                "add `description`, it is shown while listing the query",
+                None,
+            );
+        }
+        for (name, value) in &self.headers {
+            if !name.eq_ignore_ascii_case("authorization") {
+                continue;
+            }
+            if let Some(token) = value.strip_prefix("Bearer ") {
+                let fix = self
+                    .bearer_auth
+                    .is_none()
+                    .then(|| crate::lint::Fix::BearerAuth {
+                        header: name.clone(),
+                        token: token.to_string(),
+                    });
+                lints.add(
+                    file,
+                    key.to_vec(),
+                    "bearer token is sent as a header",
+                    "use `bearer_auth`",
+                    fix,
+                );
+            } else if value.starts_with("Basic ") {
+                lints.add(
+                    file,
+                    key.to_vec(),
+                    "basic credentials are sent as a header",
+                    "use `basic_auth = { username = \"..\", password = \"..\" }`",
+                    None,
+                );
+            }
+        }
+        let content_type = self.body.as_ref().and_then(TaggedBody::content_type);
+        if let Some(content_type) = content_type.filter(|ct| ct.parse::<mime::Mime>().is_err()) {
+            let message = if content_type.trim().is_empty() {
+                "body has no content type".to_string()
+            } else {
+                format!("{content_type:?} is not a valid content type")
+            };
+            lints.add(
+                file,
+                key_of(&["body"]),
+                message,
+                "set `content_type` of the body to a mime type, ex: text/plain",
+                None,
+            );
+        }
+    }
+
+    /// record values set by the query and project defaults, mirrors merging in `execute`
+    pub fn explain(
+        &self,
+        origin: &str,
+        config: &crate::parser::Config,
+        explain: &mut crate::explain::Explain,
+    ) {
+        explain.set("query", "method", self.method.clone(), origin);
+        explain.set("query", "path", self.path.clone(), origin);
+        for (name, value) in &self.headers {
+            explain.set("header", name, value.clone(), origin);
+        }
+        for (name, value) in &config.defaults.headers {
+            if !explain.contains("header", name) {
+                explain.set("header", name, value.clone(), "project defaults");
+            }
+        }
+        for (name, value) in &self.args {
+            explain.set("arg", name, value.clone(), origin);
+        }
+        let (timeout, timeout_origin) = match (self.timeout, config.defaults.timeout) {
+            (Some(timeout), _) => (timeout, origin),
+            (None, Some(timeout)) => (timeout, "project defaults"),
+            (None, None) => (default_timeout(), "built in"),
+        };
+        explain.set(
+            "query",
+            "timeout",
+            humantime::format_duration(timeout).to_string(),
+            timeout_origin,
+        );
+    }
+
     pub async fn execute(
         mut self,
+        name: &str,
         environ: Environment,
         store: &mut crate::store::Store,
         cmd_args: &crate::Arguments,
+        config: &crate::parser::Config,
         stdin: Option<&[u8]>,
-    ) -> miette::Result<Option<crate::parser::QueryResponse>> {
+    ) -> miette::Result<Option<crate::output::QueryResponse>> {
+        if let Some(reason) = &self.deprecated {
+            warn!("query is deprecated: {reason}");
+        }
         trace!("Merging Query wit env");
+        let mut base_url = environ.origin()?;
+        let ip_family = environ.ip_family();
         let Environment {
-            scheme,
-            host,
-            port,
             prefix: env_prefix,
             mut headers,
             store: env_store,
             args: mut query_args,
+            secrets,
+            hooks: env_hooks,
+            output_dir,
+            archive: env_archive,
+            ..
         } = environ;
-        let host = host.ok_or(miette::miette!("Host is empty"))?;
-        let scheme = scheme.ok_or(miette::miette!("Scheme is empty"))?;
+        let archive = crate::archive::is_enabled(self.archive, env_archive, &config.archive)
+            && !cmd_args.dry_run;
+        let output = cmd_args
+            .output
+            .as_deref()
+            .map(|output| output_path(output, output_dir.as_deref()));
         headers.extend(self.headers);
+        // project default headers apply only if environment or query doesn't have them in any case
+        for (name, value) in &config.defaults.headers {
+            if !headers.keys().any(|key| key.eq_ignore_ascii_case(name)) {
+                headers.insert(name.clone(), value.clone());
+            }
+        }
         self.headers = headers;
+        self.timeout = self.timeout.or(config.defaults.timeout);
         query_args.extend(self.args);
         self.args = query_args;
 
-        let url_str = if let Some(port) = port {
-            format!("{scheme}://{host}:{port}",)
-        } else {
-            format!("{scheme}://{host}")
-        };
-
-        let url = reqwest::Url::parse(&url_str)
-            .into_diagnostic()
-            .wrap_err("Couldn't parse given url")?;
-        let base_url = if let Some(prefix) = env_prefix {
-            url.join(&prefix)
-                .into_diagnostic()
-                .wrap_err_with(|| format!("Couldn't append environment prefix: {prefix}"))?
-        } else {
-            url
-        };
+        // prefix is joined to path before substitution so that both can contain variables
+        self.path = join_url_path(env_prefix.as_deref().unwrap_or_default(), &self.path);
 
         debug!(url = ?base_url, "Costructed base Url");
+        if cmd_args.strict {
+            let query_text = serde_json::to_string(&self)
+                .into_diagnostic()
+                .wrap_err("Couldn't serialize query")?;
+            for key in env_store
+                .keys()
+                .chain(cmd_args.vars.iter().map(|(key, _)| key))
+                .filter(|key| !crate::variable::is_referenced(&query_text, key))
+            {
+                warn!("store key {key} is not referenced by the query");
+            }
+        }
         let mut local_store = std::ops::Deref::deref(store).clone();
         local_store.extend(env_store);
-
-        let pre_hook = self.pre_hook.take();
-        let post_hook = self.post_hook.take();
+        local_store.extend(cmd_args.vars.iter().cloned());
+        crate::variable::resolve(&self.variables, &mut local_store, |name| {
+            secrets.contains_key(name) || config.provider.contains_key(name)
+        })?;
+
+        let use_etag = self.use_etag;
+        let respect_retry_after = self.respect_retry_after;
+        let post_hook_body_file = self.post_hook_body_file;
+        let preflight = self.preflight.take();
+        let hook_enabled = |hook: &crate::hook::Hook| {
+            let enabled = hook.is_enabled(&env_hooks, &config.hooks);
+            if !enabled {
+                debug!("hook is disabled in config, skipping {hook:?}");
+            }
+            enabled
+        };
+        let rewrite_rules = std::mem::take(&mut self.rewrite);
+        let response_rules = std::mem::take(&mut self.response_rewrite);
+        let expect = std::mem::take(&mut self.expect);
+        let response_encoding = self.response_encoding.take();
+        let body_checksum = self.checksum.take();
+        let idempotency_key = self.idempotency_key.take();
+        let raw_body = self.raw_body;
+        let body_template = self.template;
+        let pre_hook = self.pre_hook.take().filter(hook_enabled);
+        let post_hook = self.post_hook.take().filter(hook_enabled);
         let mut hook_args = cmd_args.args.split(|flag| flag == "--");
         let pre_hook_args = hook_args.next().unwrap_or(&[]);
         let post_hook_args = hook_args.next().unwrap_or(&[]);
+        // removed when the query is done, after post hook
+        let workspace = crate::workspace::Workspace::new(cmd_args.keep_temp);
 
         if let Some(stdin) = stdin {
             let stdin_body = rmp_serde::decode::from_slice::<StdinBody>(stdin)
@@ -238,63 +776,319 @@ impl Query {
 
         let prepared_query: PreparedQuery = self.try_into().wrap_err("Couldn't Create Query")?;
         if cmd_args.inspect_request {
-            let body_buf = crate::hook::to_msgpack(&prepared_query)
+            let mut prepared_query = prepared_query;
+            prepared_query.redact(&config.redact);
+            prepared_query.remove_commands();
+            let body_buf = crate::hook::to_msgpack(&crate::hook::versioned(&prepared_query))
                 .into_diagnostic()
                 .wrap_err("serializing input body")?;
-            return Ok(Some(body_buf));
+            return Ok(Some(crate::output::QueryResponse::raw(body_buf)));
         }
         let query = pre_hook
             .filter(|_| !(cmd_args.skip_hooks || cmd_args.skip_prehook))
-            .map(|hook| hook.run(&prepared_query, pre_hook_args))
+            .map(|hook| hook.run(&prepared_query, pre_hook_args, workspace.path()?))
             .transpose()
             .wrap_err("Failed to run pre hook")?
             .unwrap_or(prepared_query);
 
-        let substituted_query = query
-            .substitute(&local_store)
+        let mut query = query;
+        let check_body = !(raw_body || body_template.is_some());
+        let escaped = cmd_args.strict.then(|| query.escaped(check_body));
+        let vars = crate::secret::Variables::new(local_store, &secrets, &config.provider)
+            .with_expiry(store.expiry());
+        let unsubstituted_body = query
+            .body
+            .take_if(|_| raw_body || body_template.is_some())
+            .map(|body| match (body, body_template) {
+                (UnpackedBody::Utf8(body), Some(body_template)) => {
+                    body_template.render(&body, &vars).map(UnpackedBody::Utf8)
+                }
+                (_, Some(_)) => Err(miette::miette!(
+                    help = "use a text body(ex: `body.json`) with template",
+                    "Only text bodies can be templated"
+                )),
+                (body, None) => Ok(body),
+            })
+            .transpose()?;
+        let mut substituted_query = query
+            .substitute(&vars)
             .into_diagnostic()
             .wrap_err("Couldn't substitute Query request")?;
-        let client = reqwest::Client::builder()
-            .user_agent(APP_USER_AGENT)
-            .build()
-            .into_diagnostic()
-            .wrap_err("Couldn't build client")?;
+        if unsubstituted_body.is_some() {
+            substituted_query.body = unsubstituted_body;
+        }
+        substituted_query
+            .generate_body(&workspace)
+            .wrap_err("Couldn't generate body")?;
+        rewrite::apply(&rewrite_rules, &mut substituted_query);
+        if cmd_args.edit {
+            let editor = std::env::var("VISUAL")
+                .or_else(|_| std::env::var("EDITOR"))
+                .unwrap_or_else(|_| "vi".to_string());
+            (base_url, substituted_query) =
+                edit_request(base_url, substituted_query, &editor, workspace.path()?)
+                    .wrap_err("Couldn't edit the request")?;
+        }
+        if let Some(escaped) = escaped {
+            let unresolved = substituted_query.unresolved(escaped, check_body);
+            if !unresolved.is_empty() {
+                miette::bail!(
+                    help = "define them in store, environment or with --var",
+                    "Unresolved variables: {}",
+                    unresolved.join(", ")
+                )
+            }
+        }
+        let client =
+            substituted_query
+                .version
+                .client(&base_url, ip_family, cmd_args.trace_transport)?;
 
-        let request = substituted_query
+        let mut request = substituted_query
             .into_request(base_url, &client)
             .wrap_err("Couldn't construct Query")?;
+        let request_url = request.url().to_string();
+        let redacted_url = config.redact.url(request.url());
+        let request_method = request.method().to_string();
+        let request_path = request.url().path().to_string();
+        let mut validators = use_etag.then(|| {
+            store
+                .cache::<Validators>("validators")
+                .with_limit(VALIDATORS_LIMIT)
+        });
+        if let Some(validators) = &validators {
+            apply_validators(&mut request, &request_url, validators)?;
+        }
+        if let Some(range) = range_header(cmd_args, output.as_deref())? {
+            request.headers_mut().insert(reqwest::header::RANGE, range);
+        }
+        if let Some(body_checksum) = &body_checksum {
+            body_checksum
+                .apply(&mut request)
+                .wrap_err("Couldn't add checksum header")?;
+        }
+        let idempotency = match idempotency_key {
+            Some(IdempotencyKey::Auto) => {
+                let mut keys = store.cache::<String>("idempotency");
+                let operation = idempotency_operation(name, &request);
+                let key = apply_idempotency_key(&mut request, &operation, &mut keys)?;
+                if !cmd_args.quiet {
+                    eprintln!("idempotency key: {key}");
+                }
+                Some((keys, operation))
+            }
+            None => None,
+        };
 
-        display_request(&request);
+        display_request(&request, &config.redact);
 
-        let response = client
-            .execute(request)
-            .await
-            .into_diagnostic()
-            .wrap_err("Request failed")?;
+        if cmd_args.preview {
+            let preview = PreviewRequest(&request, &config.redact).to_string();
+            return Ok(Some(crate::output::QueryResponse::raw(
+                preview.into_bytes(),
+            )));
+        }
 
-        // convert response so that it can be sent to post hook
-        let response = Response::read_response(response)
-            .await
-            .wrap_err("Couldn't read response")?;
+        let (response, elapsed) = if cmd_args.dry_run {
+            let Some(fake_response) = &cmd_args.fake_response else {
+                info!("dry run, not sending the request");
+                return Ok(None);
+            };
+            info!("dry run, using fake response from {fake_response:?}");
+            let response =
+                Response::read_fake(fake_response).wrap_err("Couldn't read fake response")?;
+            (response, None)
+        } else {
+            if let Some(preflight) = preflight {
+                preflight
+                    .run(&client, &request)
+                    .await
+                    .wrap_err("Preflight failed")?;
+            }
+            let mut request = request;
+            let mut retries = RETRY_AFTER_ATTEMPTS;
+            let mut budget = RETRY_AFTER_BUDGET;
+            let faults = fault::Faults::from_args(cmd_args);
+            let (response, started) = loop {
+                // streamed bodies can't be cloned, such requests are sent only once
+                let next = respect_retry_after.then(|| request.try_clone()).flatten();
+                let started = std::time::Instant::now();
+                faults.inject(request.timeout().copied()).await?;
+                let response = client
+                    .execute(request)
+                    .await
+                    .inspect_err(|e| {
+                        if cmd_args.trace_transport {
+                            eprintln!("{}", TransportError(e));
+                        }
+                    })
+                    .into_diagnostic()
+                    .wrap_err("Request failed")?;
+                let Some((next, wait)) = next.zip(retry_after(&response)) else {
+                    break (response, started);
+                };
+                if retries == 0 || wait > budget {
+                    warn!(
+                        "not retrying {}, retry budget is exhausted",
+                        response.status()
+                    );
+                    break (response, started);
+                }
+                warn!(
+                    "{} responded, retrying after {}",
+                    response.status(),
+                    humantime::format_duration(wait)
+                );
+                retries -= 1;
+                budget -= wait;
+                tokio::time::sleep(wait).await;
+                request = next;
+            };
+            if cmd_args.trace_transport {
+                eprintln!("{}", TransportInfo(&response));
+            }
+
+            // convert response so that it can be sent to post hook
+            let response = Response::read_response(response, &config.redact)
+                .await
+                .wrap_err("Couldn't read response")?;
+            let elapsed = started.elapsed();
+            if !(cmd_args.quiet || cmd_args.health || cmd_args.race) {
+                eprintln!(
+                    "{}",
+                    StatusLine {
+                        method: &request_method,
+                        path: &request_path,
+                        response: &response,
+                        elapsed,
+                    }
+                );
+            }
+            crate::sink::publish(
+                &config.sinks,
+                &crate::sink::Summary {
+                    time: humantime::format_rfc3339_millis(std::time::SystemTime::now())
+                        .to_string(),
+                    user: std::env::var("USER").ok(),
+                    project: &config.project,
+                    query: name,
+                    env: store.environment(),
+                    method: &request_method,
+                    url: &redacted_url,
+                    status: response.status_code,
+                    duration_ms: elapsed.as_millis(),
+                },
+            );
+            // injected latency, failed responses and temporary stores would skew the baseline
+            let status = response.status_code;
+            if cmd_args.inject_latency.is_none()
+                && store.is_persistent()
+                && (200..400).contains(&status)
+            {
+                let regression = crate::baseline::record(
+                    store.baseline_path(),
+                    store.environment().to_string(),
+                    name.to_string(),
+                    elapsed,
+                    cmd_args.perf_guard,
+                )
+                .await;
+                if let Some(regression) = regression {
+                    warn!("{name} {regression}");
+                }
+            }
+            (response, Some(elapsed))
+        };
+
+        info!("{}", BodySummary(&response));
+        // headers as the server sent them, before hooks and rewrites change them
+        let headers_checked = expect.check_headers(&response.headers);
+        if let Some((mut keys, operation)) = idempotency {
+            let status = response.status_code;
+            if status >= 500 || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+                debug!("keeping idempotency key for retrying, response status is {status}");
+            } else {
+                keys.remove(&operation);
+                keys.save();
+            }
+        }
+        if let Some(validators) = &mut validators {
+            if response.status_code == reqwest::StatusCode::NOT_MODIFIED {
+                info!("resource is not modified since the last request, use previously saved body");
+            }
+            save_validators(&response, &request_url, validators);
+        }
 
         if cmd_args.inspect_response {
-            let body_buf = crate::hook::to_msgpack(&response)
+            let mut response = response;
+            response.redact(&config.redact);
+            let body_buf = crate::hook::to_msgpack(&crate::hook::versioned(&response))
                 .into_diagnostic()
                 .wrap_err("failed to serialize response")?;
-            return Ok(Some(body_buf));
+            return Ok(Some(crate::output::QueryResponse::raw(body_buf)));
         }
 
-        let mut response = post_hook
-            .filter(|_| !(cmd_args.skip_hooks || cmd_args.skip_posthook))
-            .map(|hook| hook.run(&response, post_hook_args))
-            .transpose()
-            .wrap_err("Failed to run post hook")?
-            .unwrap_or(response);
+        let mut response =
+            match post_hook.filter(|_| !(cmd_args.skip_hooks || cmd_args.skip_posthook)) {
+                Some(hook) => {
+                    let mut response = response;
+                    if post_hook_body_file {
+                        response.move_body(&workspace.path()?.join("response.body"))?;
+                    }
+                    hook.run(&response, post_hook_args, workspace.path()?)
+                        .and_then(Response::restore_body)
+                        .wrap_err("Failed to run post hook")?
+                }
+                None => response,
+            };
         if !response.store.is_empty() {
-            store.deref_mut().extend(response.store.drain());
+            // tokens captured from a response with expires_in(ex: oauth tokens) expire along with it
+            let expiring = response.expiring_tokens();
+            for (key, value) in response.store.drain() {
+                match &expiring {
+                    Some((expires_in, tokens)) if tokens.contains(&value) => {
+                        store.set_expiry(&key, *expires_in)
+                    }
+                    _ => store.clear_expiry(&key),
+                }
+                store.insert(key, value);
+            }
+        }
+        rewrite::apply_response(&response_rules, &mut response);
+        if archive {
+            let mut archived = response.clone();
+            archived.redact(&config.redact);
+            let meta = crate::archive::Meta {
+                time: humantime::format_rfc3339_millis(std::time::SystemTime::now()).to_string(),
+                query: name,
+                env: store.environment(),
+                method: &request_method,
+                url: &request_url,
+                status: archived.status_code,
+                headers: &archived.headers,
+            };
+            if let Err(e) = crate::archive::save(
+                &store.archive_dir(),
+                &meta,
+                &archived.body,
+                config.archive.retention,
+            ) {
+                warn!("Couldn't archive the response: {e:?}");
+            }
+        }
+        if let Some(encoding) = &response_encoding {
+            response.decode(encoding);
         }
+        // fake responses don't have latency to check
+        if let Some(elapsed) = elapsed {
+            expect::Expect::enforce(expect.evaluate(elapsed))?;
+        }
+        expect::Expect::enforce(headers_checked)?;
+        // body as the user sees it, after hooks and decoding
+        expect::Expect::enforce(expect.check_body(&response.body))?;
 
-        Ok(response.into())
+        Ok(Option::<crate::output::QueryResponse>::from(response)
+            .map(|response| crate::output::QueryResponse { output, ..response }))
     }
 }
 
@@ -312,11 +1106,35 @@ impl Eq for Query {}
 impl std::fmt::Display for Query {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         static KEY_STYLE: yansi::Style = yansi::Color::Yellow.bold();
+        const WIDTH: usize = 80;
         if let Some(description) = &self.description {
-            writeln!(f, "{}: {}", "description".paint(KEY_STYLE), description)?;
+            let options = textwrap::Options::new(WIDTH)
+                .initial_indent("  ")
+                .subsequent_indent("  ");
+            writeln!(f, "{}:", "description".paint(KEY_STYLE))?;
+            writeln!(f, "{}", textwrap::fill(description.trim(), options))?;
+        }
+        if let Some(url) = &self.docs_url {
+            writeln!(f, "{}: {}", "docs".paint(KEY_STYLE), url.underline().blue())?;
+        }
+        if let Some(reason) = &self.deprecated {
+            writeln!(f, "{}: {}", "deprecated".paint(KEY_STYLE), reason.red())?;
         }
         writeln!(f, "{}: {}", "method".paint(KEY_STYLE), self.method)?;
-        writeln!(f, "{}: {}", "path".paint(KEY_STYLE), self.path)
+        writeln!(f, "{}: {}", "path".paint(KEY_STYLE), self.path)?;
+        if !self.variables.is_empty() {
+            writeln!(f, "{}:", "variables".paint(KEY_STYLE))?;
+            for (name, variable) in &self.variables {
+                writeln!(f, "  {}: {variable}", name.bold())?;
+            }
+        }
+        if !self.examples.is_empty() {
+            writeln!(f, "{}:", "examples".paint(KEY_STYLE))?;
+            for example in &self.examples {
+                writeln!(f, "  {} {example}", "$".dim())?;
+            }
+        }
+        Ok(())
     }
 }
 
@@ -325,22 +1143,93 @@ impl std::fmt::Display for Query {
 enum UnpackedBody {
     Utf8(String),
     Raw(Vec<u8>),
+    /// command whose output is the body, it is run after substitution
+    Command {
+        command: Vec<String>,
+    },
+    /// json value of the binary body, encoded after substitution
+    Encoded {
+        encoding: encoding::Encoding,
+        json: String,
+    },
 }
 
 impl UnpackedBody {
-    fn substitute(self, vars: &HashMap<String, String>) -> Result<Self, subst::Error> {
+    fn substitute(self, vars: &crate::secret::Variables) -> Result<Self, subst::Error> {
         match self {
-            UnpackedBody::Utf8(s) => Ok(Self::Utf8(subst::substitute(&s, vars)?)),
+            UnpackedBody::Utf8(s) => Ok(Self::Utf8(crate::variable::substitute(&s, vars)?)),
             UnpackedBody::Raw(vec) => Ok(Self::Raw(vec)),
+            UnpackedBody::Command { command } => Ok(Self::Command {
+                command: command
+                    .iter()
+                    .map(|arg| crate::variable::substitute(arg, vars))
+                    .collect::<Result<_, _>>()?,
+            }),
+            UnpackedBody::Encoded { encoding, json } => Ok(Self::Encoded {
+                encoding,
+                json: crate::variable::substitute(&json, vars)?,
+            }),
+        }
+    }
+
+    fn not_generated() -> miette::Report {
+        miette::miette!(
+            help = "body commands and encoded bodies are generated after substitution, not after --edit",
+            "body is not generated"
+        )
+    }
+
+    /// whether the body is a command which isn't run yet
+    fn is_command(&self) -> bool {
+        matches!(self, UnpackedBody::Command { .. })
+    }
+
+    /// run the body command or encode the json body, other bodies are sent as they are
+    fn generate(self, workspace: &crate::workspace::Workspace) -> miette::Result<Self> {
+        let command = match self {
+            UnpackedBody::Command { command } => command,
+            UnpackedBody::Encoded { encoding, json } => {
+                return encoding.encode(&json).map(UnpackedBody::Raw);
+            }
+            UnpackedBody::Utf8(_) | UnpackedBody::Raw(_) => return Ok(self),
+        };
+        let Some((program, args)) = command.split_first() else {
+            miette::bail!("body command is empty")
+        };
+        debug!("generating body with {command:?}");
+        let mut process = std::process::Command::new(program);
+        crate::workspace::Workspace::configure(workspace.path()?, &mut process);
+        let output = process
+            .args(args)
+            .stdin(std::process::Stdio::null())
+            .stderr(std::process::Stdio::inherit())
+            .output()
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Couldn't run body command {program}"))?;
+        if !output.status.success() {
+            miette::bail!(
+                "body command {program} exited with error: {}",
+                output.status
+            )
         }
+        Ok(match String::from_utf8(output.stdout) {
+            Ok(text) => UnpackedBody::Utf8(text),
+            Err(e) => UnpackedBody::Raw(e.into_bytes()),
+        })
     }
 }
 
-impl From<UnpackedBody> for reqwest::Body {
-    fn from(value: UnpackedBody) -> Self {
+impl TryFrom<UnpackedBody> for reqwest::Body {
+    type Error = miette::Report;
+
+    fn try_from(value: UnpackedBody) -> Result<Self, Self::Error> {
         match value {
-            UnpackedBody::Utf8(s) => reqwest::Body::from(s),
-            UnpackedBody::Raw(vec) => reqwest::Body::from(vec),
+            UnpackedBody::Utf8(s) => Ok(reqwest::Body::from(s)),
+            UnpackedBody::Raw(vec) => Ok(reqwest::Body::from(vec)),
+            // commands are run right after substitution, these can only come after it(ex: from --edit)
+            UnpackedBody::Command { .. } | UnpackedBody::Encoded { .. } => {
+                Err(UnpackedBody::not_generated())
+            }
         }
     }
 }
@@ -351,12 +1240,12 @@ impl From<UnpackedBody> for reqwest::Body {
 struct MultiPartUnPacked {
     body: UnpackedBody,
     #[serde(default)]
-    headers: HashMap<String, String>,
+    headers: IndexMap<String, String>,
     file_name: Option<String>,
 }
 
 impl MultiPartUnPacked {
-    fn substitute(self, vars: &HashMap<String, String>) -> Result<Self, subst::Error> {
+    fn substitute(self, vars: &crate::secret::Variables) -> Result<Self, subst::Error> {
         let Self {
             body,
             headers,
@@ -365,13 +1254,13 @@ impl MultiPartUnPacked {
         let headers = headers
             .into_iter()
             .map(|(key, value)| {
-                let key = subst::substitute(&key, vars)?;
-                let val = subst::substitute(&value, vars)?;
+                let key = crate::variable::substitute(&key, vars)?;
+                let val = crate::variable::substitute(&value, vars)?;
                 Ok((key, val))
             })
             .collect::<Result<_, subst::Error>>()?;
         let file_name = file_name
-            .map(|name| subst::substitute(&name, vars))
+            .map(|name| crate::variable::substitute(&name, vars))
             .transpose()?;
         Ok(Self {
             body: body.substitute(vars)?,
@@ -382,28 +1271,82 @@ impl MultiPartUnPacked {
 }
 
 /// multipart value struct
+/// `files` is a directory or glob, each of its files becomes a part named `<name>-<n>`
 #[derive(Debug, Deserialize, Clone, Serialize)]
+#[serde(deny_unknown_fields)]
 struct Part {
-    body: TaggedBody,
+    body: Option<TaggedBody>,
+    files: Option<String>,
     #[serde(default)]
-    headers: HashMap<String, String>,
+    headers: IndexMap<String, String>,
     file_name: Option<String>,
+    /// content type of the part instead of the guessed one
+    content_type: Option<String>,
 }
 
 impl Part {
-    fn unpack(self) -> miette::Result<MultiPartUnPacked> {
+    fn unpack(self, name: String) -> miette::Result<Vec<(String, MultiPartUnPacked)>> {
         let Self {
             body,
+            files,
             mut headers,
             file_name,
+            content_type,
         } = self;
-        let (content_type, body) = body.unpack()?;
-        headers.insert(reqwest::header::CONTENT_TYPE.to_string(), content_type);
-        Ok(MultiPartUnPacked {
-            body,
-            headers,
-            file_name,
-        })
+        let content_type_header = reqwest::header::CONTENT_TYPE.as_str();
+        // content type given in headers is same as `content_type`
+        let declared = content_type.or_else(|| {
+            let key = headers
+                .keys()
+                .find(|key| key.eq_ignore_ascii_case(content_type_header))?
+                .clone();
+            headers.shift_remove(&key)
+        });
+        let pattern = match (body, files) {
+            (Some(body), None) => {
+                let (content_type, body) = body.unpack()?;
+                headers.insert(
+                    content_type_header.to_string(),
+                    declared.unwrap_or(content_type),
+                );
+                let part = MultiPartUnPacked {
+                    body,
+                    headers,
+                    file_name,
+                };
+                return Ok(vec![(name, part)]);
+            }
+            (None, Some(pattern)) if file_name.is_none() => pattern,
+            (None, Some(_)) => {
+                miette::bail!(
+                    "part {name} can't have file_name with files, names of the files are used"
+                )
+            }
+            _ => miette::bail!("part {name} should have either body or files"),
+        };
+        files::expand(&pattern)?
+            .into_iter()
+            .enumerate()
+            .map(|(index, path)| {
+                let body = std::fs::read(&path)
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Couldn't read file: {path:?}"))?;
+                let mut headers = headers.clone();
+                let content_type = declared.clone().unwrap_or_else(|| {
+                    files::content_type(Some(&path), &body)
+                        .unwrap_or_else(|| mime::APPLICATION_OCTET_STREAM.to_string())
+                });
+                headers.insert(content_type_header.to_string(), content_type);
+                let part = MultiPartUnPacked {
+                    body: UnpackedBody::Raw(body),
+                    headers,
+                    file_name: path
+                        .file_name()
+                        .map(|file_name| file_name.to_string_lossy().into_owned()),
+                };
+                Ok((format!("{name}-{}", index + 1), part))
+            })
+            .collect()
     }
 }
 
@@ -412,19 +1355,68 @@ impl Part {
 enum TaggedBody {
     #[serde(rename = "application/json")]
     ApplicationJson(Content<String>),
+    /// content type is guessed from the file extension or content if not given
     Raw {
-        content_type: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        content_type: Option<String>,
         #[serde(flatten)]
         data: Content<Vec<u8>>,
     },
     RawText {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        content_type: Option<String>,
+        #[serde(flatten)]
+        data: Content<String>,
+    },
+    /// stdout of the command is the body
+    Command {
         content_type: String,
+        run: Vec<String>,
+    },
+    /// json encoded as binary formats
+    Protobuf {
+        #[serde(flatten)]
+        schema: encoding::protobuf::Schema,
+        #[serde(flatten)]
+        data: Content<String>,
+    },
+    Msgpack(Content<String>),
+    Avro {
+        schema: std::path::PathBuf,
         #[serde(flatten)]
         data: Content<String>,
     },
 }
 
+/// json body which is encoded after substitution
+fn encoded(
+    encoding: encoding::Encoding,
+    data: Content<String>,
+) -> miette::Result<(String, UnpackedBody)> {
+    let json = data
+        .get_value()
+        .wrap_err_with(|| format!("Couldn't extract {} body", encoding.content_type()))?;
+    Ok((
+        encoding.content_type().to_string(),
+        UnpackedBody::Encoded { encoding, json },
+    ))
+}
+
 impl TaggedBody {
+    /// content type given in the query, other bodies have fixed content type
+    fn content_type(&self) -> Option<&str> {
+        match self {
+            TaggedBody::Raw { content_type, .. } | TaggedBody::RawText { content_type, .. } => {
+                content_type.as_deref()
+            }
+            TaggedBody::Command { content_type, .. } => Some(content_type),
+            TaggedBody::ApplicationJson(_)
+            | TaggedBody::Protobuf { .. }
+            | TaggedBody::Msgpack(_)
+            | TaggedBody::Avro { .. } => None,
+        }
+    }
+
     fn unpack(self) -> miette::Result<(String, UnpackedBody)> {
         match self {
             TaggedBody::ApplicationJson(content) => {
@@ -437,17 +1429,35 @@ impl TaggedBody {
                 ))
             }
             TaggedBody::Raw { content_type, data } => {
+                let path = data.path().map(std::path::Path::to_path_buf);
                 let val = data
                     .get_value()
                     .wrap_err("Couldn't extract application/json body")?;
+                let content_type = content_type.unwrap_or_else(|| {
+                    files::content_type(path.as_deref(), &val)
+                        .unwrap_or_else(|| mime::APPLICATION_OCTET_STREAM.to_string())
+                });
                 Ok((content_type, UnpackedBody::Raw(val)))
             }
             TaggedBody::RawText { content_type, data } => {
+                let path = data.path().map(std::path::Path::to_path_buf);
                 let val = data
                     .get_value()
                     .wrap_err("Couldn't extract application/json body")?;
+                let content_type = content_type.unwrap_or_else(|| {
+                    files::content_type(path.as_deref(), val.as_bytes())
+                        .unwrap_or_else(|| mime::TEXT_PLAIN_UTF_8.to_string())
+                });
                 Ok((content_type, UnpackedBody::Utf8(val)))
             }
+            TaggedBody::Command { content_type, run } => {
+                Ok((content_type, UnpackedBody::Command { command: run }))
+            }
+            TaggedBody::Protobuf { schema, data } => {
+                encoded(encoding::Encoding::Protobuf(schema), data)
+            }
+            TaggedBody::Msgpack(data) => encoded(encoding::Encoding::Msgpack, data),
+            TaggedBody::Avro { schema, data } => encoded(encoding::Encoding::Avro { schema }, data),
         }
     }
 }
@@ -489,7 +1499,14 @@ enum Content<T: FromBytes> {
 }
 
 impl<T: FromBytes> Content<T> {
-    fn get_value(self) -> miette::Result<T> {
+    fn path(&self) -> Option<&std::path::Path> {
+        match self {
+            Content::File(path) => Some(path),
+            Content::Inline(_) => None,
+        }
+    }
+
+    fn get_value(self) -> miette::Result<T> {
         match self {
             Content::File(path_buf) => {
                 let mut file = std::fs::File::open(&path_buf)
@@ -516,7 +1533,7 @@ struct PreparedQuery {
     path: String,
     method: String,
     #[serde(default)]
-    headers: HashMap<String, String>,
+    headers: IndexMap<String, String>,
     #[serde(default)]
     args: Vec<(String, String)>,
     #[serde(default = "default_timeout")]
@@ -526,8 +1543,57 @@ struct PreparedQuery {
     basic_auth: Option<BasicAuth>,
     bearer_auth: Option<String>,
     body: Option<UnpackedBody>,
-    form: Option<HashMap<String, String>>,
-    multipart: Option<HashMap<String, MultiPartUnPacked>>,
+    form: Option<IndexMap<String, String>>,
+    multipart: Option<IndexMap<String, MultiPartUnPacked>>,
+}
+
+/// final request in the form shown to the editor
+#[derive(Debug, Serialize, Deserialize)]
+struct EditableRequest {
+    origin: String,
+    #[serde(flatten)]
+    query: PreparedQuery,
+}
+
+/// open the request as json in the editor and read back the edited request
+/// editor is run with shell so it can have arguments, ex: `code --wait`
+fn edit_request(
+    origin: reqwest::Url,
+    query: PreparedQuery,
+    editor: &str,
+    workspace: &std::path::Path,
+) -> miette::Result<(reqwest::Url, PreparedQuery)> {
+    let path = workspace.join("request.json");
+    let origin = origin.to_string();
+    let mut content = serde_json::to_value(EditableRequest { origin, query })
+        .into_diagnostic()
+        .wrap_err("Couldn't serialize the request")?;
+    crate::hook::body_to_text(&mut content);
+    let content = serde_json::to_string_pretty(&content)
+        .into_diagnostic()
+        .wrap_err("Couldn't serialize the request")?;
+    std::fs::write(&path, content)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Couldn't write {path:?}"))?;
+    let status = std::process::Command::new("sh")
+        .args(["-c", &format!("{editor} \"$1\""), "sh"])
+        .arg(&path)
+        .status()
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Couldn't run editor {editor}"))?;
+    if !status.success() {
+        miette::bail!("editor {editor} exited with {status}, not sending the request")
+    }
+    let content = std::fs::read(&path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Couldn't read {path:?}"))?;
+    let EditableRequest { origin, query } = serde_json::from_slice(&content)
+        .into_diagnostic()
+        .wrap_err("Edited request is invalid")?;
+    let origin = reqwest::Url::parse(&origin)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Edited origin {origin} is invalid"))?;
+    Ok((origin, query))
 }
 
 impl TryFrom<Query> for PreparedQuery {
@@ -547,22 +1613,20 @@ impl TryFrom<Query> for PreparedQuery {
         let multipart = query
             .multipart
             .map(|m| {
-                m.into_iter()
-                    .map(|(k, part)| {
-                        let unpacked_part = part.unpack()?;
-
-                        Ok((k, unpacked_part))
-                    })
-                    .collect::<Result<HashMap<_, _>, miette::Error>>()
-                    .wrap_err("Couldn't unpack request")
+                let mut unpacked = IndexMap::new();
+                for (name, part) in m {
+                    unpacked.extend(part.unpack(name)?);
+                }
+                Ok::<_, miette::Error>(unpacked)
             })
-            .transpose()?;
+            .transpose()
+            .wrap_err("Couldn't unpack request")?;
         Ok(Self {
             path: query.path,
             method: query.method,
             headers,
             args: query.args,
-            timeout: query.timeout,
+            timeout: query.timeout.unwrap_or_else(default_timeout),
             version: query.version,
             basic_auth: query.basic_auth,
             bearer_auth: query.bearer_auth,
@@ -574,6 +1638,123 @@ impl TryFrom<Query> for PreparedQuery {
 }
 
 impl PreparedQuery {
+    /// run body commands of the body and multipart parts
+    fn generate_body(&mut self, workspace: &crate::workspace::Workspace) -> miette::Result<()> {
+        if let Some(body) = self.body.take() {
+            self.body = Some(body.generate(workspace)?);
+        }
+        for part in self.multipart.iter_mut().flat_map(IndexMap::values_mut) {
+            let body = std::mem::replace(&mut part.body, UnpackedBody::Raw(Vec::new()));
+            part.body = body.generate(workspace)?;
+        }
+        Ok(())
+    }
+
+    /// leave out bodies and parts generated by commands, they are not run for --inspect-request
+    fn remove_commands(&mut self) {
+        if self.body.as_ref().is_some_and(UnpackedBody::is_command) {
+            warn!("body command is not run, body is left out");
+            self.body = None;
+        }
+        if let Some(multipart) = &mut self.multipart {
+            multipart.retain(|name, part| {
+                let is_command = part.body.is_command();
+                if is_command {
+                    warn!("body command of part {name} is not run, part is left out");
+                }
+                !is_command
+            });
+        }
+    }
+
+    /// texts which are substituted, with where they are
+    fn texts(&self, with_body: bool) -> Vec<(String, &str)> {
+        let mut texts: Vec<(String, &str)> = vec![("path".to_string(), &self.path)];
+        for (name, value) in &self.headers {
+            texts.push((format!("header {name}"), value));
+        }
+        for (name, value) in &self.args {
+            texts.push((format!("arg {name}"), value));
+        }
+        for (name, value) in self.form.iter().flatten() {
+            texts.push((format!("form {name}"), value));
+        }
+        for (name, part) in self.multipart.iter().flatten() {
+            if let UnpackedBody::Utf8(body) = &part.body {
+                texts.push((format!("part {name}"), body));
+            }
+        }
+        if let Some(UnpackedBody::Utf8(body)) = self.body.as_ref().filter(|_| with_body) {
+            texts.push(("body".to_string(), body));
+        }
+        if let Some(token) = &self.bearer_auth {
+            texts.push(("bearer auth".to_string(), token));
+        }
+        texts
+    }
+
+    /// `${...}` patterns written as `$${...}` before substitution, with where they are
+    fn escaped(&self, check_body: bool) -> Vec<(String, String)> {
+        self.texts(check_body)
+            .into_iter()
+            .flat_map(|(place, text)| {
+                crate::variable::escaped(text)
+                    .into_iter()
+                    .map(move |pattern| (place.clone(), pattern.to_string()))
+            })
+            .collect()
+    }
+
+    /// `${...}` patterns left in the request after substitution, with where they are
+    /// `escaped` patterns are intended literals, raw and templated bodies are not substituted so `check_body` is false for them
+    fn unresolved(&self, mut escaped: Vec<(String, String)>, check_body: bool) -> Vec<String> {
+        let mut unresolved = Vec::new();
+        for (place, text) in self.texts(check_body) {
+            for pattern in crate::variable::unresolved(text) {
+                match escaped
+                    .iter()
+                    .position(|escaped| escaped.0 == place && escaped.1 == pattern)
+                {
+                    Some(index) => {
+                        escaped.swap_remove(index);
+                    }
+                    None => unresolved.push(format!("{pattern} in {place}")),
+                }
+            }
+        }
+        unresolved
+    }
+
+    /// mask sensitive headers, params, form fields and json fields of the body
+    /// credentials of basic/bearer auth are masked if `authorization` header is sensitive
+    fn redact(&mut self, redact: &crate::redact::Redact) {
+        for (name, value) in self
+            .headers
+            .iter_mut()
+            .chain(self.form.iter_mut().flatten())
+        {
+            *value = redact.value(name, value).to_string();
+        }
+        for (name, value) in &mut self.args {
+            *value = redact.value(name, value).to_string();
+        }
+        if let Some(UnpackedBody::Utf8(body)) = &mut self.body {
+            *body = redact.text(body).into_owned();
+        }
+        if redact.is_sensitive(reqwest::header::AUTHORIZATION.as_str()) {
+            if let Some(password) = self
+                .basic_auth
+                .as_mut()
+                .and_then(|auth| auth.password.as_mut())
+            {
+                *password = crate::redact::MASK.to_string();
+            }
+            if let Some(token) = &mut self.bearer_auth {
+                *token = crate::redact::MASK.to_string();
+            }
+        }
+    }
+
     fn into_request(
         self,
         base_url: reqwest::Url,
@@ -587,10 +1768,7 @@ impl PreparedQuery {
             .into_diagnostic()
             .wrap_err_with(|| format!("invalid method: {}", self.method))?;
 
-        let headers = (&self.headers)
-            .try_into()
-            .into_diagnostic()
-            .wrap_err("Invalid headers")?;
+        let headers = header_map(&self.headers)?;
         let builder = client
             .request(method, url)
             .headers(headers)
@@ -598,7 +1776,7 @@ impl PreparedQuery {
             .query(&self.args)
             .version(self.version.into());
         let builder = if let Some(body) = self.body {
-            builder.body(body)
+            builder.body(reqwest::Body::try_from(body)?)
         } else {
             builder
         };
@@ -635,16 +1813,16 @@ impl PreparedQuery {
                         let part = match body {
                             UnpackedBody::Utf8(c) => reqwest::multipart::Part::text(c),
                             UnpackedBody::Raw(vec) => reqwest::multipart::Part::bytes(vec),
+                            UnpackedBody::Command { .. } | UnpackedBody::Encoded { .. } => {
+                                return Err(UnpackedBody::not_generated());
+                            }
                         };
                         let part = if let Some(file_name) = file_name {
                             part.file_name(file_name)
                         } else {
                             part
                         };
-                        let headers = (&headers)
-                            .try_into()
-                            .into_diagnostic()
-                            .wrap_err("Invalid headers")?;
+                        let headers = header_map(&headers)?;
                         let part = part.headers(headers);
                         Ok(form.part(name, part))
                     },
@@ -661,7 +1839,7 @@ impl PreparedQuery {
             .wrap_err("Couldn't build request")
     }
 
-    fn substitute(self, vars: &HashMap<String, String>) -> Result<Self, subst::Error> {
+    fn substitute(self, vars: &crate::secret::Variables) -> Result<Self, subst::Error> {
         let Self {
             path,
             method,
@@ -675,14 +1853,14 @@ impl PreparedQuery {
             form,
             multipart,
         } = self;
-        let path = subst::substitute(&path, vars)?;
-        let method = subst::substitute(&method, vars)?;
+        let path = crate::variable::substitute(&path, vars)?;
+        let method = crate::variable::substitute(&method, vars)?;
 
         let headers = headers
             .into_iter()
             .map(|(key, value)| {
-                let key = subst::substitute(&key, vars)?;
-                let val = subst::substitute(&value, vars)?;
+                let key = crate::variable::substitute(&key, vars)?;
+                let val = crate::variable::substitute(&value, vars)?;
                 Ok((key, val))
             })
             .collect::<Result<_, subst::Error>>()?;
@@ -690,23 +1868,23 @@ impl PreparedQuery {
         let args = args
             .into_iter()
             .map(|(key, value)| {
-                let key = subst::substitute(&key, vars)?;
-                let val = subst::substitute(&value, vars)?;
+                let key = crate::variable::substitute(&key, vars)?;
+                let val = crate::variable::substitute(&value, vars)?;
                 Ok((key, val))
             })
             .collect::<Result<_, subst::Error>>()?;
 
         let basic_auth = basic_auth.map(|b| b.substitute(vars)).transpose()?;
         let bearer_auth = bearer_auth
-            .map(|b| subst::substitute(&b, vars))
+            .map(|b| crate::variable::substitute(&b, vars))
             .transpose()?;
 
         let form = form
             .map(|form| {
                 form.into_iter()
                     .map(|(key, value)| {
-                        let key = subst::substitute(&key, vars)?;
-                        let val = subst::substitute(&value, vars)?;
+                        let key = crate::variable::substitute(&key, vars)?;
+                        let val = crate::variable::substitute(&value, vars)?;
                         Ok((key, val))
                     })
                     .collect::<Result<_, subst::Error>>()
@@ -717,7 +1895,7 @@ impl PreparedQuery {
             .map(|form| {
                 form.into_iter()
                     .map(|(key, value)| {
-                        let key = subst::substitute(&key, vars)?;
+                        let key = crate::variable::substitute(&key, vars)?;
                         let val = value.substitute(vars)?;
                         Ok((key, val))
                     })
@@ -741,25 +1919,90 @@ impl PreparedQuery {
     }
 }
 
-/// To display headers
-struct DisplayResponseHeaders<'a>(&'a reqwest::header::HeaderMap);
+/// headers are added in the order they are defined
+fn header_map(headers: &IndexMap<String, String>) -> miette::Result<reqwest::header::HeaderMap> {
+    headers
+        .iter()
+        .map(|(key, value)| {
+            let key = reqwest::header::HeaderName::from_str(key)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Invalid header name {key:?}"))?;
+            let value = reqwest::header::HeaderValue::from_str(value)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Invalid value of header {key}"))?;
+            Ok((key, value))
+        })
+        .collect()
+}
+
+/// join prefix and path with exactly one `/` between them, result always starts with `/`
+/// trailing `/` of the path is preserved
+fn join_url_path(prefix: &str, path: &str) -> String {
+    let prefix = prefix.trim_matches('/');
+    let path = path.trim_start_matches('/');
+    if prefix.is_empty() {
+        format!("/{path}")
+    } else if path.is_empty() {
+        format!("/{prefix}")
+    } else {
+        format!("/{prefix}/{path}")
+    }
+}
+
+/// To display headers, values of sensitive headers are masked
+struct DisplayResponseHeaders<'a>(&'a reqwest::header::HeaderMap, &'a crate::redact::Redact);
 
 impl std::fmt::Display for DisplayResponseHeaders<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         for (key, val) in self.0 {
-            write!(f, "\n< {}: {:?}", key.yellow(), val)?
+            if self.1.is_sensitive(key.as_str()) {
+                write!(f, "\n< {}: {:?}", key.yellow(), crate::redact::MASK)?
+            } else {
+                write!(f, "\n< {}: {:?}", key.yellow(), val)?
+            }
         }
         Ok(())
     }
 }
 
-/// To display headers
-struct DisplayRequestHeaders<'a>(&'a reqwest::header::HeaderMap);
+/// To display headers, values of sensitive headers are masked
+struct DisplayRequestHeaders<'a>(&'a reqwest::header::HeaderMap, &'a crate::redact::Redact);
 
 impl std::fmt::Display for DisplayRequestHeaders<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         for (key, val) in self.0 {
-            write!(f, "\n> {}: {:?}", key.yellow(), val)?
+            if self.1.is_sensitive(key.as_str()) {
+                write!(f, "\n> {}: {:?}", key.yellow(), crate::redact::MASK)?
+            } else {
+                write!(f, "\n> {}: {:?}", key.yellow(), val)?
+            }
+        }
+        Ok(())
+    }
+}
+
+/// human readable form of the final request, sensitive values are masked
+struct PreviewRequest<'a>(&'a reqwest::Request, &'a crate::redact::Redact);
+
+impl std::fmt::Display for PreviewRequest<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let PreviewRequest(request, redact) = self;
+        writeln!(f, "{} {}", request.method(), redact.url(request.url()))?;
+        for (key, val) in request.headers() {
+            let val = String::from_utf8_lossy(val.as_bytes());
+            writeln!(f, "{key}: {}", redact.value(key.as_str(), &val))?;
+        }
+        if let Some(body) = request.body() {
+            writeln!(f)?;
+            match body.as_bytes().map(str::from_utf8) {
+                Some(Ok(decoded_body)) => writeln!(f, "{}", redact.text(decoded_body))?,
+                Some(Err(_)) => writeln!(
+                    f,
+                    "<{} bytes of binary data>",
+                    body.as_bytes().unwrap_or_default().len()
+                )?,
+                None => writeln!(f, "<streaming data, body cannot be displayed>")?,
+            }
         }
         Ok(())
     }
@@ -772,22 +2015,22 @@ fn is_extension_method(method: &reqwest::Method) -> bool {
     )
 }
 
-fn display_request(request: &reqwest::Request) {
+fn display_request(request: &reqwest::Request, redact: &crate::redact::Redact) {
     let method = request.method();
-    let url = request.url().as_str();
+    let url = redact.url(request.url());
     if is_extension_method(method) {
         warn!("using non-standard extension method: {method}");
         info!("[{}]: {url}", method.red().bold());
     } else {
         info!("[{method}]: {url}");
     }
-    let headers = DisplayRequestHeaders(request.headers());
+    let headers = DisplayRequestHeaders(request.headers(), redact);
     info!("headers: {headers}");
 
     if let Some(body) = request.body() {
         if let Some(body_bytes) = body.as_bytes().map(str::from_utf8) {
             if let Ok(decoded_body) = body_bytes {
-                info!("body: {decoded_body}")
+                info!("body: {}", redact.text(decoded_body))
             } else {
                 info!("body: {body_bytes:x?}")
             }
@@ -801,16 +2044,629 @@ fn display_request(request: &reqwest::Request) {
 struct Response {
     status_code: u16,
     version: HttpVersion,
-    headers: HashMap<String, String>,
+    headers: IndexMap<String, String>,
     store: HashMap<String, String>,
     body: Vec<u8>,
+    /// body is in this file instead of `body`, used for large bodies given to post hook
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    body_file: Option<std::path::PathBuf>,
 }
 
 impl Response {
-    async fn read_response(mut response: reqwest::Response) -> miette::Result<Self> {
+    /// write the body to the file and leave only its path in the response
+    fn move_body(&mut self, path: &std::path::Path) -> miette::Result<()> {
+        std::fs::write(path, std::mem::take(&mut self.body))
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Couldn't write body to {path:?}"))?;
+        self.body_file = Some(path.to_path_buf());
+        Ok(())
+    }
+
+    /// read back the body from `body_file`, hook can point it to any other file
+    fn restore_body(mut self) -> miette::Result<Self> {
+        if let Some(path) = self.body_file.take() {
+            self.body = std::fs::read(&path)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Couldn't read body from {path:?}"))?;
+        }
+        Ok(self)
+    }
+
+    /// replace binary body with its json, so that it can be displayed
+    fn decode(&mut self, encoding: &encoding::Encoding) {
+        let content_type = reqwest::header::CONTENT_TYPE.as_str();
+        if !self
+            .headers
+            .get(content_type)
+            .is_some_and(|value| encoding.matches(value))
+        {
+            return;
+        }
+        match encoding.decode(&self.body) {
+            Ok(json) => {
+                self.body = json.into_bytes();
+                self.headers.insert(
+                    content_type.to_string(),
+                    mime::APPLICATION_JSON.as_ref().to_string(),
+                );
+            }
+            Err(e) => warn!("Couldn't decode response: {e:?}"),
+        }
+    }
+
+    /// mask sensitive headers and json fields of the body
+    fn redact(&mut self, redact: &crate::redact::Redact) {
+        for (name, value) in &mut self.headers {
+            *value = redact.value(name, value).to_string();
+        }
+        for (name, value) in &mut self.store {
+            *value = redact.value(name, value).to_string();
+        }
+        if let Ok(mut body) = serde_json::from_slice::<serde_json::Value>(&self.body) {
+            redact.json(&mut body);
+            self.body = body.to_string().into_bytes();
+        }
+    }
+
+    /// `expires_in` seconds of the json body and the tokens which expire with it, as in oauth token responses
+    fn expiring_tokens(&self) -> Option<(std::time::Duration, Vec<String>)> {
+        let body = serde_json::from_slice::<serde_json::Value>(&self.body).ok()?;
+        let expires_in = match body.get("expires_in")? {
+            serde_json::Value::Number(seconds) => seconds.as_u64()?,
+            serde_json::Value::String(seconds) => seconds.parse().ok()?,
+            _ => return None,
+        };
+        let tokens = ["access_token", "id_token", "token"]
+            .into_iter()
+            .filter_map(|field| body.get(field)?.as_str().map(str::to_string))
+            .collect();
+        Some((std::time::Duration::from_secs(expires_in), tokens))
+    }
+}
+
+/// relative output file is placed in the output directory of the environment
+fn output_path(
+    output: &std::path::Path,
+    output_dir: Option<&std::path::Path>,
+) -> std::path::PathBuf {
+    match output_dir {
+        Some(output_dir) if output.is_relative() => output_dir.join(output),
+        _ => output.to_path_buf(),
+    }
+}
+
+/// range requested by the user or the range after the existing part of output file when resuming
+fn range_header(
+    cmd_args: &crate::Arguments,
+    output: Option<&std::path::Path>,
+) -> miette::Result<Option<reqwest::header::HeaderValue>> {
+    let range = match (&cmd_args.range, output) {
+        (Some(range), _) => range.clone(),
+        (None, Some(output)) if cmd_args.resume => {
+            let downloaded = match std::fs::metadata(output) {
+                Ok(metadata) => metadata.len(),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+                Err(e) => {
+                    return Err(e)
+                        .into_diagnostic()
+                        .wrap_err_with(|| format!("Couldn't read size of {output:?}"))
+                }
+            };
+            info!("resuming download from {downloaded} bytes");
+            format!("bytes={downloaded}-")
+        }
+        _ => return Ok(None),
+    };
+    reqwest::header::HeaderValue::from_str(&range)
+        .map(Some)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Invalid range: {range}"))
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Serialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum QueryKind {
+    #[default]
+    Request,
+    Healthcheck,
+}
+
+/// expected status and latency of a health check query
+#[derive(Debug, Deserialize, Clone, Serialize, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct HealthCheck {
+    /// expected status codes, any status below 400 is healthy if empty
+    #[serde(default)]
+    status: Vec<u16>,
+    /// slower responses are unhealthy, ex: "500ms"
+    #[serde(
+        default,
+        deserialize_with = "crate::archive::deserialize_duration",
+        serialize_with = "crate::archive::serialize_duration",
+        skip_serializing_if = "Option::is_none"
+    )]
+    max_latency: Option<std::time::Duration>,
+}
+
+impl HealthCheck {
+    /// reason if the response is unhealthy
+    pub fn evaluate(
+        &self,
+        status: Option<u16>,
+        latency: std::time::Duration,
+    ) -> Result<(), String> {
+        let Some(status) = status else {
+            return Err("no response".to_string());
+        };
+        let expected_status = if self.status.is_empty() {
+            status < 400
+        } else {
+            self.status.contains(&status)
+        };
+        if !expected_status {
+            return Err(format!("unexpected status {status}"));
+        }
+        match self.max_latency {
+            Some(max_latency) if latency > max_latency => Err(format!(
+                "latency {} ms is above {} ms",
+                latency.as_millis(),
+                max_latency.as_millis()
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Preflight {
+    Head,
+    Options,
+}
+
+impl Preflight {
+    /// send preflight request for given request and print availability/CORS headers
+    /// fails if response status is not successful
+    async fn run(
+        &self,
+        client: &reqwest::Client,
+        request: &reqwest::Request,
+    ) -> miette::Result<()> {
+        use reqwest::header;
+        let mut headers = request.headers().clone();
+        let method = match self {
+            Preflight::Head => reqwest::Method::HEAD,
+            Preflight::Options => {
+                let method = header::HeaderValue::from_str(request.method().as_str())
+                    .into_diagnostic()
+                    .wrap_err("Invalid method")?;
+                headers.insert(header::ACCESS_CONTROL_REQUEST_METHOD, method);
+                let header_names = request
+                    .headers()
+                    .keys()
+                    .map(header::HeaderName::as_str)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                if !header_names.is_empty() {
+                    let header_names = header::HeaderValue::from_str(&header_names)
+                        .into_diagnostic()
+                        .wrap_err("Invalid header names")?;
+                    headers.insert(header::ACCESS_CONTROL_REQUEST_HEADERS, header_names);
+                }
+                reqwest::Method::OPTIONS
+            }
+        };
+        debug!("sending preflight {method} {}", request.url());
+        let response = client
+            .request(method.clone(), request.url().clone())
+            .headers(headers)
+            .send()
+            .await
+            .into_diagnostic()
+            .wrap_err("Preflight request failed")?;
+        eprintln!("preflight {method}: {}", response.status());
+        for (key, val) in response.headers() {
+            if key.as_str().starts_with("access-control-") || key == header::ALLOW {
+                eprintln!("< {}: {:?}", key.yellow(), val);
+            }
+        }
+        if !response.status().is_success() {
+            miette::bail!("preflight responded with {}", response.status())
+        }
+        Ok(())
+    }
+}
+
+/// how the `Idempotency-Key` header of the request is chosen
+#[derive(Debug, Deserialize, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum IdempotencyKey {
+    /// generated per query and body
+    Auto,
+}
+
+/// operation under which the idempotency key is cached, same query with same body is treated as retry of the same operation
+fn idempotency_operation(name: &str, request: &reqwest::Request) -> String {
+    use sha2::Digest;
+    let mut hasher = sha2::Sha256::default();
+    hasher.update(request.method().as_str());
+    hasher.update(request.url().as_str());
+    if let Some(body) = request.body().and_then(reqwest::Body::as_bytes) {
+        hasher.update(body);
+    }
+    let digest = format!("{:x}", hasher.finalize());
+    format!("{name}:{}", &digest[..16])
+}
+
+/// add `Idempotency-Key` header, key of the previous unfinished attempt is reused
+/// generated key is saved right away, so that it is kept even if the query fails and can be retried
+fn apply_idempotency_key(
+    request: &mut reqwest::Request,
+    operation: &str,
+    keys: &mut crate::cache::Cache<String>,
+) -> miette::Result<String> {
+    let key = match keys.get(operation) {
+        Some(key) => {
+            debug!("reusing idempotency key of the previous attempt");
+            key.clone()
+        }
+        None => {
+            let key = uuid::Uuid::new_v4().to_string();
+            keys.insert(operation.to_string(), key.clone());
+            keys.save();
+            key
+        }
+    };
+    let value = reqwest::header::HeaderValue::from_str(&key)
+        .into_diagnostic()
+        .wrap_err("Invalid idempotency key")?;
+    request.headers_mut().insert("idempotency-key", value);
+    Ok(key)
+}
+
+/// number of times a request is resent when `respect_retry_after` is set
+const RETRY_AFTER_ATTEMPTS: usize = 3;
+/// total time spent waiting for `Retry-After` of a single query
+const RETRY_AFTER_BUDGET: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// wait duration requested by a 429/503 response, `Retry-After` can be seconds or a http date
+fn retry_after(response: &reqwest::Response) -> Option<std::time::Duration> {
+    let status = response.status();
+    if status != reqwest::StatusCode::TOO_MANY_REQUESTS
+        && status != reqwest::StatusCode::SERVICE_UNAVAILABLE
+    {
+        return None;
+    }
+    let value = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+    parse_retry_after(value, std::time::SystemTime::now())
+}
+
+fn parse_retry_after(value: &str, now: std::time::SystemTime) -> Option<std::time::Duration> {
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(std::time::Duration::from_secs(secs));
+    }
+    let at = httpdate::parse_http_date(value).ok()?;
+    Some(at.duration_since(now).unwrap_or_default())
+}
+
+/// urls whose validators are kept, validators of least recently requested urls are dropped beyond this
+const VALIDATORS_LIMIT: usize = 256;
+
+/// etag and last-modified values of the last response of an url
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Validators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+/// add conditional headers from the validators saved for the url
+/// safe methods get `If-None-Match`/`If-Modified-Since`, others get `If-Match`
+fn apply_validators(
+    request: &mut reqwest::Request,
+    url: &str,
+    saved: &crate::cache::Cache<Validators>,
+) -> miette::Result<()> {
+    use reqwest::header;
+    let Some(saved) = saved.get(url) else {
+        return Ok(());
+    };
+    let is_safe = matches!(
+        *request.method(),
+        reqwest::Method::GET | reqwest::Method::HEAD
+    );
+    let mut validators = Vec::new();
+    if let Some(etag) = &saved.etag {
+        let name = if is_safe {
+            header::IF_NONE_MATCH
+        } else {
+            header::IF_MATCH
+        };
+        validators.push((name, etag));
+    }
+    if let Some(last_modified) = saved.last_modified.as_ref().filter(|_| is_safe) {
+        validators.push((header::IF_MODIFIED_SINCE, last_modified));
+    }
+    for (name, value) in validators {
+        let value = header::HeaderValue::from_str(value)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Invalid validator for {name}"))?;
+        debug!("adding conditional header {name}: {value:?}");
+        request.headers_mut().entry(name).or_insert(value);
+    }
+    Ok(())
+}
+
+/// save etag and last-modified values of the response for the url
+fn save_validators(response: &Response, url: &str, saved: &mut crate::cache::Cache<Validators>) {
+    let mut validators = saved.get(url).cloned().unwrap_or_default();
+    let etag = response.headers.get(reqwest::header::ETAG.as_str());
+    let last_modified = response
+        .headers
+        .get(reqwest::header::LAST_MODIFIED.as_str());
+    if etag.is_none() && last_modified.is_none() {
+        return;
+    }
+    if let Some(etag) = etag {
+        validators.etag = Some(etag.clone());
+    }
+    if let Some(last_modified) = last_modified {
+        validators.last_modified = Some(last_modified.clone());
+    }
+    saved.insert(url.to_string(), validators);
+    saved.save();
+}
+
+/// size, sha256 digest and content type of the response body
+struct BodySummary<'a>(&'a Response);
+
+impl std::fmt::Display for BodySummary<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use sha2::Digest;
+        let response = self.0;
+        let content_type = response
+            .headers
+            .get(reqwest::header::CONTENT_TYPE.as_str())
+            .map(String::as_str)
+            .unwrap_or("unknown");
+        write!(
+            f,
+            "body: {} bytes, sha256: {:x}, content-type: {content_type}",
+            response.body.len(),
+            sha2::Sha256::digest(&response.body)
+        )
+    }
+}
+
+/// one line summary of the response, ex: `POST /v1/users → 201 Created (123 ms, 2.1 KB)`
+struct StatusLine<'a> {
+    method: &'a str,
+    path: &'a str,
+    response: &'a Response,
+    elapsed: std::time::Duration,
+}
+
+impl std::fmt::Display for StatusLine<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = reqwest::StatusCode::from_u16(self.response.status_code)
+            .map(|status| status.to_string())
+            .unwrap_or_else(|_| self.response.status_code.to_string());
+        let status = match self.response.status_code {
+            200..=299 => status.green(),
+            300..=399 => status.cyan(),
+            400..=499 => status.yellow(),
+            _ => status.red(),
+        };
+        write!(
+            f,
+            "{} {} → {} ({} ms, {})",
+            self.method.bold(),
+            self.path,
+            status.bold(),
+            self.elapsed.as_millis(),
+            format_size(self.response.body.len())
+        )
+    }
+}
+
+/// human readable size, ex: 2.1 KB
+fn format_size(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = UNITS[0];
+    for next_unit in &UNITS[1..] {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next_unit;
+    }
+    format!("{size:.1} {unit}")
+}
+
+/// connection details of the response, printed with --trace-transport
+/// rustls doesn't expose negotiated tls version and cipher through reqwest, so only what is available is shown
+struct TransportInfo<'a>(&'a reqwest::Response);
+
+impl std::fmt::Display for TransportInfo<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use sha2::Digest;
+        let response = self.0;
+        let remote = response
+            .remote_addr()
+            .map(|addr| addr.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        writeln!(f, "{} remote: {remote}", "transport".cyan())?;
+        let is_tls = response.url().scheme() == "https";
+        // tls version, cipher and alpn of the connection are not exposed by reqwest
+        write!(
+            f,
+            "{} http version: {:?}",
+            "transport".cyan(),
+            response.version()
+        )?;
+        match response
+            .extensions()
+            .get::<reqwest::tls::TlsInfo>()
+            .and_then(reqwest::tls::TlsInfo::peer_certificate)
+        {
+            Some(certificate) => write!(
+                f,
+                "\n{} peer certificate: {} bytes, sha256: {:x}",
+                "transport".cyan(),
+                certificate.len(),
+                sha2::Sha256::digest(certificate)
+            ),
+            None if is_tls => write!(f, "\n{} peer certificate: unavailable", "transport".cyan()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// transport failure with its kind and complete chain of causes, printed with --trace-transport
+struct TransportError<'a>(&'a reqwest::Error);
+
+impl std::fmt::Display for TransportError<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let error = self.0;
+        let kind = if error.is_timeout() {
+            "timeout"
+        } else if error.is_connect() {
+            "connect"
+        } else if error.is_redirect() {
+            "redirect"
+        } else if error.is_request() {
+            "request"
+        } else if error.is_body() {
+            "body"
+        } else {
+            "other"
+        };
+        write!(f, "{} {kind} error: {error}", "transport".red())?;
+        let mut source = std::error::Error::source(error);
+        while let Some(cause) = source {
+            write!(f, "\n{} caused by: {cause}", "transport".red())?;
+            source = cause.source();
+        }
+        Ok(())
+    }
+}
+
+/// run the hook with the request(pre hook) or response(post hook) of the fixture and print the changes done by the hook
+/// fixture with `status_code` is a response in the same format as `--fake-response`, otherwise it is a request
+pub fn test_hook(
+    script: &std::path::Path,
+    format: crate::hook::Format,
+    fixture: &std::path::Path,
+    args: &[String],
+    keep_temp: bool,
+) -> miette::Result<()> {
+    let hook = crate::hook::Hook::script(script.to_path_buf(), format);
+    let workspace = crate::workspace::Workspace::new(keep_temp);
+    let content = std::fs::read(fixture)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Couldn't read {fixture:?}"))?;
+    let fixture_value = serde_json::from_slice::<serde_json::Value>(&content)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Couldn't deserialize {fixture:?}"))?;
+    let (before, after) = if fixture_value.get("status_code").is_some() {
+        let response = Response::read_fake(fixture).wrap_err("Invalid response fixture")?;
+        let output = hook
+            .run(&response, args, workspace.path()?)
+            .wrap_err("Post hook failed or returned invalid response")?;
+        (hook_view(&response)?, hook_view(&output)?)
+    } else {
+        let request = serde_json::from_value::<PreparedQuery>(fixture_value)
+            .into_diagnostic()
+            .wrap_err("Fixture is neither a request nor a response")?;
+        let output = hook
+            .run(&request, args, workspace.path()?)
+            .wrap_err("Pre hook failed or returned invalid request")?;
+        (hook_view(&request)?, hook_view(&output)?)
+    };
+    let changes = crate::hook::diff(&before, &after);
+    if changes.is_empty() {
+        eprintln!("hook output is valid, nothing changed");
+    }
+    for change in changes {
+        if change.starts_with('-') {
+            println!("{}", change.red());
+        } else {
+            println!("{}", change.green());
+        }
+    }
+    Ok(())
+}
+
+/// json value of hook data, binary body is shown as string if it is utf8
+fn hook_view(value: &impl Serialize) -> miette::Result<serde_json::Value> {
+    let mut value = serde_json::to_value(value)
+        .into_diagnostic()
+        .wrap_err("Couldn't convert hook data to json")?;
+    crate::hook::body_to_text(&mut value);
+    Ok(value)
+}
+
+/// canned response used with dry run, read from json file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FakeResponse {
+    #[serde(default = "default_fake_status")]
+    status_code: u16,
+    #[serde(default)]
+    headers: IndexMap<String, String>,
+    /// string body is used as it is, any other json value is serialized
+    #[serde(default)]
+    body: Option<serde_json::Value>,
+}
+
+fn default_fake_status() -> u16 {
+    200
+}
+
+impl Response {
+    fn read_fake(path: &std::path::Path) -> miette::Result<Self> {
+        let content = std::fs::read(path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Couldn't read {path:?}"))?;
+        let FakeResponse {
+            status_code,
+            headers,
+            body,
+        } = serde_json::from_slice(&content)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Couldn't deserialize {path:?}"))?;
+        let body = match body {
+            None => Vec::new(),
+            Some(serde_json::Value::String(s)) => s.into_bytes(),
+            Some(value) => serde_json::to_vec(&value)
+                .into_diagnostic()
+                .wrap_err("Couldn't serialize fake response body")?,
+        };
+        Ok(Self {
+            status_code,
+            version: HttpVersion::default(),
+            headers,
+            store: HashMap::new(),
+            body,
+            body_file: None,
+        })
+    }
+
+    async fn read_response(
+        mut response: reqwest::Response,
+        redact: &crate::redact::Redact,
+    ) -> miette::Result<Self> {
         info!("status: {}", response.status());
         info!("version: {:?}", response.version());
-        let header_map = DisplayResponseHeaders(response.headers());
+        let header_map = DisplayResponseHeaders(response.headers(), redact);
         info!("headers: {header_map}");
         Ok(Self {
             status_code: response.status().into(),
@@ -830,7 +2686,7 @@ impl Response {
                             .to_string(),
                     ))
                 })
-                .collect::<Result<HashMap<_, _>, miette::Error>>()?,
+                .collect::<Result<IndexMap<_, _>, miette::Error>>()?,
             body: response
                 .bytes()
                 .await
@@ -838,12 +2694,430 @@ impl Response {
                 .wrap_err("Couldn't read response body")?
                 .into(),
             store: HashMap::new(),
+            body_file: None,
         })
     }
 }
 
-impl From<Response> for Option<crate::parser::QueryResponse> {
+impl From<Response> for Option<crate::output::QueryResponse> {
     fn from(value: Response) -> Self {
-        Some(value.body)
+        Some(crate::output::QueryResponse {
+            status: Some(value.status_code),
+            content_type: value
+                .headers
+                .get(reqwest::header::CONTENT_TYPE.as_str())
+                .cloned(),
+            body: value.body,
+            output: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_documentation() {
+        let query: Query = toml::from_str(
+            r#"
+            method = "GET"
+            path = "/users/${id}"
+            docs_url = "https://example.com/users"
+            examples = ["qwicket users get --var id=1"]
+            description = """
+Fetch a single user by id, including the profile, preferences and the teams which the user belongs to.
+
+Deleted users are not returned."""
+            "#,
+        )
+        .unwrap();
+        let text = query.to_string();
+        let text = regex::Regex::new("\x1b\\[[0-9;]*m")
+            .unwrap()
+            .replace_all(&text, "");
+        assert_eq!(
+            text,
+            "description:
+  Fetch a single user by id, including the profile, preferences and the teams
+  which the user belongs to.
+  
+  Deleted users are not returned.
+docs: https://example.com/users
+method: GET
+path: /users/${id}
+examples:
+  $ qwicket users get --var id=1
+"
+        );
+    }
+
+    #[test]
+    fn range_from_args_and_output() {
+        let path = std::env::temp_dir().join(format!("qwicket-range-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let range = |range: Option<&str>, resume| {
+            let args = crate::Arguments {
+                range: range.map(str::to_string),
+                resume,
+                ..Default::default()
+            };
+            range_header(&args, Some(&path))
+                .unwrap()
+                .map(|range| range.to_str().unwrap().to_string())
+        };
+        assert_eq!(range(None, false), None);
+        assert_eq!(range(Some("bytes=0-1023"), false).unwrap(), "bytes=0-1023");
+        // missing output file is downloaded from the start
+        assert_eq!(range(None, true).unwrap(), "bytes=0-");
+        std::fs::write(&path, [0; 10]).unwrap();
+        assert_eq!(range(None, true).unwrap(), "bytes=10-");
+        let args = crate::Arguments {
+            range: Some("bytes=\n".to_string()),
+            ..Default::default()
+        };
+        assert!(range_header(&args, None).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn health_thresholds() {
+        let latency = std::time::Duration::from_millis(120);
+        let any = HealthCheck::default();
+        assert!(any.evaluate(Some(302), latency).is_ok());
+        assert!(any.evaluate(Some(503), latency).is_err());
+        assert!(any.evaluate(None, latency).is_err());
+        let strict: HealthCheck =
+            toml::from_str("status = [204]\nmax_latency = \"100ms\"").unwrap();
+        assert!(strict
+            .evaluate(Some(200), std::time::Duration::ZERO)
+            .is_err());
+        assert!(strict
+            .evaluate(Some(204), std::time::Duration::ZERO)
+            .is_ok());
+        assert!(strict.evaluate(Some(204), latency).is_err());
+    }
+
+    #[test]
+    fn files_part_content_type() {
+        let dir = std::env::temp_dir().join(format!("qwicket-parts-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.png"), b"png").unwrap();
+        let content_types = |part: &str| -> Vec<String> {
+            let part: Part = toml::from_str(part).unwrap();
+            part.unpack("shots".to_string())
+                .unwrap()
+                .into_iter()
+                .map(|(_, part)| part.headers.values().cloned().collect::<Vec<_>>().join(","))
+                .collect()
+        };
+        let files = format!("files = {:?}", dir.to_str().unwrap());
+        assert_eq!(content_types(&files), ["image/png"]);
+        assert_eq!(
+            content_types(&format!(
+                "{files}\nheaders = {{ Content-Type = \"application/octet-stream\" }}"
+            )),
+            ["application/octet-stream"]
+        );
+        assert_eq!(
+            content_types(&format!("{files}\ncontent_type = \"image/x-png\"")),
+            ["image/x-png"]
+        );
+        assert_eq!(
+            content_types(
+                "body.raw_text = { inline = \"a\" }\ncontent_type = \"text/csv\"\nheaders = { content-type = \"text/html\" }"
+            ),
+            ["text/csv"]
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn edited_request() {
+        let workspace = crate::workspace::Workspace::new(false);
+        let query: Query = toml::from_str(
+            r#"
+            path = "/users"
+            method = "GET"
+            body."raw_text" = { content_type = "text/plain", inline = "hello" }
+            "#,
+        )
+        .unwrap();
+        let query = PreparedQuery::try_from(query).unwrap();
+        let origin = reqwest::Url::parse("http://localhost:8080").unwrap();
+        let (origin, query) = edit_request(
+            origin,
+            query,
+            "sed -i -e s/GET/POST/ -e s/hello/bye/ -e s/8080/9090/",
+            workspace.path().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(origin.as_str(), "http://localhost:9090/");
+        assert_eq!(query.method, "POST");
+        assert!(matches!(query.body, Some(UnpackedBody::Utf8(body)) if body == "bye"));
+        let query = PreparedQuery::try_from(
+            toml::from_str::<Query>("path = \"/\"\nmethod = \"GET\"").unwrap(),
+        )
+        .unwrap();
+        assert!(edit_request(origin, query, "false", workspace.path().unwrap()).is_err());
+    }
+
+    #[test]
+    fn strict_skips_escaped_literals() {
+        let query: Query = toml::from_str(
+            r#"
+            path = "/users/$${id}"
+            method = "POST"
+            headers = { x-token = "${token}", x-cost = "$$$${price}" }
+            body."raw_text" = { content_type = "text/plain", inline = "$${name} is ${name}" }
+            "#,
+        )
+        .unwrap();
+        let query = PreparedQuery::try_from(query).unwrap();
+        let escaped = query.escaped(true);
+        let values = HashMap::from([
+            ("token".to_string(), "${TOKEN}".to_string()),
+            ("name".to_string(), "admin".to_string()),
+        ]);
+        let (secrets, providers) = (HashMap::new(), HashMap::new());
+        let substituted = query
+            .substitute(&crate::secret::Variables::new(values, &secrets, &providers))
+            .unwrap();
+        assert_eq!(substituted.path, "/users/${id}");
+        // value of the store isn't an intended literal
+        assert_eq!(
+            substituted.unresolved(escaped, true),
+            ["${TOKEN} in header x-token"]
+        );
+    }
+
+    #[test]
+    fn post_hook_body_file() {
+        let workspace = crate::workspace::Workspace::new(false);
+        let mut response = Response {
+            status_code: 200,
+            version: HttpVersion::default(),
+            headers: IndexMap::new(),
+            store: HashMap::new(),
+            body: b"large".to_vec(),
+            body_file: None,
+        };
+        response
+            .move_body(&workspace.path().unwrap().join("response.body"))
+            .unwrap();
+        assert!(response.body.is_empty());
+        let hook: crate::hook::Hook = toml::from_str(
+            "command = \"printf changed > $QWICKET_WORKSPACE/response.body && cat\"\nformat = \"json\"",
+        )
+        .unwrap();
+        let response = hook
+            .run(&response, &[] as &[&str], workspace.path().unwrap())
+            .and_then(Response::restore_body)
+            .unwrap();
+        assert_eq!(response.body, b"changed");
+        assert!(response.body_file.is_none());
+    }
+
+    #[test]
+    fn generated_bodies() {
+        let body: TaggedBody = toml::from_str(
+            r#"command = { content_type = "text/plain", run = ["printf", "%s-%s", "a", "b"] }"#,
+        )
+        .unwrap();
+        let (content_type, body) = body.unpack().unwrap();
+        assert_eq!(content_type, "text/plain");
+        let workspace = crate::workspace::Workspace::new(false);
+        assert!(
+            matches!(body.generate(&workspace).unwrap(), UnpackedBody::Utf8(text) if text == "a-b")
+        );
+        let body: TaggedBody = toml::from_str(
+            r#"protobuf = { descriptor = "user.desc", message = "test.User", inline = '{"id": 1}' }"#,
+        )
+        .unwrap();
+        let (content_type, body) = body.unpack().unwrap();
+        assert_eq!(content_type, "application/x-protobuf");
+        assert!(matches!(body, UnpackedBody::Encoded { json, .. } if json == r#"{"id": 1}"#));
+        let failing = UnpackedBody::Command {
+            command: vec!["false".to_string()],
+        };
+        assert!(failing.generate(&workspace).is_err());
+    }
+
+    #[test]
+    fn command_bodies_are_not_sent() {
+        let query = || {
+            let query: Query = toml::from_str(
+                r#"
+                path = "/users"
+                method = "POST"
+                body.command = { content_type = "text/plain", run = ["date"] }
+                "#,
+            )
+            .unwrap();
+            PreparedQuery::try_from(query).unwrap()
+        };
+        let origin = reqwest::Url::parse("http://localhost").unwrap();
+        let client = reqwest::Client::new();
+        assert!(query().into_request(origin.clone(), &client).is_err());
+        let mut query = query();
+        query.remove_commands();
+        assert!(query.body.is_none());
+        assert!(query.into_request(origin, &client).is_ok());
+    }
+
+    #[test]
+    fn idempotency_key_is_reused() {
+        let path = std::env::temp_dir().join(format!("qwicket-idempotency-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let request = || {
+            reqwest::Client::new()
+                .post("http://localhost/payments")
+                .body("{\"amount\": 10}")
+                .build()
+                .unwrap()
+        };
+        let operation = idempotency_operation("payments.create", &request());
+        let mut keys = crate::cache::Cache::open(path.clone(), "dev");
+        let mut first = request();
+        let key = apply_idempotency_key(&mut first, &operation, &mut keys).unwrap();
+        assert_eq!(first.headers()["idempotency-key"], key.as_str());
+
+        // retried in the next run
+        let mut keys = crate::cache::Cache::open(path.clone(), "dev");
+        let mut retried = request();
+        assert_eq!(
+            apply_idempotency_key(&mut retried, &operation, &mut keys).unwrap(),
+            key
+        );
+        keys.remove(&operation);
+        keys.save();
+        let mut keys = crate::cache::Cache::open(path.clone(), "dev");
+        assert_ne!(
+            apply_idempotency_key(&mut request(), &operation, &mut keys).unwrap(),
+            key
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn validators_are_saved_per_url() {
+        let path = std::env::temp_dir().join(format!("qwicket-validators-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let url = "http://localhost/users";
+        let response = Response {
+            status_code: 200,
+            version: HttpVersion::default(),
+            headers: IndexMap::from([("etag".to_string(), "\"v1\"".to_string())]),
+            store: HashMap::new(),
+            body: Vec::new(),
+            body_file: None,
+        };
+        let mut saved = crate::cache::Cache::open(path.clone(), "dev").with_limit(1);
+        save_validators(&response, url, &mut saved);
+        let saved = crate::cache::Cache::open(path.clone(), "dev");
+        let mut request = reqwest::Client::new().get(url).build().unwrap();
+        apply_validators(&mut request, url, &saved).unwrap();
+        assert_eq!(request.headers()["if-none-match"], "\"v1\"");
+        let mut request = reqwest::Client::new().put(url).build().unwrap();
+        apply_validators(&mut request, url, &saved).unwrap();
+        assert_eq!(request.headers()["if-match"], "\"v1\"");
+
+        // only the recent urls are kept
+        let mut saved = crate::cache::Cache::open(path.clone(), "dev").with_limit(1);
+        save_validators(&response, "http://localhost/orders", &mut saved);
+        let saved = crate::cache::Cache::open(path.clone(), "dev");
+        let mut request = reqwest::Client::new().get(url).build().unwrap();
+        apply_validators(&mut request, url, &saved).unwrap();
+        assert!(request.headers().is_empty());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn retry_after_values() {
+        let now = httpdate::parse_http_date("Wed, 21 Oct 2015 07:28:00 GMT").unwrap();
+        let secs = std::time::Duration::from_secs;
+        assert_eq!(parse_retry_after("120", now), Some(secs(120)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:30 GMT", now),
+            Some(secs(30))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:27:00 GMT", now),
+            Some(std::time::Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    #[test]
+    fn preview_is_redacted() {
+        let redact: crate::redact::Redact =
+            serde_json::from_value(serde_json::json!(["authorization", "api_key", "password"]))
+                .unwrap();
+        let request = reqwest::Client::new()
+            .post("http://localhost/login?api_key=secret&page=1")
+            .bearer_auth("token")
+            .header("x-team", "qa")
+            .body(r#"{"user":"a","password":"b"}"#)
+            .build()
+            .unwrap();
+        let preview = PreviewRequest(&request, &redact).to_string();
+        assert_eq!(
+            preview,
+            "POST http://localhost/login?api_key=***&page=1\n\
+             authorization: ***\n\
+             x-team: qa\n\
+             \n\
+             {\"password\":\"***\",\"user\":\"a\"}\n"
+        );
+    }
+
+    #[test]
+    fn human_readable_size() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(2150), "2.1 KB");
+        assert_eq!(format_size(3 * 1024 * 1024), "3.0 MB");
+    }
+
+    #[test]
+    fn output_in_env_directory() {
+        let output_dir = std::path::Path::new("out/staging");
+        assert_eq!(
+            output_path("body.json".as_ref(), Some(output_dir)),
+            std::path::Path::new("out/staging/body.json")
+        );
+        assert_eq!(
+            output_path("/tmp/body.json".as_ref(), Some(output_dir)),
+            std::path::Path::new("/tmp/body.json")
+        );
+        assert_eq!(
+            output_path("body.json".as_ref(), None),
+            std::path::Path::new("body.json")
+        );
+    }
+
+    #[test]
+    fn join_url_path_slashes() {
+        assert_eq!(join_url_path("", ""), "/");
+        assert_eq!(join_url_path("", "users"), "/users");
+        assert_eq!(join_url_path("/", "/users"), "/users");
+        assert_eq!(join_url_path("api", ""), "/api");
+        assert_eq!(join_url_path("api/v1", "users"), "/api/v1/users");
+        assert_eq!(join_url_path("/api/v1/", "/users/"), "/api/v1/users/");
+        assert_eq!(join_url_path("api//", "//users"), "/api/users");
+    }
+
+    #[test]
+    fn join_url_path_templates() {
+        let path = join_url_path("tenants/${TENANT_ID}/", "/users/${USER}");
+        assert_eq!(path, "/tenants/${TENANT_ID}/users/${USER}");
+        let vars = HashMap::from([
+            ("TENANT_ID".to_string(), "t1".to_string()),
+            ("USER".to_string(), "u1".to_string()),
+        ]);
+        let url = reqwest::Url::parse("https://example.com/ignored/")
+            .unwrap()
+            .join(&subst::substitute(&path, &vars).unwrap())
+            .unwrap();
+        assert_eq!(url.as_str(), "https://example.com/tenants/t1/users/u1");
     }
 }
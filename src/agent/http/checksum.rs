@@ -0,0 +1,95 @@
+//! digest of the final request body sent as a header, required by object storage uploads
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum Algorithm {
+    Md5,
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+enum DigestEncoding {
+    #[default]
+    Base64,
+    Hex,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Checksum {
+    algo: Algorithm,
+    /// header which carries the digest, ex: `x-amz-checksum-sha256`
+    header: String,
+    #[serde(default)]
+    encoding: DigestEncoding,
+}
+
+impl Checksum {
+    fn digest(&self, body: &[u8]) -> String {
+        use sha2::Digest;
+        let digest = match self.algo {
+            Algorithm::Md5 => md5::Md5::digest(body).to_vec(),
+            Algorithm::Sha1 => sha1::Sha1::digest(body).to_vec(),
+            Algorithm::Sha256 => sha2::Sha256::digest(body).to_vec(),
+            Algorithm::Sha512 => sha2::Sha512::digest(body).to_vec(),
+        };
+        match self.encoding {
+            DigestEncoding::Base64 => base64::engine::general_purpose::STANDARD.encode(digest),
+            DigestEncoding::Hex => digest.iter().map(|byte| format!("{byte:02x}")).collect(),
+        }
+    }
+
+    /// add digest header of the request body, requests without body get the digest of empty body
+    pub fn apply(&self, request: &mut reqwest::Request) -> miette::Result<()> {
+        let body = match request.body() {
+            Some(body) => body.as_bytes().ok_or_else(|| {
+                miette::miette!("Checksum can't be computed for streamed(multipart) body")
+            })?,
+            None => &[],
+        };
+        let digest = self.digest(body);
+        let name = reqwest::header::HeaderName::try_from(self.header.as_str())
+            .map_err(|e| miette::miette!("Invalid checksum header {}: {e}", self.header))?;
+        let value = reqwest::header::HeaderValue::try_from(digest)
+            .map_err(|e| miette::miette!("Invalid checksum header value: {e}"))?;
+        request.headers_mut().insert(name, value);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn body_digest() {
+        let sha256: Checksum =
+            serde_json::from_str(r#"{"algo": "sha256", "header": "x-amz-checksum-sha256"}"#)
+                .unwrap();
+        assert_eq!(
+            sha256.digest(b"hello"),
+            "LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ="
+        );
+        let md5: Checksum =
+            serde_json::from_str(r#"{"algo": "md5", "header": "content-md5", "encoding": "hex"}"#)
+                .unwrap();
+        assert_eq!(md5.digest(b"hello"), "5d41402abc4b2a76b9719d911017c592");
+
+        let mut request = reqwest::Request::new(
+            reqwest::Method::PUT,
+            "http://localhost/object".parse().unwrap(),
+        );
+        *request.body_mut() = Some(reqwest::Body::from("hello"));
+        sha256.apply(&mut request).unwrap();
+        assert_eq!(
+            request.headers()["x-amz-checksum-sha256"],
+            "LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ="
+        );
+    }
+}
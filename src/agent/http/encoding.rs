@@ -0,0 +1,95 @@
+//! binary body formats which are written as json in the query, encoded after substitution and decoded back for display
+
+mod avro;
+pub mod protobuf;
+
+use miette::{Context, IntoDiagnostic};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    Protobuf(protobuf::Schema),
+    Msgpack,
+    /// avro binary encoding, schema is a json `.avsc` file
+    Avro {
+        schema: std::path::PathBuf,
+    },
+}
+
+impl Encoding {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Encoding::Protobuf(_) => "application/x-protobuf",
+            Encoding::Msgpack => "application/msgpack",
+            Encoding::Avro { .. } => "avro/binary",
+        }
+    }
+
+    /// check if the content type of the response is of this encoding
+    pub fn matches(&self, content_type: &str) -> bool {
+        let name = match self {
+            Encoding::Protobuf(_) => "protobuf",
+            Encoding::Msgpack => "msgpack",
+            Encoding::Avro { .. } => "avro",
+        };
+        content_type.contains(name)
+    }
+
+    fn avro_schema(schema: &std::path::Path) -> miette::Result<avro::Avro> {
+        std::fs::read_to_string(schema)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Couldn't read avro schema {schema:?}"))?
+            .parse()
+            .map_err(|e| miette::miette!("Invalid avro schema {schema:?}: {e}"))
+    }
+
+    pub fn encode(&self, json: &str) -> miette::Result<Vec<u8>> {
+        let value = || -> miette::Result<serde_json::Value> {
+            serde_json::from_str(json)
+                .into_diagnostic()
+                .wrap_err("Body is not a valid json")
+        };
+        match self {
+            Encoding::Protobuf(schema) => schema.encode(json),
+            Encoding::Msgpack => rmp_serde::to_vec(&value()?)
+                .into_diagnostic()
+                .wrap_err("Couldn't encode body as msgpack"),
+            Encoding::Avro { schema } => Self::avro_schema(schema)?
+                .encode(&value()?)
+                .map_err(|e| miette::miette!("Couldn't encode body as avro: {e}")),
+        }
+    }
+
+    /// decode to pretty json
+    pub fn decode(&self, bytes: &[u8]) -> miette::Result<String> {
+        let value = match self {
+            Encoding::Protobuf(schema) => return schema.decode(bytes),
+            Encoding::Msgpack => rmp_serde::from_slice::<serde_json::Value>(bytes)
+                .into_diagnostic()
+                .wrap_err("Invalid msgpack")?,
+            Encoding::Avro { schema } => Self::avro_schema(schema)?
+                .decode(bytes)
+                .map_err(|e| miette::miette!("Invalid avro: {e}"))?,
+        };
+        serde_json::to_string_pretty(&value)
+            .into_diagnostic()
+            .wrap_err("Couldn't convert to json")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn msgpack_round_trip() {
+        let encoded = Encoding::Msgpack.encode(r#"{"id": 1}"#).unwrap();
+        assert_eq!(encoded, [0x81, 0xa2, b'i', b'd', 0x01]);
+        assert_eq!(
+            Encoding::Msgpack.decode(&encoded).unwrap(),
+            "{\n  \"id\": 1\n}"
+        );
+        assert!(Encoding::Msgpack.matches("application/x-msgpack"));
+    }
+}
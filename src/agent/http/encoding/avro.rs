@@ -0,0 +1,493 @@
+//! avro binary encoding of json values with a schema(`.avsc`), logical types are treated as their underlying type
+//! bytes and fixed are json strings whose characters are the bytes(ISO-8859-1), as in avro json encoding
+
+use std::collections::HashMap;
+
+use serde_json::{Map, Number, Value};
+
+#[derive(Debug, Clone)]
+enum Schema {
+    Null,
+    Boolean,
+    Int,
+    Long,
+    Float,
+    Double,
+    Bytes,
+    String,
+    Record(Vec<(String, Schema)>),
+    Enum(Vec<String>),
+    Array(Box<Schema>),
+    Map(Box<Schema>),
+    Union(Vec<Schema>),
+    Fixed(usize),
+    /// named type defined earlier in the schema
+    Ref(String),
+}
+
+/// parsed schema with its named types
+#[derive(Debug)]
+pub struct Avro {
+    root: Schema,
+    names: HashMap<String, Schema>,
+}
+
+impl std::str::FromStr for Avro {
+    type Err = String;
+
+    fn from_str(schema: &str) -> Result<Self, Self::Err> {
+        let schema: Value = serde_json::from_str(schema).map_err(|e| e.to_string())?;
+        let mut names = HashMap::new();
+        let root = parse(&schema, None, &mut names)?;
+        Ok(Self { root, names })
+    }
+}
+
+fn full_name(name: &str, namespace: Option<&str>) -> String {
+    match namespace {
+        Some(namespace) if !name.contains('.') && !namespace.is_empty() => {
+            format!("{namespace}.{name}")
+        }
+        _ => name.to_string(),
+    }
+}
+
+fn parse(
+    schema: &Value,
+    namespace: Option<&str>,
+    names: &mut HashMap<String, Schema>,
+) -> Result<Schema, String> {
+    match schema {
+        Value::String(name) => Ok(match name.as_str() {
+            "null" => Schema::Null,
+            "boolean" => Schema::Boolean,
+            "int" => Schema::Int,
+            "long" => Schema::Long,
+            "float" => Schema::Float,
+            "double" => Schema::Double,
+            "bytes" => Schema::Bytes,
+            "string" => Schema::String,
+            name => {
+                let name = [full_name(name, namespace), name.to_string()]
+                    .into_iter()
+                    .find(|name| names.contains_key(name))
+                    .ok_or_else(|| format!("unknown type {name}"))?;
+                Schema::Ref(name)
+            }
+        }),
+        Value::Array(branches) => branches
+            .iter()
+            .map(|branch| parse(branch, namespace, names))
+            .collect::<Result<_, _>>()
+            .map(Schema::Union),
+        Value::Object(definition) => {
+            let kind = definition.get("type").ok_or("type is missing")?;
+            let Value::String(kind) = kind else {
+                return parse(kind, namespace, names);
+            };
+            let name = || {
+                let name = definition
+                    .get("name")
+                    .and_then(Value::as_str)
+                    .ok_or(format!("{kind} doesn't have name"))?;
+                let namespace = definition
+                    .get("namespace")
+                    .and_then(Value::as_str)
+                    .or(namespace);
+                Ok::<_, String>(full_name(name, namespace))
+            };
+            let items = |key: &str, names: &mut HashMap<String, Schema>| {
+                parse(
+                    definition
+                        .get(key)
+                        .ok_or(format!("{kind} doesn't have {key}"))?,
+                    namespace,
+                    names,
+                )
+            };
+            match kind.as_str() {
+                "record" | "error" => {
+                    let name = name()?;
+                    let record_namespace = name.rsplit_once('.').map(|(namespace, _)| namespace);
+                    // placeholder so that fields can refer the record recursively
+                    names.insert(name.clone(), Schema::Record(Vec::new()));
+                    let fields = definition
+                        .get("fields")
+                        .and_then(Value::as_array)
+                        .ok_or(format!("record {name} doesn't have fields"))?
+                        .iter()
+                        .map(|field| {
+                            let field_name = field
+                                .get("name")
+                                .and_then(Value::as_str)
+                                .ok_or(format!("field of {name} doesn't have name"))?;
+                            let field_type = field
+                                .get("type")
+                                .ok_or(format!("field {field_name} doesn't have type"))?;
+                            Ok((
+                                field_name.to_string(),
+                                parse(field_type, record_namespace, names)?,
+                            ))
+                        })
+                        .collect::<Result<Vec<_>, String>>()?;
+                    names.insert(name.clone(), Schema::Record(fields));
+                    Ok(Schema::Ref(name))
+                }
+                "enum" => {
+                    let symbols = definition
+                        .get("symbols")
+                        .and_then(Value::as_array)
+                        .ok_or("enum doesn't have symbols")?
+                        .iter()
+                        .map(|symbol| symbol.as_str().map(str::to_string))
+                        .collect::<Option<_>>()
+                        .ok_or("enum symbols should be strings")?;
+                    let name = name()?;
+                    names.insert(name.clone(), Schema::Enum(symbols));
+                    Ok(Schema::Ref(name))
+                }
+                "fixed" => {
+                    let size = definition
+                        .get("size")
+                        .and_then(Value::as_u64)
+                        .ok_or("fixed doesn't have size")?;
+                    let name = name()?;
+                    names.insert(name.clone(), Schema::Fixed(size as usize));
+                    Ok(Schema::Ref(name))
+                }
+                "array" => Ok(Schema::Array(Box::new(items("items", names)?))),
+                "map" => Ok(Schema::Map(Box::new(items("values", names)?))),
+                _ => parse(&Value::String(kind.clone()), namespace, names),
+            }
+        }
+        _ => Err(format!("invalid schema {schema}")),
+    }
+}
+
+fn write_long(value: i64, out: &mut Vec<u8>) {
+    let mut zigzag = ((value << 1) ^ (value >> 63)) as u64;
+    while zigzag >= 0x80 {
+        out.push((zigzag as u8 & 0x7f) | 0x80);
+        zigzag >>= 7;
+    }
+    out.push(zigzag as u8);
+}
+
+fn read_long(input: &mut &[u8]) -> Result<i64, String> {
+    let mut zigzag = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = input.split_first().ok_or("unexpected end of data")?;
+        *input = rest;
+        zigzag |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok((zigzag >> 1) as i64 ^ -((zigzag & 1) as i64));
+        }
+    }
+    Err("invalid variable length integer".to_string())
+}
+
+/// bytes as characters of ISO-8859-1
+fn latin1_string(bytes: &[u8]) -> String {
+    bytes.iter().copied().map(char::from).collect()
+}
+
+/// characters of ISO-8859-1 as bytes, None if a character is beyond it
+fn latin1_bytes(text: &str) -> Option<Vec<u8>> {
+    text.chars().map(|c| u8::try_from(c).ok()).collect()
+}
+
+fn read_bytes<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8], String> {
+    if input.len() < len {
+        return Err("unexpected end of data".to_string());
+    }
+    let (bytes, rest) = input.split_at(len);
+    *input = rest;
+    Ok(bytes)
+}
+
+impl Avro {
+    fn resolve<'s>(&'s self, schema: &'s Schema) -> &'s Schema {
+        match schema {
+            Schema::Ref(name) => &self.names[name],
+            schema => schema,
+        }
+    }
+
+    /// check if the value can be written with the schema, used to pick union branch
+    fn matches(&self, schema: &Schema, value: &Value) -> bool {
+        match (self.resolve(schema), value) {
+            (Schema::Null, Value::Null) | (Schema::Boolean, Value::Bool(_)) => true,
+            (Schema::Int | Schema::Long, Value::Number(number)) => number.is_i64(),
+            (Schema::Float | Schema::Double, Value::Number(_)) => true,
+            (Schema::String, Value::String(_)) => true,
+            (Schema::Bytes, Value::String(bytes)) => latin1_bytes(bytes).is_some(),
+            (Schema::Enum(symbols), Value::String(symbol)) => symbols.contains(symbol),
+            (Schema::Fixed(size), Value::String(bytes)) => {
+                latin1_bytes(bytes).is_some_and(|bytes| bytes.len() == *size)
+            }
+            (Schema::Array(_), Value::Array(_)) => true,
+            (Schema::Map(_), Value::Object(_)) => true,
+            (Schema::Union(branches), value) => {
+                branches.iter().any(|branch| self.matches(branch, value))
+            }
+            (Schema::Record(fields), Value::Object(object)) => {
+                fields.iter().all(|(name, schema)| {
+                    self.matches(schema, object.get(name).unwrap_or(&Value::Null))
+                })
+            }
+            _ => false,
+        }
+    }
+
+    /// encode json value, union takes the first matching branch
+    pub fn encode(&self, value: &Value) -> Result<Vec<u8>, String> {
+        let mut out = Vec::new();
+        self.write(&self.root, value, "$", &mut out)?;
+        Ok(out)
+    }
+
+    fn write(
+        &self,
+        schema: &Schema,
+        value: &Value,
+        path: &str,
+        out: &mut Vec<u8>,
+    ) -> Result<(), String> {
+        let mismatch = || format!("{path}: {value} doesn't match {schema:?}");
+        match (self.resolve(schema), value) {
+            (Schema::Null, Value::Null) => {}
+            (Schema::Boolean, Value::Bool(flag)) => out.push(u8::from(*flag)),
+            (Schema::Int | Schema::Long, Value::Number(number)) => {
+                write_long(number.as_i64().ok_or_else(mismatch)?, out)
+            }
+            (Schema::Float, Value::Number(number)) => {
+                out.extend((number.as_f64().ok_or_else(mismatch)? as f32).to_le_bytes())
+            }
+            (Schema::Double, Value::Number(number)) => {
+                out.extend(number.as_f64().ok_or_else(mismatch)?.to_le_bytes())
+            }
+            (Schema::String, Value::String(text)) => {
+                write_long(text.len() as i64, out);
+                out.extend(text.as_bytes());
+            }
+            (Schema::Bytes, Value::String(bytes)) => {
+                let bytes = latin1_bytes(bytes).ok_or_else(mismatch)?;
+                write_long(bytes.len() as i64, out);
+                out.extend(bytes);
+            }
+            (Schema::Fixed(size), Value::String(bytes)) => {
+                let bytes = latin1_bytes(bytes)
+                    .filter(|bytes| bytes.len() == *size)
+                    .ok_or_else(mismatch)?;
+                out.extend(bytes)
+            }
+            (Schema::Enum(symbols), Value::String(symbol)) => {
+                let index = symbols
+                    .iter()
+                    .position(|s| s == symbol)
+                    .ok_or_else(mismatch)?;
+                write_long(index as i64, out)
+            }
+            (Schema::Array(items), Value::Array(values)) => {
+                if !values.is_empty() {
+                    write_long(values.len() as i64, out);
+                    for (index, value) in values.iter().enumerate() {
+                        self.write(items, value, &format!("{path}[{index}]"), out)?;
+                    }
+                }
+                out.push(0);
+            }
+            (Schema::Map(values), Value::Object(object)) => {
+                if !object.is_empty() {
+                    write_long(object.len() as i64, out);
+                    for (key, value) in object {
+                        write_long(key.len() as i64, out);
+                        out.extend(key.as_bytes());
+                        self.write(values, value, &format!("{path}.{key}"), out)?;
+                    }
+                }
+                out.push(0);
+            }
+            (Schema::Record(fields), Value::Object(object)) => {
+                for (name, schema) in fields {
+                    let value = object.get(name).unwrap_or(&Value::Null);
+                    self.write(schema, value, &format!("{path}.{name}"), out)?;
+                }
+            }
+            (Schema::Union(branches), value) => {
+                let index = branches
+                    .iter()
+                    .position(|branch| self.matches(branch, value))
+                    .ok_or_else(mismatch)?;
+                write_long(index as i64, out);
+                self.write(&branches[index], value, path, out)?;
+            }
+            _ => return Err(mismatch()),
+        }
+        Ok(())
+    }
+
+    pub fn decode(&self, mut input: &[u8]) -> Result<Value, String> {
+        self.read(&self.root, &mut input)
+    }
+
+    fn read(&self, schema: &Schema, input: &mut &[u8]) -> Result<Value, String> {
+        let read_len = |input: &mut &[u8]| {
+            usize::try_from(read_long(input)?).map_err(|_| "negative length".to_string())
+        };
+        Ok(match self.resolve(schema) {
+            Schema::Null => Value::Null,
+            Schema::Boolean => Value::Bool(read_bytes(input, 1)?[0] != 0),
+            Schema::Int | Schema::Long => Value::from(read_long(input)?),
+            Schema::Float => {
+                let bytes = read_bytes(input, 4)?
+                    .try_into()
+                    .map_err(|_| "invalid float")?;
+                Number::from_f64(f64::from(f32::from_le_bytes(bytes)))
+                    .map_or(Value::Null, Value::Number)
+            }
+            Schema::Double => {
+                let bytes = read_bytes(input, 8)?
+                    .try_into()
+                    .map_err(|_| "invalid double")?;
+                Number::from_f64(f64::from_le_bytes(bytes)).map_or(Value::Null, Value::Number)
+            }
+            Schema::String => {
+                let len = read_len(input)?;
+                Value::String(String::from_utf8_lossy(read_bytes(input, len)?).into_owned())
+            }
+            Schema::Bytes => {
+                let len = read_len(input)?;
+                Value::String(latin1_string(read_bytes(input, len)?))
+            }
+            Schema::Fixed(size) => Value::String(latin1_string(read_bytes(input, *size)?)),
+            Schema::Enum(symbols) => {
+                let index = read_len(input)?;
+                Value::String(symbols.get(index).ok_or("invalid enum index")?.clone())
+            }
+            Schema::Array(items) => {
+                let mut values = Vec::new();
+                let mut limit = input.len();
+                while let Some(count) = read_block_count(input, &mut limit)? {
+                    for _ in 0..count {
+                        values.push(self.read(items, input)?);
+                    }
+                }
+                Value::Array(values)
+            }
+            Schema::Map(values) => {
+                let mut object = Map::new();
+                let mut limit = input.len();
+                while let Some(count) = read_block_count(input, &mut limit)? {
+                    for _ in 0..count {
+                        let len = read_len(input)?;
+                        let key = String::from_utf8_lossy(read_bytes(input, len)?).into_owned();
+                        object.insert(key, self.read(values, input)?);
+                    }
+                }
+                Value::Object(object)
+            }
+            Schema::Record(fields) => {
+                let mut object = Map::new();
+                for (name, schema) in fields {
+                    object.insert(name.clone(), self.read(schema, input)?);
+                }
+                Value::Object(object)
+            }
+            Schema::Union(branches) => {
+                let index = read_len(input)?;
+                self.read(branches.get(index).ok_or("invalid union index")?, input)?
+            }
+            Schema::Ref(name) => return Err(format!("unresolved type {name}")),
+        })
+    }
+}
+
+/// item count of the next block of array/map, None at the end
+/// negative count is followed by the size of the block in bytes
+/// items of all the blocks can't be more than the bytes left at the start of array/map(limit),
+/// otherwise crafted counts of empty items(ex: null) would never end
+fn read_block_count(input: &mut &[u8], limit: &mut usize) -> Result<Option<usize>, String> {
+    let count = match read_long(input)? {
+        0 => return Ok(None),
+        count if count < 0 => {
+            read_long(input)?;
+            count.unsigned_abs()
+        }
+        count => count as u64,
+    };
+    let count = usize::try_from(count)
+        .ok()
+        .filter(|count| count <= limit)
+        .ok_or_else(|| format!("block of {count} items is more than the data left"))?;
+    *limit -= count;
+    Ok(Some(count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_and_decode() {
+        let avro: Avro = r#"{
+            "type": "record", "name": "Event", "namespace": "ingest",
+            "fields": [
+                {"name": "id", "type": "long"},
+                {"name": "kind", "type": {"type": "enum", "name": "Kind", "symbols": ["click", "view"]}},
+                {"name": "tags", "type": {"type": "array", "items": "string"}},
+                {"name": "parent", "type": ["null", "Event"]},
+                {"name": "score", "type": "double"}
+            ]
+        }"#
+        .parse()
+        .unwrap();
+        let event = serde_json::json!({
+            "id": -2,
+            "kind": "view",
+            "tags": ["a"],
+            "parent": {"id": 1, "kind": "click", "tags": [], "parent": null, "score": 0.5},
+            "score": 1.5
+        });
+        let encoded = avro.encode(&event).unwrap();
+        assert_eq!(&encoded[..6], [0x03, 0x02, 0x02, 0x02, b'a', 0x00]);
+        assert_eq!(avro.decode(&encoded).unwrap(), event);
+        assert!(avro
+            .encode(&serde_json::json!({"id": 1, "kind": "scroll", "tags": [], "score": 0}))
+            .is_err());
+    }
+
+    #[test]
+    fn bytes_are_latin1() {
+        let avro: Avro = r#"{
+            "type": "record", "name": "Blob",
+            "fields": [
+                {"name": "data", "type": "bytes"},
+                {"name": "hash", "type": {"type": "fixed", "name": "Hash", "size": 2}}
+            ]
+        }"#
+        .parse()
+        .unwrap();
+        let blob = serde_json::json!({"data": "\u{ff}\u{0}", "hash": "\u{e9}a"});
+        let encoded = avro.encode(&blob).unwrap();
+        assert_eq!(encoded, [0x04, 0xff, 0x00, 0xe9, b'a']);
+        assert_eq!(avro.decode(&encoded).unwrap(), blob);
+        assert!(avro
+            .encode(&serde_json::json!({"data": "\u{100}", "hash": "ab"}))
+            .is_err());
+    }
+
+    #[test]
+    fn crafted_block_count_fails() {
+        let avro: Avro = r#"{"type": "array", "items": "null"}"#.parse().unwrap();
+        assert_eq!(
+            avro.decode(&[0x04, 0x00]).unwrap(),
+            serde_json::json!([null, null])
+        );
+        // count of i64::MAX nulls
+        let mut input = Vec::new();
+        write_long(i64::MAX, &mut input);
+        input.push(0);
+        assert!(avro.decode(&input).is_err());
+    }
+}
@@ -0,0 +1,145 @@
+//! protobuf bodies written as json, encoded with the message descriptor while sending and decoded back for display
+
+use miette::{Context, IntoDiagnostic};
+use prost_reflect::prost::Message;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+/// message type and the file describing it
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct Schema {
+    /// `.proto` file(compiled with protoc) or file descriptor set(`protoc --include_imports --descriptor_set_out`)
+    descriptor: std::path::PathBuf,
+    /// fully qualified message name, ex: `shop.v1.Order`
+    message: String,
+}
+
+impl Schema {
+    fn message_descriptor(&self) -> miette::Result<prost_reflect::MessageDescriptor> {
+        let descriptor_set = if self
+            .descriptor
+            .extension()
+            .is_some_and(|extension| extension == "proto")
+        {
+            compile(&self.descriptor)?
+        } else {
+            std::fs::read(&self.descriptor)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Couldn't read descriptor {:?}", self.descriptor))?
+        };
+        let pool = prost_reflect::DescriptorPool::decode(descriptor_set.as_slice())
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Invalid descriptor set {:?}", self.descriptor))?;
+        pool.get_message_by_name(&self.message).ok_or_else(|| {
+            miette::miette!(
+                "Couldn't find message {} in {:?}",
+                self.message,
+                self.descriptor
+            )
+        })
+    }
+
+    /// encode json value of the message to binary
+    pub fn encode(&self, json: &str) -> miette::Result<Vec<u8>> {
+        let descriptor = self.message_descriptor()?;
+        let mut deserializer = serde_json::Deserializer::from_str(json);
+        let message = prost_reflect::DynamicMessage::deserialize(descriptor, &mut deserializer)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Couldn't convert json to {}", self.message))?;
+        Ok(message.encode_to_vec())
+    }
+
+    /// decode binary message to pretty json
+    pub fn decode(&self, bytes: &[u8]) -> miette::Result<String> {
+        let descriptor = self.message_descriptor()?;
+        let message = prost_reflect::DynamicMessage::decode(descriptor, bytes)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Couldn't decode {}", self.message))?;
+        serde_json::to_string_pretty(&message)
+            .into_diagnostic()
+            .wrap_err("Couldn't convert message to json")
+    }
+}
+
+/// compile `.proto` file to descriptor set with protoc, imports are searched relative to its directory
+fn compile(proto: &std::path::Path) -> miette::Result<Vec<u8>> {
+    let out = std::env::temp_dir().join(format!("qwicket-{}.desc", std::process::id()));
+    let include = proto
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(std::path::Path::new("."));
+    debug!("compiling {proto:?} to {out:?}");
+    let status = std::process::Command::new("protoc")
+        .arg("--include_imports")
+        .arg(format!("--descriptor_set_out={}", out.display()))
+        .arg("-I")
+        .arg(include)
+        .arg(proto)
+        .stderr(std::process::Stdio::inherit())
+        .status()
+        .into_diagnostic()
+        .wrap_err("Couldn't run protoc, install it or use descriptor set instead of .proto")?;
+    if !status.success() {
+        miette::bail!("protoc exited with error: {status}")
+    }
+    let descriptor_set = std::fs::read(&out)
+        .into_diagnostic()
+        .wrap_err("Couldn't read compiled descriptor set");
+    let _ = std::fs::remove_file(&out);
+    descriptor_set
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prost_reflect::prost_types::{
+        field_descriptor_proto::{Label, Type},
+        DescriptorProto, FieldDescriptorProto, FileDescriptorProto, FileDescriptorSet,
+    };
+
+    fn field(name: &str, number: i32, kind: Type) -> FieldDescriptorProto {
+        FieldDescriptorProto {
+            name: Some(name.to_string()),
+            json_name: Some(name.to_string()),
+            number: Some(number),
+            label: Some(Label::Optional as i32),
+            r#type: Some(kind as i32),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let descriptor_set = FileDescriptorSet {
+            file: vec![FileDescriptorProto {
+                name: Some("user.proto".to_string()),
+                package: Some("test".to_string()),
+                syntax: Some("proto3".to_string()),
+                message_type: vec![DescriptorProto {
+                    name: Some("User".to_string()),
+                    field: vec![field("id", 1, Type::Int32), field("name", 2, Type::String)],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+        let descriptor = std::env::temp_dir().join("qwicket-test-user.desc");
+        std::fs::write(&descriptor, descriptor_set.encode_to_vec()).unwrap();
+        let schema = Schema {
+            descriptor: descriptor.clone(),
+            message: "test.User".to_string(),
+        };
+        let encoded = schema.encode(r#"{"id": 150, "name": "a"}"#).unwrap();
+        assert_eq!(encoded, [0x08, 0x96, 0x01, 0x12, 0x01, b'a']);
+        let decoded: serde_json::Value =
+            serde_json::from_str(&schema.decode(&encoded).unwrap()).unwrap();
+        assert_eq!(decoded, serde_json::json!({"id": 150, "name": "a"}));
+        assert!(schema.encode(r#"{"unknown": 1}"#).is_err());
+        let missing = Schema {
+            message: "test.Order".to_string(),
+            ..schema
+        };
+        assert!(missing.encode("{}").is_err());
+        std::fs::remove_file(descriptor).unwrap();
+    }
+}
@@ -0,0 +1,575 @@
+//! expectations of the response, query fails when any of them is not met
+
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{Number, Value};
+use tracing::warn;
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Expect {
+    /// latency budget of the request, ex: "500ms"
+    #[serde(
+        default,
+        deserialize_with = "crate::archive::deserialize_duration",
+        serialize_with = "crate::archive::serialize_duration",
+        skip_serializing_if = "Option::is_none"
+    )]
+    max_duration: Option<std::time::Duration>,
+    /// severity of `max_duration` instead of `severity`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_duration_severity: Option<Severity>,
+    /// headers which should be in the response, with any value
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    headers_present: Vec<String>,
+    /// values of the response headers, ex: `header."content-type" = "application/json"`
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    header: IndexMap<String, HeaderMatch>,
+    /// assertions on the json body, all of them should pass
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    body: Vec<BodyAssertion>,
+    /// `warn` only reports the failures, for checks which are being introduced
+    /// checks without their own severity use this
+    #[serde(default, skip_serializing_if = "Severity::is_error")]
+    severity: Severity,
+}
+
+/// failed expectation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+    severity: Severity,
+    reason: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum Severity {
+    /// query fails
+    #[default]
+    Error,
+    Warn,
+}
+
+impl Severity {
+    fn is_error(&self) -> bool {
+        *self == Severity::Error
+    }
+}
+
+/// assertion of the body list, which can have its own severity
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+struct BodyAssertion {
+    #[serde(flatten)]
+    assertion: Assertion,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    severity: Option<Severity>,
+}
+
+/// `all`, `any` and `not` combine other assertions, rest are checks of the values at a jsonpath
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+enum Assertion {
+    All { all: Vec<Assertion> },
+    Any { any: Vec<Assertion> },
+    Not { not: Box<Assertion> },
+    Check(Check),
+}
+
+/// every value matching the path should pass all the comparisons, ex: `{ path = "$.items", length = true, gte = 1 }`
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+struct Check {
+    path: crate::jsonpath::JsonPath,
+    /// whether the path should match any value
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    exists: Option<bool>,
+    /// compare number of items of arrays, fields of objects or characters of strings instead of the values
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    length: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    eq: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ne: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    gt: Option<Number>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    gte: Option<Number>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    lt: Option<Number>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    lte: Option<Number>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Operator {
+    Eq,
+    Ne,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+}
+
+impl Operator {
+    fn passes(self, actual: &Value, expected: &Value) -> bool {
+        let numbers = actual.as_f64().zip(expected.as_f64());
+        match self {
+            // 1 and 1.0 are same
+            Operator::Eq => {
+                numbers.map_or(actual == expected, |(actual, expected)| actual == expected)
+            }
+            Operator::Ne => !Operator::Eq.passes(actual, expected),
+            Operator::Gt => numbers.is_some_and(|(actual, expected)| actual > expected),
+            Operator::Gte => numbers.is_some_and(|(actual, expected)| actual >= expected),
+            Operator::Lt => numbers.is_some_and(|(actual, expected)| actual < expected),
+            Operator::Lte => numbers.is_some_and(|(actual, expected)| actual <= expected),
+        }
+    }
+}
+
+impl std::fmt::Display for Operator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Operator::Eq => "==",
+            Operator::Ne => "!=",
+            Operator::Gt => ">",
+            Operator::Gte => ">=",
+            Operator::Lt => "<",
+            Operator::Lte => "<=",
+        })
+    }
+}
+
+impl Check {
+    fn comparisons(&self) -> Vec<(Operator, Value)> {
+        let numbers = [
+            (Operator::Gt, &self.gt),
+            (Operator::Gte, &self.gte),
+            (Operator::Lt, &self.lt),
+            (Operator::Lte, &self.lte),
+        ]
+        .into_iter()
+        .filter_map(|(operator, number)| Some((operator, Value::Number(number.clone()?))));
+        [(Operator::Eq, &self.eq), (Operator::Ne, &self.ne)]
+            .into_iter()
+            .filter_map(|(operator, value)| Some((operator, value.clone()?)))
+            .chain(numbers)
+            .collect()
+    }
+
+    fn evaluate(&self, body: &Value) -> Result<(), String> {
+        let selected = self.path.select(body);
+        match self.exists {
+            Some(true) if selected.is_empty() => {
+                return Err(format!("{} doesn't exist", self.path))
+            }
+            Some(false) if !selected.is_empty() => return Err(format!("{} exists", self.path)),
+            _ => {}
+        }
+        let comparisons = self.comparisons();
+        if comparisons.is_empty() && !self.length {
+            return Ok(());
+        }
+        if selected.is_empty() {
+            return Err(format!("{} doesn't exist", self.path));
+        }
+        for value in selected {
+            let actual = if self.length {
+                match value {
+                    Value::Array(list) => Value::from(list.len()),
+                    Value::Object(map) => Value::from(map.len()),
+                    Value::String(text) => Value::from(text.chars().count()),
+                    _ => {
+                        return Err(format!(
+                            "{} is {value}, which doesn't have a length",
+                            self.path
+                        ))
+                    }
+                }
+            } else {
+                value.clone()
+            };
+            if !comparisons
+                .iter()
+                .all(|(operator, expected)| operator.passes(&actual, expected))
+            {
+                return Err(format!("expected {self}, found {actual}"));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Check {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.path)?;
+        if self.length {
+            write!(f, ".length")?;
+        }
+        let mut conditions: Vec<String> = self
+            .comparisons()
+            .iter()
+            .map(|(operator, expected)| format!("{operator} {expected}"))
+            .collect();
+        match self.exists {
+            Some(true) => conditions.insert(0, "exists".to_string()),
+            Some(false) => conditions.insert(0, "doesn't exist".to_string()),
+            None => {}
+        }
+        write!(f, " {}", conditions.join(" and "))
+    }
+}
+
+impl Assertion {
+    /// reason of the failure
+    fn evaluate(&self, body: &Value) -> Result<(), String> {
+        match self {
+            Assertion::All { all: assertions } => all(assertions, body),
+            Assertion::Any { any } => {
+                let mut reasons = Vec::new();
+                for assertion in any {
+                    match assertion.evaluate(body) {
+                        Ok(()) => return Ok(()),
+                        Err(reason) => reasons.push(reason),
+                    }
+                }
+                Err(format!("none of any passed({})", reasons.join(" or ")))
+            }
+            Assertion::Not { not } => match not.evaluate(body) {
+                Ok(()) => Err(format!("expected not({not})")),
+                Err(_) => Ok(()),
+            },
+            Assertion::Check(check) => check.evaluate(body),
+        }
+    }
+}
+
+/// reasons of all the failed assertions
+fn all(assertions: &[Assertion], body: &Value) -> Result<(), String> {
+    let reasons: Vec<String> = assertions
+        .iter()
+        .filter_map(|assertion| assertion.evaluate(body).err())
+        .collect();
+    if reasons.is_empty() {
+        Ok(())
+    } else {
+        Err(reasons.join(", "))
+    }
+}
+
+impl std::fmt::Display for Assertion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let join = |assertions: &[Assertion]| {
+            assertions
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        match self {
+            Assertion::All { all } => write!(f, "all({})", join(all)),
+            Assertion::Any { any } => write!(f, "any({})", join(any)),
+            Assertion::Not { not } => write!(f, "not({not})"),
+            Assertion::Check(check) => write!(f, "{check}"),
+        }
+    }
+}
+
+/// exact value or `{ regex = "..." }` which should match some part of the value
+/// `{ value = "...", severity = "warn" }` is exact value with its own severity
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+enum HeaderMatch {
+    Exact(String),
+    Value {
+        value: String,
+        severity: Option<Severity>,
+    },
+    Regex {
+        regex: String,
+        severity: Option<Severity>,
+    },
+}
+
+impl HeaderMatch {
+    fn matches(&self, value: &str) -> Result<bool, String> {
+        match self {
+            HeaderMatch::Exact(expected)
+            | HeaderMatch::Value {
+                value: expected, ..
+            } => Ok(expected == value),
+            HeaderMatch::Regex { regex, .. } => regex::Regex::new(regex)
+                .map(|regex| regex.is_match(value))
+                .map_err(|e| format!("invalid regex {regex}: {e}")),
+        }
+    }
+
+    fn severity(&self) -> Option<Severity> {
+        match self {
+            HeaderMatch::Exact(_) => None,
+            HeaderMatch::Value { severity, .. } | HeaderMatch::Regex { severity, .. } => *severity,
+        }
+    }
+}
+
+impl std::fmt::Display for HeaderMatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HeaderMatch::Exact(expected)
+            | HeaderMatch::Value {
+                value: expected, ..
+            } => write!(f, "{expected:?}"),
+            HeaderMatch::Regex { regex, .. } => write!(f, "matching /{regex}/"),
+        }
+    }
+}
+
+impl Expect {
+    fn failure(&self, severity: Option<Severity>, reason: String) -> Failure {
+        Failure {
+            severity: severity.unwrap_or(self.severity),
+            reason,
+        }
+    }
+
+    /// failure if the response took more than the latency budget
+    pub fn evaluate(&self, elapsed: std::time::Duration) -> Vec<Failure> {
+        match self.max_duration {
+            Some(max_duration) if elapsed > max_duration => vec![self.failure(
+                self.max_duration_severity,
+                format!(
+                    "took {} ms, expected at most {}",
+                    elapsed.as_millis(),
+                    humantime::format_duration(max_duration)
+                ),
+            )],
+            _ => Vec::new(),
+        }
+    }
+
+    /// missing or mismatching headers, header names are case insensitive
+    pub fn check_headers(&self, headers: &IndexMap<String, String>) -> Vec<Failure> {
+        let find = |name: &str| {
+            headers
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.as_str())
+        };
+        let mut failures: Vec<Failure> = self
+            .headers_present
+            .iter()
+            .filter(|name| find(name).is_none())
+            .map(|name| self.failure(None, format!("header {name} is missing")))
+            .collect();
+        for (name, expected) in &self.header {
+            let reason = match find(name).map(|value| (value, expected.matches(value))) {
+                None => format!("header {name} is missing, expected {expected}"),
+                Some((_, Err(e))) => e,
+                Some((value, Ok(false))) => {
+                    format!("header {name} is {value:?}, expected {expected}")
+                }
+                Some((_, Ok(true))) => continue,
+            };
+            failures.push(self.failure(expected.severity(), reason));
+        }
+        failures
+    }
+
+    /// fail the query with the failures of error severity, failures of warn severity are only reported
+    pub fn enforce(failures: Vec<Failure>) -> miette::Result<()> {
+        let mut errors = Vec::new();
+        for Failure { severity, reason } in failures {
+            match severity {
+                Severity::Error => errors.push(reason),
+                Severity::Warn => warn!("Expectation failed: {reason}"),
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(miette::miette!("Expectation failed: {}", errors.join(", ")))
+        }
+    }
+
+    /// failed body assertions, body should be json when there are any
+    pub fn check_body(&self, body: &[u8]) -> Vec<Failure> {
+        if self.body.is_empty() {
+            return Vec::new();
+        }
+        let body: Value = match serde_json::from_slice(body) {
+            Ok(body) => body,
+            Err(e) => return vec![self.failure(None, format!("body is not json: {e}"))],
+        };
+        self.body
+            .iter()
+            .filter_map(
+                |BodyAssertion {
+                     assertion,
+                     severity,
+                 }| {
+                    let reason = assertion.evaluate(&body).err()?;
+                    Some(self.failure(*severity, reason))
+                },
+            )
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// reasons of the failures regardless of severity
+    fn reasons(failures: Vec<Failure>) -> Result<(), String> {
+        if failures.is_empty() {
+            return Ok(());
+        }
+        Err(failures
+            .into_iter()
+            .map(|failure| failure.reason)
+            .collect::<Vec<_>>()
+            .join(", "))
+    }
+
+    #[test]
+    fn latency_budget() {
+        let expect: Expect = toml::from_str(r#"max_duration = "500ms""#).unwrap();
+        assert!(expect
+            .evaluate(std::time::Duration::from_millis(499))
+            .is_empty());
+        assert_eq!(
+            reasons(expect.evaluate(std::time::Duration::from_millis(812))),
+            Err("took 812 ms, expected at most 500ms".to_string())
+        );
+        assert!(Expect::default()
+            .evaluate(std::time::Duration::from_secs(60))
+            .is_empty());
+        assert!(toml::from_str::<Expect>(r#"max_duration = "soon""#).is_err());
+    }
+
+    #[test]
+    fn header_matchers() {
+        let expect: Expect = toml::from_str(
+            r#"
+            headers_present = ["X-Request-Id"]
+            header."content-type" = "application/json"
+            header.cache-control = { regex = "max-age=\\d+" }
+            "#,
+        )
+        .unwrap();
+        let mut headers = IndexMap::from([
+            ("x-request-id".to_string(), "abc".to_string()),
+            ("content-type".to_string(), "application/json".to_string()),
+            (
+                "cache-control".to_string(),
+                "public, max-age=60".to_string(),
+            ),
+        ]);
+        assert!(expect.check_headers(&headers).is_empty());
+        headers.shift_remove("x-request-id");
+        headers.insert("cache-control".to_string(), "no-store".to_string());
+        assert_eq!(
+            reasons(expect.check_headers(&headers)),
+            Err(r#"header X-Request-Id is missing, header cache-control is "no-store", expected matching /max-age=\d+/"#.to_string())
+        );
+    }
+
+    #[test]
+    fn body_assertions() {
+        let expect: Expect = toml::from_str(
+            r#"
+            body = [
+                { path = "$.items", length = true, gte = 1 },
+                { path = "$.total", gt = 0, lte = 100 },
+                { any = [{ path = "$.status", eq = "active" }, { path = "$.status", eq = "pending" }] },
+                { not = { path = "$.error", exists = true } },
+                { all = [{ path = "$.items[*].price", gte = 0.5 }, { path = "$.currency", ne = "XXX" }] },
+            ]
+            "#,
+        )
+        .unwrap();
+        let body = serde_json::json!({
+            "items": [{"price": 1}, {"price": 2.5}],
+            "total": 2,
+            "status": "active",
+            "currency": "EUR",
+        });
+        assert!(expect.check_body(body.to_string().as_bytes()).is_empty());
+
+        let body = serde_json::json!({
+            "items": [{"price": 0}],
+            "total": 0,
+            "status": "closed",
+            "currency": "EUR",
+            "error": "gone",
+        });
+        assert_eq!(
+            reasons(expect.check_body(body.to_string().as_bytes())),
+            Err([
+                "expected $.total > 0 and <= 100, found 0",
+                r#"none of any passed(expected $.status == "active", found "closed" or expected $.status == "pending", found "closed")"#,
+                "expected not($.error exists)",
+                "expected $.items[*].price >= 0.5, found 0",
+            ]
+            .join(", "))
+        );
+        assert!(!expect.check_body(b"<html>").is_empty());
+        assert!(Expect::default().check_body(b"<html>").is_empty());
+        assert!(toml::from_str::<Expect>(r#"body = [{ path = "$.total", greater = 1 }]"#).is_err());
+    }
+
+    #[test]
+    fn soft_expectations() {
+        let mut expect: Expect = toml::from_str(r#"headers_present = ["etag"]"#).unwrap();
+        assert!(Expect::enforce(expect.check_headers(&IndexMap::new())).is_err());
+        expect = toml::from_str(
+            r#"
+            headers_present = ["etag"]
+            severity = "warn"
+            "#,
+        )
+        .unwrap();
+        assert!(Expect::enforce(expect.check_headers(&IndexMap::new())).is_ok());
+        assert!(toml::from_str::<Expect>(r#"severity = "fatal""#).is_err());
+
+        // checks with their own severity
+        let expect: Expect = toml::from_str(
+            r#"
+            max_duration = "1s"
+            max_duration_severity = "warn"
+            header.etag = { value = "v1", severity = "warn" }
+            header.vary = { regex = "accept", severity = "error" }
+            body = [
+                { path = "$.id", exists = true, severity = "warn" },
+                { path = "$.name", exists = true },
+            ]
+            severity = "warn"
+            "#,
+        )
+        .unwrap();
+        let severities = |failures: Vec<Failure>| -> Vec<Severity> {
+            failures
+                .into_iter()
+                .map(|failure| failure.severity)
+                .collect()
+        };
+        assert_eq!(
+            severities(expect.evaluate(std::time::Duration::from_secs(2))),
+            [Severity::Warn]
+        );
+        let headers = expect.check_headers(&IndexMap::new());
+        assert_eq!(
+            severities(headers.clone()),
+            [Severity::Warn, Severity::Error]
+        );
+        assert!(Expect::enforce(headers).is_err());
+        assert_eq!(
+            severities(expect.check_body(b"{}")),
+            [Severity::Warn, Severity::Warn]
+        );
+        assert!(toml::from_str::<Expect>(
+            r#"body = [{ all = [{ path = "$.id", exists = true, severity = "warn" }] }]"#
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,87 @@
+//! faults injected on the client side to exercise retry logic of hooks and automation without a misbehaving server
+
+use tracing::warn;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Faults {
+    /// delay before sending each request, counted in its latency
+    pub latency: Option<std::time::Duration>,
+    /// fraction of requests failed without sending them, 0 to 1
+    pub abort_rate: f64,
+    /// fraction of requests failed as timed out after waiting for their timeout, 0 to 1
+    pub timeout_rate: f64,
+}
+
+impl Faults {
+    pub fn from_args(args: &crate::Arguments) -> Self {
+        Self {
+            latency: args.inject_latency,
+            abort_rate: args.inject_abort_rate.unwrap_or_default(),
+            timeout_rate: args.inject_timeout_rate.unwrap_or_default(),
+        }
+    }
+
+    /// called before every attempt of sending the request with its timeout
+    pub async fn inject(&self, timeout: Option<std::time::Duration>) -> miette::Result<()> {
+        if let Some(latency) = self.latency {
+            warn!("injecting {} latency", humantime::format_duration(latency));
+            tokio::time::sleep(latency).await;
+        }
+        if aborts(self.abort_rate, fastrand::f64()) {
+            miette::bail!("request aborted by --inject-abort-rate")
+        }
+        if aborts(self.timeout_rate, fastrand::f64()) {
+            if let Some(timeout) = timeout {
+                tokio::time::sleep(timeout).await;
+            }
+            miette::bail!("request timed out by --inject-timeout-rate")
+        }
+        Ok(())
+    }
+}
+
+/// random is uniform in 0..1
+fn aborts(rate: f64, random: f64) -> bool {
+    random < rate
+}
+
+/// fraction between 0 and 1
+pub fn parse_rate(rate: &str) -> Result<f64, String> {
+    let rate: f64 = rate
+        .parse()
+        .map_err(|e| format!("invalid rate {rate}: {e}"))?;
+    if (0.0..=1.0).contains(&rate) {
+        Ok(rate)
+    } else {
+        Err(format!("rate should be between 0 and 1, found {rate}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn abort_rate() {
+        assert_eq!(parse_rate("0.2"), Ok(0.2));
+        assert!(parse_rate("1.5").is_err());
+        assert!(parse_rate("ten").is_err());
+        assert!(aborts(0.2, 0.1));
+        assert!(!aborts(0.2, 0.5));
+        assert!(!aborts(0.0, 0.0));
+        assert!(aborts(1.0, 0.999));
+    }
+
+    #[tokio::test]
+    async fn timeout_waits_for_request_timeout() {
+        let faults = Faults {
+            timeout_rate: 1.0,
+            ..Faults::default()
+        };
+        let started = std::time::Instant::now();
+        let timeout = std::time::Duration::from_millis(20);
+        assert!(faults.inject(Some(timeout)).await.is_err());
+        assert!(started.elapsed() >= timeout);
+        assert!(Faults::default().inject(Some(timeout)).await.is_ok());
+    }
+}
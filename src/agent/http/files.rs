@@ -0,0 +1,137 @@
+//! files used in bodies, files of a multipart `files` part(a directory or a glob pattern like `artifacts/**/*.png`)
+//! and content types of the files
+
+use std::path::{Path, PathBuf};
+
+use miette::{Context, IntoDiagnostic};
+
+fn is_pattern(component: &str) -> bool {
+    component.contains(['*', '?', '['])
+}
+
+/// files of the directory or matching the pattern, sorted by path
+/// `*` doesn't match across directories, `**` matches any number of directories
+pub fn expand(pattern: &str) -> miette::Result<Vec<PathBuf>> {
+    let path = Path::new(pattern);
+    let mut files = if path.is_dir() {
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Couldn't read directory {path:?}"))?
+        {
+            let entry = entry.into_diagnostic()?;
+            if entry.file_type().into_diagnostic()?.is_file() {
+                files.push(entry.path());
+            }
+        }
+        files
+    } else {
+        let components: Vec<_> = pattern.split('/').collect();
+        let literal = components
+            .iter()
+            .take_while(|component| !is_pattern(component))
+            .count();
+        if literal == components.len() {
+            miette::bail!("{pattern} is neither a directory nor a glob pattern")
+        }
+        let base: PathBuf = match components[..literal].join("/") {
+            base if base.is_empty() && pattern.starts_with('/') => "/".into(),
+            base if base.is_empty() => ".".into(),
+            base => base.into(),
+        };
+        let mut files = Vec::new();
+        walk(&base, &components[literal..], &mut files)?;
+        files
+    };
+    if files.is_empty() {
+        miette::bail!("no files found for {pattern}")
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// content type from the extension of the file, else from magic bytes of the content
+pub fn content_type(path: Option<&Path>, content: &[u8]) -> Option<String> {
+    path.and_then(|path| mime_guess::from_path(path).first())
+        .map(|mime| mime.to_string())
+        .or_else(|| infer::get(content).map(|kind| kind.mime_type().to_string()))
+}
+
+fn walk(directory: &Path, pattern: &[&str], files: &mut Vec<PathBuf>) -> miette::Result<()> {
+    let Some((component, rest)) = pattern.split_first() else {
+        return Ok(());
+    };
+    if *component == "**" {
+        // zero directories
+        walk(directory, rest, files)?;
+    }
+    let matcher = wildmatch::WildMatch::new(component);
+    let entries = std::fs::read_dir(directory)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Couldn't read directory {directory:?}"))?;
+    for entry in entries {
+        let entry = entry.into_diagnostic()?;
+        let file_type = entry.file_type().into_diagnostic()?;
+        if *component == "**" {
+            if file_type.is_dir() {
+                walk(&entry.path(), pattern, files)?;
+            }
+            continue;
+        }
+        if !matcher.matches(&entry.file_name().to_string_lossy()) {
+            continue;
+        }
+        if rest.is_empty() {
+            if file_type.is_file() {
+                files.push(entry.path());
+            }
+        } else if file_type.is_dir() {
+            walk(&entry.path(), rest, files)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directory_and_globs() {
+        let dir = std::env::temp_dir().join(format!("qwicket-files-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        for file in ["b.png", "a.png", "notes.txt", "nested/c.png"] {
+            std::fs::write(dir.join(file), file).unwrap();
+        }
+        let root = dir.to_str().unwrap();
+        let names = |pattern: String| -> Vec<String> {
+            expand(&pattern)
+                .unwrap()
+                .iter()
+                .map(|path| path.strip_prefix(&dir).unwrap().display().to_string())
+                .collect()
+        };
+        assert_eq!(names(format!("{root}/*.png")), ["a.png", "b.png"]);
+        assert_eq!(
+            names(format!("{root}/**/*.png")),
+            ["a.png", "b.png", "nested/c.png"]
+        );
+        assert_eq!(names(root.to_string()), ["a.png", "b.png", "notes.txt"]);
+        assert!(expand(&format!("{root}/*.gif")).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn guessed_content_types() {
+        assert_eq!(
+            content_type(Some(Path::new("a.png")), b"").as_deref(),
+            Some("image/png")
+        );
+        let gzip = [0x1f, 0x8b, 0x08];
+        assert_eq!(
+            content_type(Some(Path::new("data")), &gzip).as_deref(),
+            Some("application/gzip")
+        );
+        assert_eq!(content_type(None, b"plain"), None);
+    }
+}
@@ -0,0 +1,252 @@
+//! declarative changes to the request applied after substitution and to the response before it is shown,
+//! covers simple cases which otherwise need a hook
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use super::{PreparedQuery, Response, UnpackedBody};
+
+/// name and value pair of header or query param
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Pair {
+    name: String,
+    value: String,
+}
+
+/// regex replacement, `with` can refer capture groups as `$1` or `${name}`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Replace {
+    #[serde(with = "serde_regex")]
+    pattern: regex::Regex,
+    with: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum Rule {
+    /// add the header or replace existing one(case insensitive)
+    SetHeader(Pair),
+    RemoveHeader(String),
+    /// append query param, existing params with same name are kept
+    AddParam(Pair),
+    /// replace all the matches in utf8 body, binary bodies are not changed
+    ReplaceBody(Replace),
+}
+
+impl Rule {
+    fn apply(&self, query: &mut PreparedQuery) {
+        match self {
+            Rule::SetHeader(Pair { name, value }) => {
+                query
+                    .headers
+                    .retain(|key, _| !key.eq_ignore_ascii_case(name));
+                query.headers.insert(name.clone(), value.clone());
+            }
+            Rule::RemoveHeader(name) => {
+                query
+                    .headers
+                    .retain(|key, _| !key.eq_ignore_ascii_case(name));
+            }
+            Rule::AddParam(Pair { name, value }) => {
+                query.args.push((name.clone(), value.clone()));
+            }
+            Rule::ReplaceBody(Replace { pattern, with }) => match &mut query.body {
+                Some(UnpackedBody::Utf8(body)) => {
+                    *body = pattern.replace_all(body, with.as_str()).into_owned();
+                }
+                Some(
+                    UnpackedBody::Raw(_)
+                    | UnpackedBody::Command { .. }
+                    | UnpackedBody::Encoded { .. },
+                ) => {
+                    warn!("body is binary, not replacing {pattern}")
+                }
+                None => debug!("request doesn't have body, not replacing {pattern}"),
+            },
+        }
+    }
+}
+
+/// apply the rules in the given order
+pub(super) fn apply(rules: &[Rule], query: &mut PreparedQuery) {
+    for rule in rules {
+        debug!(?rule, "applying rewrite rule");
+        rule.apply(query);
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum ResponseRule {
+    /// remove the header from response(case insensitive)
+    DropHeader(String),
+    /// replace json fields matching the jsonpath with `***`, non json bodies are not changed
+    Redact(crate::jsonpath::JsonPath),
+    /// keep only the first given bytes of the body
+    TruncateBody(usize),
+}
+
+impl ResponseRule {
+    fn apply(&self, response: &mut Response) {
+        match self {
+            ResponseRule::DropHeader(name) => {
+                response
+                    .headers
+                    .retain(|key, _| !key.eq_ignore_ascii_case(name));
+            }
+            ResponseRule::Redact(path) => {
+                let Ok(mut body) = serde_json::from_slice::<serde_json::Value>(&response.body)
+                else {
+                    warn!("body is not json, not redacting {path}");
+                    return;
+                };
+                path.for_each_mut(&mut body, &mut |value| {
+                    *value = serde_json::Value::String(crate::redact::MASK.to_string())
+                });
+                response.body = serde_json::to_vec(&body)
+                    .expect("json value which was just deserialized should be serializable");
+            }
+            ResponseRule::TruncateBody(length) => response.body.truncate(*length),
+        }
+    }
+}
+
+/// apply the response rules in the given order
+pub(super) fn apply_response(rules: &[ResponseRule], response: &mut Response) {
+    for rule in rules {
+        debug!(?rule, "applying response rewrite rule");
+        rule.apply(response);
+    }
+}
+
+/// regex is (de)serialized as its pattern string
+mod serde_regex {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        regex: &regex::Regex,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(regex.as_str())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<regex::Regex, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        regex::Regex::new(&pattern).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indexmap::IndexMap;
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn query() -> PreparedQuery {
+        PreparedQuery {
+            path: "/users".to_string(),
+            method: "POST".to_string(),
+            headers: IndexMap::from([("X-Debug".to_string(), "0".to_string())]),
+            args: vec![("page".to_string(), "1".to_string())],
+            timeout: super::super::default_timeout(),
+            version: Default::default(),
+            basic_auth: None,
+            bearer_auth: None,
+            body: Some(UnpackedBody::Utf8(r#"{"id": 12, "name": "a"}"#.to_string())),
+            form: None,
+            multipart: None,
+        }
+    }
+
+    #[test]
+    fn rules_in_order() {
+        #[derive(Deserialize)]
+        struct Rules {
+            rewrite: Vec<Rule>,
+        }
+        let Rules { rewrite } = toml::from_str(
+            r#"
+            [[rewrite]]
+            set_header = { name = "x-debug", value = "1" }
+            [[rewrite]]
+            remove_header = "accept"
+            [[rewrite]]
+            add_param = { name = "page", value = "2" }
+            [[rewrite]]
+            replace_body = { pattern = '"id": \d+', with = '"id": 0' }
+            "#,
+        )
+        .unwrap();
+        let mut query = query();
+        query
+            .headers
+            .insert("Accept".to_string(), "*/*".to_string());
+        apply(&rewrite, &mut query);
+        assert_eq!(
+            query.headers,
+            IndexMap::from([("x-debug".to_string(), "1".to_string())])
+        );
+        assert_eq!(
+            query.args,
+            vec![
+                ("page".to_string(), "1".to_string()),
+                ("page".to_string(), "2".to_string())
+            ]
+        );
+        assert!(
+            matches!(query.body, Some(UnpackedBody::Utf8(body)) if body == r#"{"id": 0, "name": "a"}"#)
+        );
+    }
+
+    #[test]
+    fn response_rules() {
+        #[derive(Deserialize)]
+        struct Rules {
+            response_rewrite: Vec<ResponseRule>,
+        }
+        let Rules { response_rewrite } = toml::from_str(
+            r#"
+            [[response_rewrite]]
+            drop_header = "set-cookie"
+            [[response_rewrite]]
+            redact = "$..token"
+            "#,
+        )
+        .unwrap();
+        let mut response = Response {
+            status_code: 200,
+            version: Default::default(),
+            headers: IndexMap::from([
+                ("Set-Cookie".to_string(), "session=1".to_string()),
+                ("Content-Type".to_string(), "application/json".to_string()),
+            ]),
+            store: HashMap::new(),
+            body: br#"{"token":"a","user":{"token":"b","name":"x"}}"#.to_vec(),
+            body_file: None,
+        };
+        apply_response(&response_rewrite, &mut response);
+        assert_eq!(
+            response.headers,
+            IndexMap::from([("Content-Type".to_string(), "application/json".to_string())])
+        );
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&response.body).unwrap(),
+            serde_json::json!({"token": "***", "user": {"token": "***", "name": "x"}})
+        );
+        apply_response(&[ResponseRule::TruncateBody(5)], &mut response);
+        assert_eq!(response.body, br#"{"tok"#);
+    }
+
+    #[test]
+    fn invalid_pattern() {
+        let rule = toml::from_str::<HashMap<String, Rule>>(
+            r#"rule = { replace_body = { pattern = "(", with = "" } }"#,
+        );
+        assert!(rule.is_err());
+    }
+}
@@ -0,0 +1,83 @@
+//! templating engines for bodies which can't be expressed with plain substitution
+
+use std::collections::HashMap;
+
+use miette::{Context, IntoDiagnostic};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Template {
+    /// minijinja template, store values, secrets and providers are available as variables
+    Jinja,
+}
+
+impl Template {
+    /// only the variables used by the template are looked up, so secrets and providers are read only when used
+    pub fn render(self, body: &str, vars: &crate::secret::Variables) -> miette::Result<String> {
+        match self {
+            Template::Jinja => {
+                let mut env = minijinja::Environment::new();
+                env.set_undefined_behavior(minijinja::UndefinedBehavior::Strict);
+                // store values are strings, json values(ex: lists) can be parsed to loop over them
+                env.add_filter("from_json", from_json);
+                let template = env
+                    .template_from_str(body)
+                    .into_diagnostic()
+                    .wrap_err("Couldn't parse jinja template of the body")?;
+                let values: HashMap<_, _> = template
+                    .undeclared_variables(false)
+                    .into_iter()
+                    .filter_map(|name| {
+                        let value = subst::VariableMap::get(vars, &name)?;
+                        Some((name, value))
+                    })
+                    .collect();
+                template
+                    .render(values)
+                    .into_diagnostic()
+                    .wrap_err("Couldn't render jinja template of the body")
+            }
+        }
+    }
+}
+
+fn from_json(value: &str) -> Result<minijinja::Value, minijinja::Error> {
+    serde_json::from_str::<serde_json::Value>(value)
+        .map(minijinja::Value::from_serialize)
+        .map_err(|e| {
+            minijinja::Error::new(minijinja::ErrorKind::InvalidOperation, "invalid json")
+                .with_source(e)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_jinja() {
+        let values = HashMap::from([
+            ("count".to_string(), "3".to_string()),
+            ("users".to_string(), r#"["a", "b"]"#.to_string()),
+        ]);
+        let secrets = HashMap::from([(
+            "token".to_string(),
+            crate::secret::Secret::Command(vec!["echo".to_string(), "s3cret".to_string()]),
+        )]);
+        let providers = HashMap::new();
+        let values = crate::secret::Variables::new(values, &secrets, &providers);
+        let body = r#"{"ids": [{% for i in range(count|int) %}{{ i }}{% if not loop.last %}, {% endif %}{% endfor %}], "users": {{ users|from_json|tojson }}}"#;
+        assert_eq!(
+            Template::Jinja.render(body, &values).unwrap(),
+            r#"{"ids": [0, 1, 2], "users": ["a","b"]}"#
+        );
+        assert_eq!(
+            Template::Jinja
+                .render(r#"{"token": "{{ token }}"}"#, &values)
+                .unwrap(),
+            r#"{"token": "s3cret"}"#
+        );
+        assert!(Template::Jinja.render("{{ missing }}", &values).is_err());
+    }
+}
@@ -1,2 +1,66 @@
 pub mod http;
+pub mod plugin;
 pub mod sql;
+
+use std::future::Future;
+
+/// protocol of the queries in a group
+/// parser lists, searches, merges environments and runs queries of every protocol through this,
+/// a new protocol only needs an implementation and a variant in `parser::GroupContent` and `parser::QuerySearchResult`
+pub trait Agent {
+    type Query: Clone
+        + PartialEq
+        + Eq
+        + std::fmt::Debug
+        + std::fmt::Display
+        + serde::Serialize
+        + serde::de::DeserializeOwned;
+    type Environment: Clone
+        + PartialEq
+        + Eq
+        + std::fmt::Debug
+        + serde::Serialize
+        + serde::de::DeserializeOwned;
+
+    /// columns shown while listing the queries
+    fn query_columns() -> &'static [&'static str];
+    fn query_row(query: &Self::Query) -> Vec<String>;
+    /// columns shown while listing the environments
+    fn environment_columns() -> &'static [&'static str];
+    fn environment_row(environment: &Self::Environment) -> Vec<String>;
+
+    /// hidden queries are not listed but they can still be executed
+    fn is_hidden(query: &Self::Query) -> bool;
+    fn is_deprecated(query: &Self::Query) -> bool;
+    /// whether query contains all the given tags
+    fn has_tags(query: &Self::Query, tags: &[String]) -> bool;
+    /// which field of the query(or the environments) contains the pattern, pattern is in lower case
+    fn matching_field<'e>(
+        query: &Self::Query,
+        pattern: &str,
+        environments: impl Iterator<Item = &'e Self::Environment>,
+    ) -> Option<&'static str>
+    where
+        Self::Environment: 'e;
+    /// report common mistakes of the query, key is the path of the query in its file
+    fn lint(
+        query: &Self::Query,
+        file: Option<&std::path::Path>,
+        key: &[String],
+        lints: &mut crate::lint::Lints,
+    );
+
+    /// fill the missing values of the environment from the same environment of the parent group
+    fn inherit(environment: &mut Self::Environment, parent: &Self::Environment);
+
+    /// run the query in the environment, name is used to record who changed the store values
+    fn execute(
+        query: Self::Query,
+        name: &str,
+        environment: Self::Environment,
+        store: &mut crate::store::Store,
+        args: &crate::Arguments,
+        config: &crate::parser::Config,
+        stdin: Option<&[u8]>,
+    ) -> impl Future<Output = miette::Result<Option<crate::output::QueryResponse>>>;
+}
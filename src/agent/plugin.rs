@@ -0,0 +1,422 @@
+//! queries of other protocols run by `qwicket-agent-<name>` executables found in PATH
+//! plugin is run with the command as its only argument, request is written to stdin as single line of json
+//! and response is read from stdout as json
+//! - `describe`: `{"version": "..", "description": ".."}`
+//! - `environment-schema`: `{"<field>": {"description": "..", "required": true}}`
+//! - `execute`: `{"name", "query", "environment"}` gives `{"status", "content_type", "body", "store"}`
+
+use std::collections::HashMap;
+
+use miette::{Context, IntoDiagnostic};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tracing::{debug, error, trace, warn};
+use yansi::Paint;
+
+/// prefix of the plugin executables, name of the agent follows it
+pub const EXECUTABLE_PREFIX: &str = "qwicket-agent-";
+
+/// agent which runs queries through the plugin named in the query
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Plugin;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Query {
+    /// name of the plugin, `qwicket-agent-<agent>` is run
+    agent: String,
+    description: Option<String>,
+    /// reason/alternative for deprecation, warns when the query is executed
+    deprecated: Option<String>,
+    /// hidden queries are not listed but they can still be executed
+    #[serde(default)]
+    hidden: bool,
+    #[serde(default)]
+    tags: Vec<String>,
+    /// rest of the fields are given to the plugin after substitution
+    #[serde(flatten)]
+    fields: serde_json::Map<String, serde_json::Value>,
+}
+
+/// values are given to the plugin as they are after substitution, missing ones are taken from the parent group
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, Default)]
+pub struct Environment {
+    /// variables read from secret stores while substituting, these are not given to the plugin
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    secrets: HashMap<String, crate::secret::Secret>,
+    #[serde(flatten)]
+    values: serde_json::Map<String, serde_json::Value>,
+}
+
+/// output of `describe`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Description {
+    pub version: Option<String>,
+    pub description: Option<String>,
+}
+
+/// field of the environment as described by `environment-schema`
+#[derive(Debug, Deserialize)]
+struct Field {
+    #[serde(default)]
+    required: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct Request<'r> {
+    name: &'r str,
+    query: &'r serde_json::Map<String, serde_json::Value>,
+    environment: &'r serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Response {
+    status: Option<u16>,
+    content_type: Option<String>,
+    #[serde(default)]
+    body: String,
+    /// values to be set in the store
+    #[serde(default)]
+    store: HashMap<String, String>,
+}
+
+/// plugin executable of the agent, None if it is not in PATH
+pub fn find(agent: &str) -> Option<std::path::PathBuf> {
+    let name = format!("{EXECUTABLE_PREFIX}{agent}");
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|directory| directory.join(&name))
+        .find(|path| path.is_file())
+}
+
+/// names of all the plugins in PATH, sorted and deduplicated
+pub fn discover() -> Vec<String> {
+    let Some(paths) = std::env::var_os("PATH") else {
+        return Vec::new();
+    };
+    let mut agents: Vec<_> = std::env::split_paths(&paths)
+        .filter_map(|directory| std::fs::read_dir(directory).ok())
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.ok()?.file_name().into_string().ok()?;
+            Some(name.strip_prefix(EXECUTABLE_PREFIX)?.to_string())
+        })
+        .filter(|agent| !agent.is_empty())
+        .collect();
+    agents.sort_unstable();
+    agents.dedup();
+    agents
+}
+
+/// run `describe` of the plugin
+pub async fn describe(agent: &str) -> miette::Result<Description> {
+    let output = run(agent, "describe", b"{}\n").await?;
+    serde_json::from_slice(&output)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Invalid description from agent {agent}"))
+}
+
+/// print the plugins in PATH along with their description
+pub async fn print_all() {
+    let mut table = crate::parser::default_table_structure();
+    table.set_header(["agent", "version", "description"]);
+    for agent in discover() {
+        let row = match describe(&agent).await {
+            Ok(description) => [
+                agent,
+                description.version.unwrap_or_default(),
+                description.description.unwrap_or_default(),
+            ],
+            Err(e) => {
+                warn!("{e:?}");
+                [agent, String::new(), "couldn't describe".to_string()]
+            }
+        };
+        table.add_row(row);
+    }
+    eprintln!("{table}");
+}
+
+/// run the plugin with the command and return its stdout
+async fn run(agent: &str, command: &str, input: &[u8]) -> miette::Result<Vec<u8>> {
+    let Some(path) = find(agent) else {
+        miette::bail!(
+            help = format!("add {EXECUTABLE_PREFIX}{agent} to PATH"),
+            "Couldn't find agent {agent}"
+        )
+    };
+    debug!("running {path:?} {command}");
+    let mut child = tokio::process::Command::new(&path)
+        .arg(command)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Couldn't run agent {agent}"))?;
+    child
+        .stdin
+        .take()
+        .expect("stdin of agent is piped")
+        .write_all(input)
+        .await
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to send request to agent {agent}"))?;
+    let output = child
+        .wait_with_output()
+        .await
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to read output of agent {agent}"))?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !stderr.is_empty() {
+        error!("agent {agent} stderr: `{stderr}`");
+    }
+    if !output.status.success() {
+        miette::bail!(
+            "agent {agent} {command} exited with error: {}",
+            output.status
+        )
+    }
+    Ok(output.stdout)
+}
+
+/// substitute variables in all the strings of the value
+fn substitute(
+    value: &mut serde_json::Value,
+    vars: &crate::secret::Variables,
+) -> Result<(), subst::Error> {
+    match value {
+        serde_json::Value::String(text) => *text = crate::variable::substitute(text, vars)?,
+        serde_json::Value::Array(values) => {
+            for value in values {
+                substitute(value, vars)?;
+            }
+        }
+        serde_json::Value::Object(values) => {
+            for value in values.values_mut() {
+                substitute(value, vars)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+impl std::fmt::Display for Query {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        static KEY_STYLE: yansi::Style = yansi::Color::Yellow.bold();
+        if let Some(description) = &self.description {
+            writeln!(f, "{}: {description}", "description".paint(KEY_STYLE))?;
+        }
+        if let Some(reason) = &self.deprecated {
+            writeln!(f, "{}: {}", "deprecated".paint(KEY_STYLE), reason.red())?;
+        }
+        writeln!(f, "{}: {}", "agent".paint(KEY_STYLE), self.agent)?;
+        for (key, value) in &self.fields {
+            writeln!(f, "{}: {value}", key.paint(KEY_STYLE))?;
+        }
+        Ok(())
+    }
+}
+
+impl super::Agent for Plugin {
+    type Query = Query;
+    type Environment = Environment;
+
+    fn query_columns() -> &'static [&'static str] {
+        &["agent"]
+    }
+
+    fn query_row(query: &Query) -> Vec<String> {
+        vec![query.agent.clone()]
+    }
+
+    fn environment_columns() -> &'static [&'static str] {
+        &["values"]
+    }
+
+    fn environment_row(environment: &Environment) -> Vec<String> {
+        vec![serde_json::Value::Object(environment.values.clone()).to_string()]
+    }
+
+    fn is_hidden(query: &Query) -> bool {
+        query.hidden
+    }
+
+    fn is_deprecated(query: &Query) -> bool {
+        query.deprecated.is_some()
+    }
+
+    fn has_tags(query: &Query, tags: &[String]) -> bool {
+        tags.iter().all(|tag| query.tags.contains(tag))
+    }
+
+    fn matching_field<'e>(
+        query: &Query,
+        pattern: &str,
+        _environments: impl Iterator<Item = &'e Environment>,
+    ) -> Option<&'static str> {
+        if query
+            .description
+            .as_ref()
+            .is_some_and(|description| description.to_lowercase().contains(pattern))
+        {
+            Some("description")
+        } else if query.agent.to_lowercase().contains(pattern) {
+            Some("agent")
+        } else {
+            None
+        }
+    }
+
+    fn lint(
+        query: &Query,
+        file: Option<&std::path::Path>,
+        key: &[String],
+        lints: &mut crate::lint::Lints,
+    ) {
+        if find(&query.agent).is_none() {
+            lints.add(
+                file,
+                key.to_vec(),
+                format!("agent {} is not installed", query.agent),
+                format!("add {EXECUTABLE_PREFIX}{} to PATH", query.agent),
+                None,
+            );
+        }
+    }
+
+    fn inherit(environment: &mut Environment, parent: &Environment) {
+        for (key, value) in &parent.values {
+            environment
+                .values
+                .entry(key.clone())
+                .or_insert_with(|| value.clone());
+        }
+        for (name, secret) in &parent.secrets {
+            environment
+                .secrets
+                .entry(name.clone())
+                .or_insert_with(|| secret.clone());
+        }
+    }
+
+    async fn execute(
+        mut query: Query,
+        name: &str,
+        mut environment: Environment,
+        store: &mut crate::store::Store,
+        args: &crate::Arguments,
+        config: &crate::parser::Config,
+        stdin: Option<&[u8]>,
+    ) -> miette::Result<Option<crate::output::QueryResponse>> {
+        if let Some(reason) = &query.deprecated {
+            warn!("query is deprecated: {reason}");
+        }
+        if stdin.is_some() {
+            warn!(
+                "stdin is not supported by agent {}, ignoring it",
+                query.agent
+            );
+        }
+        let schema = run(&query.agent, "environment-schema", b"{}\n").await?;
+        let schema: HashMap<String, Field> = serde_json::from_slice(&schema)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Invalid environment schema from agent {}", query.agent))?;
+        let mut missing: Vec<_> = schema
+            .iter()
+            .filter(|(key, field)| field.required && !environment.values.contains_key(*key))
+            .map(|(key, _)| key.as_str())
+            .collect();
+        if !missing.is_empty() {
+            missing.sort_unstable();
+            miette::bail!(
+                help = format!(
+                    "add them to the environment, see `{EXECUTABLE_PREFIX}{} environment-schema`",
+                    query.agent
+                ),
+                "environment is missing {missing:?} required by agent {}",
+                query.agent
+            )
+        }
+
+        let mut local_store = std::ops::Deref::deref(store).clone();
+        local_store.extend(args.vars.iter().cloned());
+        let vars =
+            crate::secret::Variables::new(local_store, &environment.secrets, &config.provider)
+                .with_expiry(store.expiry());
+        for value in query
+            .fields
+            .values_mut()
+            .chain(environment.values.values_mut())
+        {
+            substitute(value, &vars)
+                .into_diagnostic()
+                .wrap_err("Couldn't substitute query")?;
+        }
+        let mut request = serde_json::to_vec(&Request {
+            name,
+            query: &query.fields,
+            environment: &environment.values,
+        })
+        .into_diagnostic()
+        .wrap_err("Couldn't serialize request for agent")?;
+        request.push(b'\n');
+        if args.dry_run {
+            eprintln!("{}", String::from_utf8_lossy(&request).trim_end());
+            return Ok(None);
+        }
+
+        let output = run(&query.agent, "execute", &request).await?;
+        let response: Response = serde_json::from_slice(&output)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Invalid response from agent {}", query.agent))?;
+        trace!("agent response: {response:?}");
+        store.extend(response.store);
+        Ok(Some(crate::output::QueryResponse {
+            status: response.status,
+            content_type: response.content_type,
+            body: response.body.into_bytes(),
+            output: args.output.clone(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::Agent;
+
+    #[test]
+    fn environment_inherits_missing_values() {
+        let mut environment: Environment = toml::from_str(r#"host = "db.local""#).unwrap();
+        let parent: Environment = toml::from_str("host = \"db.prod\"\nport = 5432").unwrap();
+        Plugin::inherit(&mut environment, &parent);
+        assert_eq!(environment.values["host"], "db.local");
+        assert_eq!(environment.values["port"], 5432);
+
+        let query: Query =
+            toml::from_str("agent = \"sql\"\nstatement = \"select 1\"\ntags = [\"db\"]").unwrap();
+        assert_eq!(query.fields.keys().collect::<Vec<_>>(), ["statement"]);
+        assert!(Plugin::has_tags(&query, &["db".to_string()]));
+    }
+
+    #[test]
+    fn secrets_are_not_values() {
+        let mut environment: Environment = toml::from_str(
+            "dsn = \"postgres://app:${password}@db\"\n[secrets]\npassword = { command = [\"echo\", \"hunter2\"] }",
+        )
+        .unwrap();
+        let parent: Environment =
+            toml::from_str("[secrets]\ntoken = { op = \"op://dev/db/token\" }").unwrap();
+        Plugin::inherit(&mut environment, &parent);
+        assert_eq!(environment.values.keys().collect::<Vec<_>>(), ["dsn"]);
+        let mut secrets: Vec<_> = environment.secrets.keys().collect();
+        secrets.sort_unstable();
+        assert_eq!(secrets, ["password", "token"]);
+
+        let (store, providers) = (HashMap::new(), HashMap::new());
+        let vars = crate::secret::Variables::new(store, &environment.secrets, &providers);
+        let mut dsn = environment.values["dsn"].clone();
+        substitute(&mut dsn, &vars).unwrap();
+        assert_eq!(dsn, "postgres://app:hunter2@db");
+    }
+}
@@ -0,0 +1,144 @@
+//! keeps a copy of every response of the archived queries, to answer what an api returned earlier
+//! responses are saved in `<store file>.archive/<query>/<timestamp>/` as `meta.json` and `body`
+
+use std::path::Path;
+
+use indexmap::IndexMap;
+use miette::{Context, IntoDiagnostic};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, trace, warn};
+
+/// project level archive settings
+#[derive(Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    /// archive responses of all the queries
+    pub enabled: Option<bool>,
+    /// archived responses older than this are removed, ex: "30days", kept forever if not set
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub retention: Option<std::time::Duration>,
+}
+
+/// details of the request and response saved along with the body
+#[derive(Debug, Serialize)]
+pub struct Meta<'a> {
+    /// rfc3339 timestamp of the response
+    pub time: String,
+    pub query: &'a str,
+    pub env: &'a str,
+    pub method: &'a str,
+    pub url: &'a str,
+    pub status: u16,
+    pub headers: &'a IndexMap<String, String>,
+}
+
+/// most specific setting is used, i.e. query, environment and then project, disabled if none of them are set
+pub fn is_enabled(query: Option<bool>, environment: Option<bool>, project: &Settings) -> bool {
+    query.or(environment).or(project.enabled).unwrap_or(false)
+}
+
+/// save the response in the archive directory of the query and remove the ones older than retention
+pub fn save(
+    directory: &Path,
+    meta: &Meta,
+    body: &[u8],
+    retention: Option<std::time::Duration>,
+) -> miette::Result<()> {
+    let query_directory = directory.join(meta.query);
+    // `:` is not allowed in file names on some platforms
+    let entry = query_directory.join(meta.time.replace(':', "-"));
+    debug!("archiving response to {entry:?}");
+    std::fs::create_dir_all(&entry)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Couldn't create archive directory {entry:?}"))?;
+    let meta_json = serde_json::to_vec_pretty(meta)
+        .into_diagnostic()
+        .wrap_err("Couldn't serialize archive metadata")?;
+    std::fs::write(entry.join("meta.json"), meta_json)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Couldn't write archive metadata to {entry:?}"))?;
+    std::fs::write(entry.join("body"), body)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Couldn't write archived body to {entry:?}"))?;
+    if let Some(retention) = retention {
+        remove_expired(&query_directory, retention);
+    }
+    Ok(())
+}
+
+/// remove archived responses which are older than retention, failures are only logged
+fn remove_expired(query_directory: &Path, retention: std::time::Duration) {
+    let Ok(entries) = std::fs::read_dir(query_directory) else {
+        return;
+    };
+    let now = std::time::SystemTime::now();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let expired = entry
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .is_ok_and(|modified| now.duration_since(modified).unwrap_or_default() > retention);
+        if expired {
+            trace!("removing expired archive {path:?}");
+            if let Err(e) = std::fs::remove_dir_all(&path) {
+                warn!("Couldn't remove expired archive {path:?}: {e}");
+            }
+        }
+    }
+}
+
+/// human readable duration, ex: "1s 500ms"
+pub(crate) fn deserialize_duration<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<std::time::Duration>, D::Error> {
+    let duration = String::deserialize(deserializer)?;
+    humantime::parse_duration(&duration)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+/// duration in the form read by `deserialize_duration`
+pub(crate) fn serialize_duration<S: serde::Serializer>(
+    duration: &Option<std::time::Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match duration {
+        Some(duration) => {
+            serializer.serialize_str(&humantime::format_duration(*duration).to_string())
+        }
+        None => serializer.serialize_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_and_expire() {
+        let directory =
+            std::env::temp_dir().join(format!("qwicket-archive-{}", std::process::id()));
+        let headers = IndexMap::new();
+        let meta = |time: &str| Meta {
+            time: time.to_string(),
+            query: "users.list",
+            env: "dev",
+            method: "GET",
+            url: "http://localhost/users",
+            status: 200,
+            headers: &headers,
+        };
+        save(&directory, &meta("2024-01-01T00:00:00Z"), b"old", None).unwrap();
+        let old = directory.join("users.list/2024-01-01T00-00-00Z");
+        assert_eq!(std::fs::read(old.join("body")).unwrap(), b"old");
+
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        let retention = Some(std::time::Duration::from_millis(10));
+        save(&directory, &meta("2024-01-02T00:00:00Z"), b"new", retention).unwrap();
+        assert!(!old.exists());
+        assert!(directory
+            .join("users.list/2024-01-02T00-00-00Z/meta.json")
+            .exists());
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
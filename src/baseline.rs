@@ -0,0 +1,192 @@
+//! rolling latency baselines of the queries per environment, to notice when an api gets slower than it used to be
+//! recent latencies are kept in `<store file>.baseline` as json, baseline is their median
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use miette::{Context, IntoDiagnostic};
+use tracing::{debug, warn};
+
+/// number of recent latencies kept for each query
+const SAMPLES: usize = 20;
+/// baseline isn't used until these many latencies are recorded
+const MIN_SAMPLES: usize = 3;
+
+/// baselines file is read and written back by every record, environments of `--race` record at the same time
+static LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// latencies in milliseconds by environment and query, oldest first
+type Baselines = BTreeMap<String, BTreeMap<String, Vec<u64>>>;
+
+fn load(path: &Path) -> miette::Result<Baselines> {
+    match std::fs::read(path) {
+        Ok(content) => serde_json::from_slice(&content)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Couldn't parse baselines, try after removing {path:?}")),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Baselines::new()),
+        Err(e) => Err(e)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Couldn't read {path:?}")),
+    }
+}
+
+fn median(samples: &[u64]) -> Option<u64> {
+    if samples.len() < MIN_SAMPLES {
+        return None;
+    }
+    let mut samples = samples.to_vec();
+    samples.sort_unstable();
+    Some(samples[samples.len() / 2])
+}
+
+/// latency which is slower than the baseline by more than allowed
+#[derive(Debug, PartialEq)]
+pub struct Regression {
+    baseline_ms: u64,
+    latency_ms: u64,
+    /// fraction by which the latency is slower
+    slower: f64,
+}
+
+impl std::fmt::Display for Regression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "took {} ms, {:.0}% slower than the baseline of {} ms",
+            self.latency_ms,
+            self.slower * 100.0,
+            self.baseline_ms
+        )
+    }
+}
+
+/// when the latency is slower than the baseline by more than allowed by the guard
+fn regression(baseline_ms: u64, latency_ms: u64, guard: f64) -> Option<Regression> {
+    let slower = (latency_ms as f64 - baseline_ms as f64) / baseline_ms.max(1) as f64;
+    (slower > guard).then_some(Regression {
+        baseline_ms,
+        latency_ms,
+        slower,
+    })
+}
+
+/// compare the latency with the baseline of the query and add it to the baseline
+/// returns the regression when the latency is beyond the guard, failures to read or write baselines only warn
+pub async fn record(
+    path: PathBuf,
+    env: String,
+    query: String,
+    latency: Duration,
+    guard: Option<f64>,
+) -> Option<Regression> {
+    let _lock = LOCK.lock().await;
+    tokio::task::spawn_blocking(move || update(&path, &env, &query, latency, guard))
+        .await
+        .unwrap_or_else(|e| {
+            warn!("Couldn't record latency baseline: {e}");
+            None
+        })
+}
+
+fn update(
+    path: &Path,
+    env: &str,
+    query: &str,
+    latency: Duration,
+    guard: Option<f64>,
+) -> Option<Regression> {
+    let mut baselines = match load(path) {
+        Ok(baselines) => baselines,
+        Err(e) => {
+            warn!("{e:?}");
+            return None;
+        }
+    };
+    let latency = latency.as_millis() as u64;
+    let samples = baselines
+        .entry(env.to_string())
+        .or_default()
+        .entry(query.to_string())
+        .or_default();
+    let baseline = median(samples);
+    debug!("latency baseline of {query}: {baseline:?} ms");
+    let regression = baseline
+        .zip(guard)
+        .and_then(|(baseline, guard)| regression(baseline, latency, guard));
+    samples.push(latency);
+    if samples.len() > SAMPLES {
+        samples.drain(..samples.len() - SAMPLES);
+    }
+    let written = serde_json::to_vec(&baselines)
+        .into_diagnostic()
+        .and_then(|content| std::fs::write(path, content).into_diagnostic())
+        .wrap_err_with(|| format!("Couldn't write baselines to {path:?}"));
+    if let Err(e) = written {
+        warn!("{e:?}");
+    }
+    regression
+}
+
+/// percentage like `50%`(or `50`) as fraction
+pub fn parse_percent(percent: &str) -> Result<f64, String> {
+    let value: f64 = percent
+        .strip_suffix('%')
+        .unwrap_or(percent)
+        .trim()
+        .parse()
+        .map_err(|e| format!("invalid percentage {percent}: {e}"))?;
+    if value.is_sign_negative() {
+        return Err(format!("percentage should be positive, found {percent}"));
+    }
+    Ok(value / 100.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn regression_from_baseline() {
+        assert_eq!(parse_percent("50%"), Ok(0.5));
+        assert_eq!(parse_percent("150"), Ok(1.5));
+        assert!(parse_percent("-5%").is_err());
+
+        let path = std::env::temp_dir().join(format!("qwicket-baseline-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let ms = Duration::from_millis;
+        let guard = Some(0.5);
+        let record = |env: &str, latency, guard| {
+            record(
+                path.clone(),
+                env.to_string(),
+                "users.get".to_string(),
+                latency,
+                guard,
+            )
+        };
+        // not enough latencies for a baseline yet
+        assert_eq!(record("dev", ms(100), guard).await, None);
+        assert_eq!(record("dev", ms(400), guard).await, None);
+        assert_eq!(record("dev", ms(100), guard).await, None);
+        assert_eq!(record("dev", ms(120), guard).await, None);
+        let regression = record("dev", ms(240), guard).await.unwrap();
+        assert_eq!(regression.slower, 1.0);
+        assert_eq!(
+            regression.to_string(),
+            "took 240 ms, 100% slower than the baseline of 120 ms"
+        );
+        // other environments have their own baselines
+        assert_eq!(record("prod", ms(900), guard).await, None);
+        assert_eq!(record("dev", ms(900), None).await, None);
+        // racing environments don't lose each other's latencies
+        futures_util::future::join_all(["dev", "prod", "qa"].map(|env| record(env, ms(100), None)))
+            .await;
+
+        let baselines = load(&path).unwrap();
+        assert_eq!(baselines["dev"]["users.get"].len(), 7);
+        assert_eq!(baselines["prod"]["users.get"].len(), 2);
+        assert_eq!(baselines["qa"]["users.get"].len(), 1);
+        std::fs::remove_file(path).unwrap();
+    }
+}
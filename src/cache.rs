@@ -0,0 +1,127 @@
+//! values the queries keep for themselves(idempotency keys, validators, expiry of store values) in files next to the store file
+//! these are not part of the store, so they are not listed, returned by `--get` or substituted
+
+use std::path::PathBuf;
+
+use indexmap::IndexMap;
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::{trace, warn};
+
+/// entries by environment
+type Entries<T> = IndexMap<String, IndexMap<String, T>>;
+
+/// entries of one environment, oldest first
+#[derive(Debug, Clone)]
+pub struct Cache<T> {
+    path: PathBuf,
+    env: String,
+    entries: IndexMap<String, T>,
+    /// oldest entries are removed beyond this
+    limit: Option<usize>,
+    /// whether entries are changed since opened or saved, unchanged entries are not written
+    changed: bool,
+}
+
+fn read<T: DeserializeOwned>(path: &std::path::Path) -> Entries<T> {
+    match std::fs::read(path) {
+        Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|e| {
+            warn!("Couldn't parse {path:?}, ignoring it: {e}");
+            Entries::new()
+        }),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Entries::new(),
+        Err(e) => {
+            warn!("Couldn't read {path:?}: {e}");
+            Entries::new()
+        }
+    }
+}
+
+impl<T: Serialize + DeserializeOwned + Clone> Cache<T> {
+    /// missing or unreadable file is an empty cache
+    pub fn open(path: PathBuf, env: &str) -> Self {
+        let entries = read(&path).shift_remove(env).unwrap_or_default();
+        Self {
+            path,
+            env: env.to_string(),
+            entries,
+            limit: None,
+            changed: false,
+        }
+    }
+
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn get(&self, key: &str) -> Option<&T> {
+        self.entries.get(key)
+    }
+
+    /// inserted or updated entry becomes the newest
+    pub fn insert(&mut self, key: String, value: T) {
+        self.changed = true;
+        self.entries.shift_remove(&key);
+        self.entries.insert(key, value);
+        if let Some(limit) = self.limit {
+            let excess = self.entries.len().saturating_sub(limit);
+            self.entries.drain(..excess);
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<T> {
+        let removed = self.entries.shift_remove(key);
+        self.changed |= removed.is_some();
+        removed
+    }
+
+    /// write the entries of the environment back if changed, entries of other environments are kept
+    pub fn save(&mut self) {
+        if !self.changed {
+            return;
+        }
+        self.changed = false;
+        trace!("writing {:?}", self.path);
+        let mut entries = read::<T>(&self.path);
+        if self.entries.is_empty() {
+            entries.shift_remove(&self.env);
+        } else {
+            entries.insert(self.env.clone(), self.entries.clone());
+        }
+        let written = serde_json::to_vec(&entries)
+            .map_err(std::io::Error::from)
+            .and_then(|content| std::fs::write(&self.path, content));
+        if let Err(e) = written {
+            warn!("Couldn't write {:?}: {e}", self.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn environments_and_limit() {
+        let path = std::env::temp_dir().join(format!("qwicket-cache-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        // unchanged entries are not written
+        Cache::<i32>::open(path.clone(), "dev").save();
+        assert!(!path.exists());
+        let mut dev = Cache::open(path.clone(), "dev").with_limit(2);
+        for (key, value) in [("a", 1), ("b", 2), ("a", 3), ("c", 4)] {
+            dev.insert(key.to_string(), value);
+        }
+        dev.save();
+        let mut prod = Cache::open(path.clone(), "prod");
+        prod.insert("a".to_string(), 5);
+        prod.save();
+
+        let dev = Cache::<i32>::open(path.clone(), "dev");
+        assert_eq!(dev.get("b"), None);
+        assert_eq!(dev.get("a"), Some(&3));
+        assert_eq!(dev.get("c"), Some(&4));
+        assert_eq!(Cache::<i32>::open(path.clone(), "prod").get("a"), Some(&5));
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,2 +1,5 @@
 pub const KEY_CURRENT_ENVIRONMENT: &str = "NEST";
+pub const KEY_WORKSPACE: &str = "QWICKET_WORKSPACE";
 pub const GROUP_FILE_NAME: &str = "index.toml";
+pub const PATH_SEPARATOR: &str = ".";
+pub const DEFAULT_ENVIRONMENT: &str = "default";
@@ -0,0 +1,117 @@
+//! where the effective values of the query come from, printed with `--explain`
+
+use std::collections::BTreeMap;
+
+use miette::{Context, IntoDiagnostic};
+
+/// effective value and its origin, keyed by kind and name of the setting
+#[derive(Debug, Default)]
+pub struct Explain {
+    values: BTreeMap<(&'static str, String), (String, String)>,
+}
+
+impl Explain {
+    /// value from this origin overrides the existing one
+    pub fn set(&mut self, kind: &'static str, name: &str, value: String, origin: &str) {
+        self.values
+            .insert((kind, name.to_string()), (value, origin.to_string()));
+    }
+
+    /// value from this origin is used only if there is none yet
+    pub fn fill(&mut self, kind: &'static str, name: &str, value: String, origin: &str) {
+        self.values
+            .entry((kind, name.to_string()))
+            .or_insert_with(|| (value, origin.to_string()));
+    }
+
+    /// check if the setting has a value, names are compared case insensitively
+    pub fn contains(&self, kind: &str, name: &str) -> bool {
+        self.values
+            .keys()
+            .any(|(k, n)| *k == kind && n.eq_ignore_ascii_case(name))
+    }
+
+    pub fn print(&self, redact: &crate::redact::Redact) {
+        let mut table = crate::parser::default_table_structure();
+        table.set_header(["kind", "name", "value", "from"]);
+        for ((kind, name), (value, origin)) in &self.values {
+            table.add_row([*kind, name, redact.value(name, value), origin]);
+        }
+        println!("{table}");
+    }
+}
+
+/// merge the layers of the query the same way they are merged while executing it
+pub fn query(
+    groups: &crate::parser::Group,
+    args: &crate::Arguments,
+    env: &str,
+    store: &crate::store::Store,
+    config: &crate::parser::Config,
+) -> miette::Result<Explain> {
+    let Some((_, group_path)) = args.endpoint.split_last() else {
+        miette::bail!("Couldn't find query")
+    };
+    let Some(crate::parser::QuerySearchResult::Http(found)) =
+        groups.find(&args.endpoint).and_then(|result| result.query)
+    else {
+        miette::bail!(
+            "{} is not a query",
+            args.endpoint.join(crate::constants::PATH_SEPARATOR)
+        )
+    };
+    let query = found.query;
+    let layers = groups.environment_layers(group_path, env);
+
+    let mut explain = Explain::default();
+    // store also contains process environment, so only the values used by the query are shown
+    let query_text = serde_json::to_string(&query)
+        .into_diagnostic()
+        .wrap_err("Couldn't serialize query")?;
+    for (key, value) in store.iter() {
+        if crate::variable::is_referenced(&query_text, key) {
+            explain.set("store", key, value.clone(), &format!("store [{env}]"));
+        }
+    }
+    // innermost group comes first, parent groups only fill the missing values
+    let mut inherited = false;
+    for layer in &layers {
+        if let Some((name, environment)) = &layer.environment {
+            let origin = format!("{} [{name}]", layer.source);
+            environment.explain(&origin, inherited, &mut explain);
+            inherited = true;
+        }
+    }
+    let query_source = layers
+        .first()
+        .map(|layer| layer.source.as_str())
+        .unwrap_or("query");
+    query.explain(query_source, config, &mut explain);
+    for (key, value) in &args.vars {
+        explain.set("store", key, value.clone(), "--var");
+    }
+    Ok(explain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_overrides_fill_keeps() {
+        let mut explain = Explain::default();
+        explain.fill("env", "host", "inner".to_string(), "inner.toml");
+        explain.fill("env", "host", "outer".to_string(), "outer.toml");
+        explain.set("header", "Accept", "text/plain".to_string(), "inner.toml");
+        explain.set("header", "Accept", "*/*".to_string(), "outer.toml");
+        assert!(explain.contains("header", "accept"));
+        assert_eq!(
+            explain.values[&("env", "host".to_string())],
+            ("inner".to_string(), "inner.toml".to_string())
+        );
+        assert_eq!(
+            explain.values[&("header", "Accept".to_string())],
+            ("*/*".to_string(), "outer.toml".to_string())
+        );
+    }
+}
@@ -1,81 +1,437 @@
+use crate::workspace::Workspace;
 use miette::{Context, IntoDiagnostic};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::{borrow::Borrow, io::Write, os::unix::process::ExitStatusExt};
 use tracing::{debug, error, instrument, trace};
 
+mod builtin;
+
 // TODO: add Hook executor which takes arguments like executor which executes given script
 #[derive(Debug, Deserialize, Clone, Serialize)]
 #[serde(deny_unknown_fields)]
 #[serde(rename_all = "snake_case")]
-pub enum Hook {
+pub enum Kind {
     Closure(String),
     #[serde(rename = "script")]
     Path(std::path::PathBuf),
+    /// javascript/typescript module exporting `transform(input, args)`, run with deno
+    Js(std::path::PathBuf),
+    /// inline command, same as script but doesn't need a file
+    Command(Command),
+    /// implemented in qwicket, can also be written as `"builtin:<name>"`
+    Builtin(builtin::Builtin),
+}
+
+/// shell string is run with `sh -c`, hook arguments are given to it as `$1`, `$2`...
+#[derive(Debug, Deserialize, Clone, Serialize)]
+#[serde(untagged)]
+pub enum Command {
+    Argv(Vec<String>),
+    Shell(String),
+}
+
+impl Command {
+    fn build(&self, args: &[impl Borrow<str>]) -> miette::Result<std::process::Command> {
+        let mut command = match self {
+            Command::Argv(argv) => {
+                let Some((program, program_args)) = argv.split_first() else {
+                    miette::bail!("command of the hook is empty")
+                };
+                let mut command = std::process::Command::new(program);
+                command.args(program_args);
+                command
+            }
+            Command::Shell(script) => {
+                let mut command = std::process::Command::new("sh");
+                command.args(["-c", script, "sh"]);
+                command
+            }
+        };
+        command.args(args.iter().map(|arg| arg.borrow()));
+        Ok(command)
+    }
+}
+
+/// loads the js hook module and exchanges json with it over stdio
+const JS_RUNNER: &str = r#"
+const [moduleUrl, ...args] = Deno.args;
+const { transform } = await import(moduleUrl);
+const input = JSON.parse(await new Response(Deno.stdin.readable).text());
+const output = (await transform(input, args)) ?? input;
+await Deno.stdout.write(new TextEncoder().encode(JSON.stringify(output)));
+"#;
+
+/// serialization used to exchange data with script hooks
+#[derive(Debug, Deserialize, Clone, Copy, Serialize, Default, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    #[default]
+    Msgpack,
+    /// single line of json, utf8 body is given as string
+    Json,
+}
+
+/// hook of a query, can be disabled without removing it
+#[derive(Debug, Deserialize, Clone, Serialize)]
+#[serde(remote = "Self")]
+pub struct Hook {
+    #[serde(flatten)]
+    kind: Kind,
+    /// takes priority over environment and project hooks setting
+    enabled: Option<bool>,
+    /// format of script hooks, js hooks always use json
+    #[serde(default)]
+    format: Format,
+}
+
+/// `"builtin:<name>"` is short for `{ builtin = "<name>" }`
+impl<'de> Deserialize<'de> for Hook {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match toml::Value::deserialize(deserializer)? {
+            toml::Value::String(hook) => {
+                let Some(name) = hook.strip_prefix("builtin:") else {
+                    return Err(serde::de::Error::custom(format!(
+                        "hook should be a table or builtin:<name>, found {hook}"
+                    )));
+                };
+                Ok(Self {
+                    kind: Kind::Builtin(name.parse().map_err(serde::de::Error::custom)?),
+                    enabled: None,
+                    format: Format::default(),
+                })
+            }
+            hook => Hook::deserialize(hook).map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+impl Serialize for Hook {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Hook::serialize(self, serializer)
+    }
+}
+
+/// languages for which hook scaffold can be generated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Language {
+    #[default]
+    Python,
+    Js,
+}
+
+/// write a hook template of given language to the file
+pub fn scaffold(language: Language, path: &std::path::Path) -> miette::Result<()> {
+    let (template, format_hint) = match language {
+        Language::Python => (
+            include_str!("../example-hooks/scaffold/hook.py"),
+            format!("pre_hook = {{ script = {path:?}, format = \"json\" }}"),
+        ),
+        Language::Js => (
+            include_str!("../example-hooks/scaffold/hook.ts"),
+            format!("pre_hook = {{ js = {path:?} }}"),
+        ),
+    };
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Couldn't create {path:?}"))?;
+    file.write_all(template.as_bytes())
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Couldn't write to {path:?}"))?;
+    if language == Language::Python {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o755))
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Couldn't make {path:?} executable"))?;
+    }
+    eprintln!("created {path:?}, use it in query as `{format_hint}`");
+    Ok(())
+}
+
+/// hooks setting of project or environment
+#[derive(Debug, Deserialize, Clone, Serialize, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    pub enabled: Option<bool>,
 }
 
 impl Hook {
+    /// enabled hook which runs given script, `.js`/`.ts` scripts are run as js hooks
+    pub fn script(path: std::path::PathBuf, format: Format) -> Self {
+        let is_js = path
+            .extension()
+            .is_some_and(|extension| extension == "js" || extension == "ts");
+        Self {
+            kind: if is_js {
+                Kind::Js(path)
+            } else {
+                Kind::Path(path)
+            },
+            enabled: None,
+            format,
+        }
+    }
+
+    /// most specific setting is used, i.e. query hook, environment and then project, enabled if none of them are set
+    pub fn is_enabled(&self, environment: &Settings, project: &Settings) -> bool {
+        self.enabled
+            .or(environment.enabled)
+            .or(project.enabled)
+            .unwrap_or(true)
+    }
+
     #[instrument(skip(input, args))]
     pub fn run<T: Serialize + DeserializeOwned>(
         &self,
         input: &T,
         args: &[impl Borrow<str>],
+        workspace: &std::path::Path,
     ) -> miette::Result<T> {
         trace!("running Hook");
-        // size will always be larger than obj, but atleast optimize is for single allocation
-        let body_buf = to_msgpack(&input)
-            .into_diagnostic()
-            .wrap_err("serializing input body")?;
-        match self {
-            Hook::Closure(_cl) => unimplemented!("Currently closures are not supported"),
-            Hook::Path(path) => {
+        match &self.kind {
+            Kind::Closure(_cl) => unimplemented!("Currently closures are not supported"),
+            Kind::Path(path) => {
                 debug!("Executing hook: {path:?}");
-                // setup child to take stdin and return both stdout and stdin
-                let mut child = std::process::Command::new(path)
-                    .stdin(std::process::Stdio::piped())
-                    .stdout(std::process::Stdio::piped())
-                    .stderr(std::process::Stdio::piped())
-                    .args(args.iter().map(|arg| arg.borrow()))
-                    .spawn()
+                let mut command = std::process::Command::new(path);
+                command.args(args.iter().map(|arg| arg.borrow()));
+                Workspace::configure(workspace, &mut command);
+                run_formatted(command, self.format, input)
+            }
+            Kind::Command(command) => {
+                debug!("Executing command hook: {command:?}");
+                let mut command = command.build(args)?;
+                Workspace::configure(workspace, &mut command);
+                run_formatted(command, self.format, input)
+            }
+            Kind::Builtin(builtin) => {
+                debug!("Executing builtin hook: {builtin}");
+                let mut value = serde_json::to_value(input)
                     .into_diagnostic()
-                    .wrap_err("Couldn't run hook")?;
+                    .wrap_err("serializing input body")?;
+                builtin
+                    .apply(&mut value)
+                    .wrap_err_with(|| format!("builtin hook {builtin} failed"))?;
+                serde_json::from_value(value)
+                    .into_diagnostic()
+                    .wrap_err("Failed to deserialize output of hooks")
+            }
+            Kind::Js(path) => {
+                debug!("Executing js hook: {path:?}");
+                let module = std::fs::canonicalize(path)
+                    .ok()
+                    .and_then(|path| url::Url::from_file_path(path).ok())
+                    .ok_or_else(|| miette::miette!("Couldn't find js hook {path:?}"))?;
+                let mut command = std::process::Command::new("deno");
+                command
+                    .args(["eval", "--quiet", JS_RUNNER])
+                    .arg(module.as_str())
+                    .args(args.iter().map(|arg| arg.borrow()));
+                Workspace::configure(workspace, &mut command);
+                run_json(command, input)
+            }
+        }
+    }
+}
 
-                debug!("writing to child: {body_buf:x?}");
+/// version of the request/response given to the hooks, bumped whenever their structure changes
+/// output of the previous version is still accepted
+pub const SCHEMA_VERSION: u32 = 2;
 
-                // execute child with input
-                child
-                    .stdin
-                    .take()
-                    .expect("Childs stdin is not open, eventhough body is present")
-                    .write_all(&body_buf)
-                    .into_diagnostic()
-                    .wrap_err("Failed to send body to hook")?;
+/// json schema of the request/response of the current version
+pub const SCHEMA: &str = include_str!("../example-hooks/schema.json");
 
-                // collect child output
-                let output = child
-                    .wait_with_output()
-                    .into_diagnostic()
-                    .wrap_err("Failed to read hook output")?;
-                debug!("pre-hook output: {:x?}", output.stdout);
-
-                // assuming stderr to be utf-8
-                let child_stderr = String::from_utf8_lossy(&output.stderr);
-
-                if !child_stderr.is_empty() {
-                    error!("pre-hook stderr: `{}`", child_stderr);
-                }
-                // check if the execution is success or not
-                if !output.status.success() {
-                    let code =
-                        std::process::ExitStatus::from_raw(output.status.code().unwrap_or(1));
-                    miette::bail!("hook exited with error: {code}")
-                }
-
-                // deserialize output and read from stdout
-                let pre_hook_obj: T = rmp_serde::from_slice(output.stdout.as_ref())
-                    .into_diagnostic()
-                    .wrap_err("Failed to deserialize output of hooks")?;
+/// request/response given to the hooks along with the schema version
+#[derive(Debug, Serialize)]
+pub struct Versioned<'t, T> {
+    schema_version: u32,
+    #[serde(flatten)]
+    payload: &'t T,
+}
+
+pub fn versioned<T: Serialize>(payload: &T) -> Versioned<'_, T> {
+    Versioned {
+        schema_version: SCHEMA_VERSION,
+        payload,
+    }
+}
 
-                Ok(pre_hook_obj)
+/// schema version declared by the hook output
+#[derive(Debug, Deserialize)]
+struct Declared {
+    schema_version: Option<u32>,
+}
+
+/// output without version is of version 1 which only differs by not having schema_version
+fn check_version(version: Option<u32>) -> miette::Result<()> {
+    match version.unwrap_or(1) {
+        SCHEMA_VERSION => Ok(()),
+        previous if previous == SCHEMA_VERSION - 1 => {
+            debug!("hook output is of schema version {previous}, reading it as {SCHEMA_VERSION}");
+            Ok(())
+        }
+        version => miette::bail!(
+            help = format!("hooks should write schema version {SCHEMA_VERSION}, see --hook-schema"),
+            "hook output is of unsupported schema version {version}"
+        ),
+    }
+}
+
+/// run the hook process exchanging data in given format
+fn run_formatted<T: Serialize + DeserializeOwned>(
+    command: std::process::Command,
+    format: Format,
+    input: &T,
+) -> miette::Result<T> {
+    if format == Format::Json {
+        return run_json(command, input);
+    }
+    // size will always be larger than obj, but atleast optimize is for single allocation
+    let body_buf = to_msgpack(&versioned(input))
+        .into_diagnostic()
+        .wrap_err("serializing input body")?;
+    let output = run_process(command, &body_buf)?;
+
+    // deserialize output and read from stdout
+    let declared: Declared = rmp_serde::from_slice(output.as_ref())
+        .into_diagnostic()
+        .wrap_err("Failed to deserialize output of hooks")?;
+    check_version(declared.schema_version)?;
+    rmp_serde::from_slice(output.as_ref())
+        .into_diagnostic()
+        .wrap_err("Failed to deserialize output of hooks")
+}
+
+/// run the hook process exchanging data as single line of json
+fn run_json<T: Serialize + DeserializeOwned>(
+    command: std::process::Command,
+    input: &T,
+) -> miette::Result<T> {
+    let mut input = serde_json::to_value(versioned(input))
+        .into_diagnostic()
+        .wrap_err("serializing input body")?;
+    body_to_text(&mut input);
+    let mut body_buf = serde_json::to_vec(&input)
+        .into_diagnostic()
+        .wrap_err("serializing input body")?;
+    body_buf.push(b'\n');
+    let output = run_process(command, &body_buf)?;
+
+    let mut output = serde_json::from_slice::<serde_json::Value>(&output)
+        .into_diagnostic()
+        .wrap_err("hook didn't return valid json")?;
+    let declared = output
+        .as_object_mut()
+        .and_then(|output| output.remove("schema_version"));
+    let declared = declared
+        .map(serde_json::from_value)
+        .transpose()
+        .into_diagnostic()
+        .wrap_err("invalid schema_version in hook output")?;
+    check_version(declared)?;
+    body_to_bytes(&mut output);
+    serde_json::from_value(output)
+        .into_diagnostic()
+        .wrap_err("Failed to deserialize output of hooks")
+}
+
+/// run the hook process with given input on stdin and return its stdout
+fn run_process(mut command: std::process::Command, input: &[u8]) -> miette::Result<Vec<u8>> {
+    // setup child to take stdin and return both stdout and stdin
+    let mut child = command
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .into_diagnostic()
+        .wrap_err("Couldn't run hook")?;
+
+    debug!("writing to child: {input:x?}");
+
+    // execute child with input
+    child
+        .stdin
+        .take()
+        .expect("Childs stdin is not open, eventhough body is present")
+        .write_all(input)
+        .into_diagnostic()
+        .wrap_err("Failed to send body to hook")?;
+
+    // collect child output
+    let output = child
+        .wait_with_output()
+        .into_diagnostic()
+        .wrap_err("Failed to read hook output")?;
+    debug!("hook output: {:x?}", output.stdout);
+
+    // assuming stderr to be utf-8
+    let child_stderr = String::from_utf8_lossy(&output.stderr);
+
+    if !child_stderr.is_empty() {
+        error!("hook stderr: `{}`", child_stderr);
+    }
+    // check if the execution is success or not
+    if !output.status.success() {
+        let code = std::process::ExitStatus::from_raw(output.status.code().unwrap_or(1));
+        miette::bail!("hook exited with error: {code}")
+    }
+    Ok(output.stdout)
+}
+
+/// json has no binary type, so utf8 body is given as string to json hooks
+pub fn body_to_text(value: &mut serde_json::Value) {
+    if let Some(body) = value.get_mut("body") {
+        if let Ok(bytes) = serde_json::from_value::<Vec<u8>>(body.clone()) {
+            if let Ok(text) = String::from_utf8(bytes) {
+                *body = serde_json::Value::String(text);
+            }
+        }
+    }
+}
+
+/// reverse of body_to_text, string body is converted back to bytes
+fn body_to_bytes(value: &mut serde_json::Value) {
+    if let Some(serde_json::Value::String(text)) = value.get_mut("body") {
+        let bytes = std::mem::take(text).into_bytes();
+        value["body"] = serde_json::Value::from(bytes);
+    }
+}
+
+/// changes done by a hook to its input, removed values are prefixed with `-` and added with `+`
+/// nested fields are joined with `.`
+pub fn diff(before: &serde_json::Value, after: &serde_json::Value) -> Vec<String> {
+    let mut changes = Vec::new();
+    diff_into("", Some(before), Some(after), &mut changes);
+    changes
+}
+
+fn diff_into(
+    path: &str,
+    before: Option<&serde_json::Value>,
+    after: Option<&serde_json::Value>,
+    changes: &mut Vec<String>,
+) {
+    use serde_json::Value;
+    match (before, after) {
+        (Some(Value::Object(before)), Some(Value::Object(after))) => {
+            let keys: std::collections::BTreeSet<_> = before.keys().chain(after.keys()).collect();
+            for key in keys {
+                let path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{path}.{key}")
+                };
+                diff_into(&path, before.get(key), after.get(key), changes);
+            }
+        }
+        (before, after) if before == after => {}
+        (before, after) => {
+            if let Some(before) = before {
+                changes.push(format!("- {path}: {before}"));
+            }
+            if let Some(after) = after {
+                changes.push(format!("+ {path}: {after}"));
             }
         }
     }
@@ -90,3 +446,125 @@ pub fn to_msgpack<T: Serialize>(value: &T) -> Result<Vec<u8>, rmp_serde::encode:
     value.serialize(&mut serializer)?;
     Ok(output)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_nested_fields() {
+        let before = serde_json::json!({"path": "/a", "headers": {"x": "1", "y": "2"}});
+        let after = serde_json::json!({"path": "/a", "headers": {"x": "3", "z": "4"}});
+        assert_eq!(
+            diff(&before, &after),
+            vec![
+                r#"- headers.x: "1""#,
+                r#"+ headers.x: "3""#,
+                r#"- headers.y: "2""#,
+                r#"+ headers.z: "4""#,
+            ]
+        );
+        assert!(diff(&before, &before).is_empty());
+    }
+
+    #[test]
+    fn shell_command_hook() {
+        let hook: Hook = toml::from_str("command = \"sed s#/a#/$1#\"\nformat = \"json\"").unwrap();
+        let workspace = Workspace::new(false);
+        let output = hook
+            .run(
+                &serde_json::json!({"path": "/a"}),
+                &["b"],
+                workspace.path().unwrap(),
+            )
+            .unwrap();
+        assert_eq!(output["path"], "/b");
+
+        let hook: Hook = toml::from_str("command = [\"cat\"]\nformat = \"json\"").unwrap();
+        let output = hook.run(
+            &serde_json::json!({"path": "/a"}),
+            &[] as &[&str],
+            workspace.path().unwrap(),
+        );
+        assert_eq!(output.unwrap()["path"], "/a");
+
+        let hook: Hook = toml::from_str(
+            "command = \"cat > $QWICKET_WORKSPACE/request.json && cat $QWICKET_WORKSPACE/request.json\"\nformat = \"json\"",
+        )
+        .unwrap();
+        hook.run(
+            &serde_json::json!({"path": "/a"}),
+            &[] as &[&str],
+            workspace.path().unwrap(),
+        )
+        .unwrap();
+        assert!(workspace.path().unwrap().join("request.json").exists());
+    }
+
+    #[test]
+    fn schema_versions() {
+        let schema: serde_json::Value = serde_json::from_str(SCHEMA).unwrap();
+        assert_eq!(schema["$defs"]["schema_version"]["const"], SCHEMA_VERSION);
+        assert!(check_version(Some(SCHEMA_VERSION)).is_ok());
+        assert!(check_version(None).is_ok());
+        assert!(check_version(Some(SCHEMA_VERSION + 1)).is_err());
+
+        let input = serde_json::to_value(versioned(&serde_json::json!({"path": "/a"}))).unwrap();
+        assert_eq!(input["schema_version"], SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn json_body_round_trip() {
+        let mut value = serde_json::json!({"status_code": 200, "body": [104, 105]});
+        body_to_text(&mut value);
+        assert_eq!(value["body"], "hi");
+        body_to_bytes(&mut value);
+        assert_eq!(value["body"], serde_json::json!([104, 105]));
+
+        let mut binary = serde_json::json!({"body": [255, 0]});
+        body_to_text(&mut binary);
+        assert_eq!(binary["body"], serde_json::json!([255, 0]));
+    }
+
+    #[test]
+    fn python_scaffold_round_trip() {
+        let dir = std::env::temp_dir().join(format!("qwicket-scaffold-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("hook.py");
+        let _ = std::fs::remove_file(&path);
+        scaffold(Language::Python, &path).unwrap();
+        assert!(
+            scaffold(Language::Python, &path).is_err(),
+            "overwrote existing file"
+        );
+
+        let hook = Hook::script(path, Format::Json);
+        let input = serde_json::json!({"status_code": 200, "headers": {}, "body": [104, 105]});
+        let output: serde_json::Value = hook.run(&input, &["arg"], &dir).unwrap();
+        assert_eq!(output, input);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn enabled_precedence() {
+        let hook: Hook = toml::from_str(r#"script = "./hook.sh""#).unwrap();
+        let disabled: Hook = toml::from_str(
+            r#"
+            script = "./hook.sh"
+            enabled = false
+            "#,
+        )
+        .unwrap();
+        let on = Settings {
+            enabled: Some(true),
+        };
+        let off = Settings {
+            enabled: Some(false),
+        };
+        let unset = Settings::default();
+        assert!(hook.is_enabled(&unset, &unset));
+        assert!(!hook.is_enabled(&unset, &off));
+        assert!(hook.is_enabled(&on, &off));
+        assert!(!disabled.is_enabled(&on, &on));
+    }
+}
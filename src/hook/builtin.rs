@@ -0,0 +1,185 @@
+//! hooks implemented in qwicket itself, used as `pre_hook = "builtin:<name>(<args>)"` without any external process
+
+use miette::{Context, IntoDiagnostic};
+use serde_json::Value;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Builtin {
+    /// print the request as json to stderr
+    LogRequest,
+    /// print the response as json to stderr
+    LogResponse,
+    /// write the body to the file, `save-body(<file>)`
+    SaveBody(std::path::PathBuf),
+    /// fail if the response status is not one of them, `assert-status(200, 201)`
+    AssertStatus(Vec<u16>),
+    /// save value of the json body at the path to the store, `extract(json, $.id, USER_ID)`
+    Extract {
+        path: crate::jsonpath::JsonPath,
+        key: String,
+    },
+}
+
+impl std::str::FromStr for Builtin {
+    type Err = String;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let (name, args) = match source.split_once('(') {
+            Some((name, rest)) => {
+                let args = rest
+                    .strip_suffix(')')
+                    .ok_or_else(|| format!("missing ) in builtin hook {source}"))?;
+                (name.trim(), Some(args))
+            }
+            None => (source.trim(), None),
+        };
+        match (name, args) {
+            ("log-request", None) => Ok(Self::LogRequest),
+            ("log-response", None) => Ok(Self::LogResponse),
+            ("save-body", Some(file)) if !file.trim().is_empty() => {
+                Ok(Self::SaveBody(file.trim().into()))
+            }
+            ("assert-status", Some(statuses)) => statuses
+                .split(',')
+                .map(|status| status.trim().parse())
+                .collect::<Result<_, _>>()
+                .map(Self::AssertStatus)
+                .map_err(|e| format!("invalid status in {source}: {e}")),
+            ("extract", Some(args)) => {
+                let (format, rest) = args
+                    .split_once(',')
+                    .ok_or_else(|| format!("expected extract(json, <path>, <key>): {source}"))?;
+                let (path, key) = rest
+                    .rsplit_once(',')
+                    .ok_or_else(|| format!("expected extract(json, <path>, <key>): {source}"))?;
+                if format.trim() != "json" {
+                    return Err(format!("only json can be extracted, found {}", format.trim()));
+                }
+                Ok(Self::Extract {
+                    path: path.trim().parse()?,
+                    key: key.trim().to_string(),
+                })
+            }
+            _ => Err(format!(
+                "unknown builtin hook {source}, available are log-request, log-response, save-body(<file>), assert-status(<status>), extract(json, <path>, <key>)"
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Builtin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Builtin::LogRequest => f.write_str("log-request"),
+            Builtin::LogResponse => f.write_str("log-response"),
+            Builtin::SaveBody(file) => write!(f, "save-body({})", file.display()),
+            Builtin::AssertStatus(statuses) => {
+                let statuses: Vec<_> = statuses.iter().map(u16::to_string).collect();
+                write!(f, "assert-status({})", statuses.join(", "))
+            }
+            Builtin::Extract { path, key } => write!(f, "extract(json, {path}, {key})"),
+        }
+    }
+}
+
+impl serde::Serialize for Builtin {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Builtin {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        source.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// body of request(string or bytes) or response(bytes)
+fn body(value: &Value) -> Option<Vec<u8>> {
+    match value.get("body")? {
+        Value::String(text) => Some(text.clone().into_bytes()),
+        body => serde_json::from_value(body.clone()).ok(),
+    }
+}
+
+impl Builtin {
+    /// input is the json form of the request or response given to the hooks
+    pub fn apply(&self, input: &mut Value) -> miette::Result<()> {
+        match self {
+            Builtin::LogRequest | Builtin::LogResponse => {
+                let mut printable = input.clone();
+                super::body_to_text(&mut printable);
+                let text = serde_json::to_string_pretty(&printable)
+                    .into_diagnostic()
+                    .wrap_err("Couldn't serialize hook input")?;
+                eprintln!("{text}");
+            }
+            Builtin::SaveBody(file) => {
+                let body = body(input).unwrap_or_default();
+                std::fs::write(file, body)
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Couldn't save body to {file:?}"))?;
+            }
+            Builtin::AssertStatus(statuses) => {
+                let Some(status) = input.get("status_code").and_then(Value::as_u64) else {
+                    miette::bail!("assert-status can only be used as post hook")
+                };
+                if !statuses
+                    .iter()
+                    .any(|expected| u64::from(*expected) == status)
+                {
+                    miette::bail!("status {status} is not one of {statuses:?}")
+                }
+            }
+            Builtin::Extract { path, key } => {
+                let body = body(input).unwrap_or_default();
+                let Some(store) = input.get_mut("store").and_then(Value::as_object_mut) else {
+                    miette::bail!("extract can only be used as post hook")
+                };
+                let mut body: Value = serde_json::from_slice(&body)
+                    .into_diagnostic()
+                    .wrap_err("Couldn't parse body as json for extract")?;
+                let mut extracted = None;
+                path.for_each_mut(&mut body, &mut |value| {
+                    extracted.get_or_insert_with(|| value.clone());
+                });
+                let Some(extracted) = extracted else {
+                    miette::bail!("{path} is not found in the body")
+                };
+                let extracted = match extracted {
+                    Value::String(text) => text,
+                    value => value.to_string(),
+                };
+                store.insert(key.clone(), Value::String(extracted));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_apply() {
+        let extract: Builtin = "extract(json, $.user.id, USER_ID)".parse().unwrap();
+        assert_eq!(extract.to_string(), "extract(json, $.user.id, USER_ID)");
+        assert!("extract(xml, $.id, ID)".parse::<Builtin>().is_err());
+        assert!("unknown".parse::<Builtin>().is_err());
+
+        let mut response = serde_json::json!({
+            "status_code": 201,
+            "store": {},
+            "body": br#"{"user": {"id": 42}}"#.to_vec(),
+        });
+        extract.apply(&mut response).unwrap();
+        assert_eq!(response["store"]["USER_ID"], "42");
+
+        let assert: Builtin = "assert-status(200, 201)".parse().unwrap();
+        assert!(assert.apply(&mut response).is_ok());
+        response["status_code"] = 500.into();
+        assert!(assert.apply(&mut response).is_err());
+    }
+}
@@ -0,0 +1,215 @@
+//! minimal jsonpath used to select fields of json bodies
+//! supports `$.field`, `$['field']`, `$.list[0]`, wildcards(`.*`, `[*]`) and recursive descent(`$..field`)
+
+use serde_json::Value;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Field(String),
+    Index(usize),
+    Wildcard,
+    /// field at any depth
+    Descendant(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonPath {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+impl std::str::FromStr for JsonPath {
+    type Err = String;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let Some(mut rest) = source.strip_prefix('$') else {
+            return Err(format!("jsonpath should start with $: {source}"));
+        };
+        let mut segments = Vec::new();
+        let field_end = |s: &str| s.find(['.', '[']).unwrap_or(s.len());
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix("..") {
+                let end = field_end(after);
+                if end == 0 {
+                    return Err(format!("missing field name after .. in {source}"));
+                }
+                segments.push(Segment::Descendant(after[..end].to_string()));
+                rest = &after[end..];
+            } else if let Some(after) = rest.strip_prefix('.') {
+                let end = field_end(after);
+                segments.push(match &after[..end] {
+                    "" => return Err(format!("missing field name after . in {source}")),
+                    "*" => Segment::Wildcard,
+                    field => Segment::Field(field.to_string()),
+                });
+                rest = &after[end..];
+            } else if let Some(after) = rest.strip_prefix('[') {
+                let Some(end) = after.find(']') else {
+                    return Err(format!("missing ] in {source}"));
+                };
+                let inner = &after[..end];
+                segments.push(if inner == "*" {
+                    Segment::Wildcard
+                } else if let Some(field) = inner
+                    .strip_prefix('\'')
+                    .and_then(|inner| inner.strip_suffix('\''))
+                {
+                    Segment::Field(field.to_string())
+                } else {
+                    inner
+                        .parse()
+                        .map(Segment::Index)
+                        .map_err(|_| format!("invalid index {inner} in {source}"))?
+                });
+                rest = &after[end + 1..];
+            } else {
+                return Err(format!("unexpected {rest} in {source}"));
+            }
+        }
+        Ok(Self {
+            source: source.to_string(),
+            segments,
+        })
+    }
+}
+
+impl std::fmt::Display for JsonPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl serde::Serialize for JsonPath {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for JsonPath {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        source.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl JsonPath {
+    /// call `f` on every value matching the path
+    pub fn for_each_mut(&self, value: &mut Value, f: &mut impl FnMut(&mut Value)) {
+        visit(value, &self.segments, f);
+    }
+
+    /// all values matching the path
+    pub fn select<'a>(&self, value: &'a Value) -> Vec<&'a Value> {
+        let mut selected = Vec::new();
+        collect(value, &self.segments, &mut selected);
+        selected
+    }
+}
+
+fn children(value: &Value) -> Box<dyn Iterator<Item = &Value> + '_> {
+    match value {
+        Value::Object(map) => Box::new(map.values()),
+        Value::Array(list) => Box::new(list.iter()),
+        _ => Box::new(std::iter::empty()),
+    }
+}
+
+fn collect<'a>(value: &'a Value, segments: &[Segment], selected: &mut Vec<&'a Value>) {
+    let Some((segment, rest)) = segments.split_first() else {
+        selected.push(value);
+        return;
+    };
+    match segment {
+        Segment::Field(field) => {
+            if let Some(child) = value.get(field.as_str()) {
+                collect(child, rest, selected)
+            }
+        }
+        Segment::Index(index) => {
+            if let Some(child) = value.get(*index) {
+                collect(child, rest, selected)
+            }
+        }
+        Segment::Wildcard => children(value).for_each(|child| collect(child, rest, selected)),
+        Segment::Descendant(field) => {
+            if let Some(child) = value.get(field.as_str()) {
+                collect(child, rest, selected)
+            }
+            children(value).for_each(|child| collect(child, segments, selected));
+        }
+    }
+}
+
+fn children_mut(value: &mut Value) -> Box<dyn Iterator<Item = &mut Value> + '_> {
+    match value {
+        Value::Object(map) => Box::new(map.values_mut()),
+        Value::Array(list) => Box::new(list.iter_mut()),
+        _ => Box::new(std::iter::empty()),
+    }
+}
+
+fn visit(value: &mut Value, segments: &[Segment], f: &mut impl FnMut(&mut Value)) {
+    let Some((segment, rest)) = segments.split_first() else {
+        f(value);
+        return;
+    };
+    match segment {
+        Segment::Field(field) => {
+            if let Some(child) = value.get_mut(field.as_str()) {
+                visit(child, rest, f)
+            }
+        }
+        Segment::Index(index) => {
+            if let Some(child) = value.get_mut(*index) {
+                visit(child, rest, f)
+            }
+        }
+        Segment::Wildcard => children_mut(value).for_each(|child| visit(child, rest, f)),
+        Segment::Descendant(field) => {
+            if let Some(child) = value.get_mut(field.as_str()) {
+                visit(child, rest, f)
+            }
+            children_mut(value).for_each(|child| visit(child, segments, f));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_paths() {
+        let path: JsonPath = "$.data[*]['full name'][0]..token".parse().unwrap();
+        assert_eq!(
+            path.segments,
+            vec![
+                Segment::Field("data".to_string()),
+                Segment::Wildcard,
+                Segment::Field("full name".to_string()),
+                Segment::Index(0),
+                Segment::Descendant("token".to_string()),
+            ]
+        );
+        assert!("data.token".parse::<JsonPath>().is_err());
+        assert!("$.".parse::<JsonPath>().is_err());
+        assert!("$[x]".parse::<JsonPath>().is_err());
+    }
+
+    #[test]
+    fn select_and_modify() {
+        let mut value = serde_json::json!({
+            "token": "a",
+            "users": [{"name": "x", "auth": {"token": "b"}}, {"name": "y"}]
+        });
+        let names: JsonPath = "$.users[*].name".parse().unwrap();
+        let mut selected = Vec::new();
+        names.for_each_mut(&mut value, &mut |name| selected.push(name.clone()));
+        assert_eq!(selected, vec!["x", "y"]);
+        assert_eq!(names.select(&value), vec!["x", "y"]);
+        let tokens: JsonPath = "$..token".parse().unwrap();
+        tokens.for_each_mut(&mut value, &mut |token| *token = Value::Null);
+        assert_eq!(value["token"], Value::Null);
+        assert_eq!(value["users"][0]["auth"]["token"], Value::Null);
+    }
+}
@@ -0,0 +1,523 @@
+//! make rest queries from toml collections
+//!
+//! the binary is a thin layer over this crate, [`Project`] can be used to run queries of a collection from other tools
+
+pub mod agent;
+mod archive;
+mod baseline;
+mod cache;
+pub mod constants;
+pub mod explain;
+pub mod hook;
+mod jsonpath;
+pub mod lint;
+mod markup;
+pub mod metrics;
+pub mod output;
+pub mod parser;
+pub mod proxy;
+mod redact;
+pub mod report;
+mod secret;
+pub mod sink;
+pub mod store;
+mod traversal;
+pub mod upgrade;
+mod variable;
+pub mod waterfall;
+pub mod wizard;
+mod workspace;
+
+use miette::{Context, IntoDiagnostic};
+
+#[derive(Debug, Default, clap::Parser)]
+#[command(author, version, about, long_about)]
+#[command(group(clap::ArgGroup::new("summarized").args(["run_group", "health"])))]
+/// make rest queries, automate
+///
+/// qwicket  Copyright (C) 2024  hardfau1t
+/// This program comes with ABSOLUTELY NO WARRANTY; for details type `show w'.
+/// This is free software, and you are welcome to redistribute it
+/// under certain conditions; type `show c' for details.
+pub struct Arguments {
+    #[arg(short, long, global=true, action=clap::ArgAction::Count)]
+    pub verbose: u8,
+    /// don't print the status line of the response
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
+    /// configuration file containing queries
+    #[arg(short, long, default_value = "./qwicket.toml")]
+    pub config_file: std::path::PathBuf,
+
+    /// rewrite config and group files of an older version to the current version, originals are kept as backup
+    #[arg(long("upgrade-config"))]
+    pub upgrade_config: bool,
+
+    /// set store variable(doesn't set in current shell)
+    /// example: --set key=value
+    /// to unset a value, just don't include value
+    /// example: --set key
+    #[arg(long)]
+    pub set: Option<String>,
+
+    /// get store variable
+    #[arg(long)]
+    pub get: Option<String>,
+
+    /// show the log of changes made to store values of current environment
+    #[arg(long("store-log"), conflicts_with_all(["get", "set"]))]
+    pub store_log: bool,
+
+    /// don't store changes to config store back to disk
+    #[arg(short('p'), long("no-persistent"))]
+    pub no_persistent: bool,
+
+    /// when same group is defined more than once use the last one with a warning instead of failing
+    #[arg(long)]
+    pub allow_shadowing: bool,
+
+    // write output to given file
+    #[arg(short, long)]
+    pub output: Option<std::path::PathBuf>,
+
+    /// request only part of the resource, ex: --range bytes=0-1023
+    #[arg(long)]
+    pub range: Option<String>,
+
+    /// continue download from the size of existing output file and append to it
+    #[arg(long, requires("output"), conflicts_with("range"))]
+    pub resume: bool,
+
+    /// open the response with system default application, irrespective of content type
+    #[arg(long, conflicts_with("output"))]
+    pub open: bool,
+
+    /// write binary response body to terminal, by default it is refused
+    #[arg(long, conflicts_with_all(["output", "open"]))]
+    pub raw: bool,
+
+    /// copy the response body to system clipboard, body is presented as usual
+    #[arg(long)]
+    pub copy: bool,
+
+    /// convert the response body before presenting or writing to --output
+    #[arg(long)]
+    pub render: Option<output::Render>,
+
+    /// fields of the rendered rows in order, nested fields as `a.b`
+    #[arg(long, requires("render"), value_delimiter = ',')]
+    pub fields: Vec<String>,
+
+    /// send a desktop notification with status and duration when the query finishes
+    #[arg(long)]
+    pub notify: bool,
+
+    /// list available options (services/endpoints)
+    #[arg(short, long)]
+    pub list: bool,
+
+    /// list queries as sorted, uncolored `name METHOD path` lines, useful for keeping an inventory in git
+    #[arg(long, requires("list"))]
+    pub plain: bool,
+
+    /// use given environment
+    #[arg(short, long)]
+    pub environment: Option<String>,
+
+    /// don't run the query just run till pre-hook
+    /// use with --verbose(-v) to be useful
+    #[arg(short = 'n', long = "dry-run")]
+    pub dry_run: bool,
+
+    /// with dry run, use response from given json file(status_code, headers, body) and continue with post hook
+    #[arg(long = "fake-response", requires("dry_run"))]
+    pub fake_response: Option<std::path::PathBuf>,
+
+    /// don't run any hooks
+    #[arg(short = 's', long = "skip-hooks")]
+    pub skip_hooks: bool,
+
+    /// don't run pre request hook
+    #[arg(long = "skip-prehook", conflicts_with("skip_hooks"))]
+    pub skip_prehook: bool,
+
+    /// don't run post responnse hook
+    #[arg(long = "skip-posthook", conflicts_with("skip_hooks"))]
+    pub skip_posthook: bool,
+
+    /// set variable for this run, takes priority over store and environment variables
+    /// example: --var key=value
+    #[arg(long("var"), value_parser = parse_var)]
+    pub vars: Vec<(String, String)>,
+
+    /// run given hook script with the request or response from --with fixture and show what it changes
+    #[arg(long("test-hook"), requires("fixture"))]
+    pub test_hook: Option<std::path::PathBuf>,
+
+    /// json fixture for --test-hook, a request or a response(same format as --fake-response)
+    #[arg(long("with"), requires("test_hook"))]
+    pub fixture: Option<std::path::PathBuf>,
+
+    /// format used by the --test-hook script
+    #[arg(long("hook-format"), requires("test_hook"), default_value = "msgpack")]
+    pub hook_format: hook::Format,
+
+    /// don't remove the temporary directory given to hooks and body commands in `QWICKET_WORKSPACE`
+    #[arg(long("keep-temp"))]
+    pub keep_temp: bool,
+
+    /// create the query at the endpoint(`<group>.<name>`) by answering prompts and add it to the group file
+    #[arg(long("new-query"), conflicts_with_all(["list", "list_json", "run_group", "health", "race", "lint", "search"]))]
+    pub new_query: bool,
+
+    /// generate a hook template in the file
+    #[arg(long("scaffold-hook"), value_name("FILE"))]
+    pub scaffold_hook: Option<std::path::PathBuf>,
+
+    /// language of the --scaffold-hook template
+    #[arg(
+        long("hook-language"),
+        requires("scaffold_hook"),
+        default_value = "python"
+    )]
+    pub hook_language: hook::Language,
+
+    /// print the json schema of the request/response given to the hooks
+    #[arg(long("hook-schema"))]
+    pub hook_schema: bool,
+
+    /// list the agent plugins(`qwicket-agent-*` executables) found in PATH
+    #[arg(long("list-agents"))]
+    pub list_agents: bool,
+
+    /// open the final request(after pre hook and substitution) as json in `$VISUAL`/`$EDITOR` and send the edited request
+    #[arg(long, conflicts_with_all(["run_group", "health", "race", "inspect_request"]))]
+    pub edit: bool,
+
+    /// print the final request(after pre hook and substitution) in human readable form instead of sending it
+    #[arg(long, conflicts_with_all(["inspect_request", "inspect_response"]))]
+    pub preview: bool,
+
+    /// delay every request by given duration before sending it, ex: 2s
+    #[arg(long("inject-latency"), value_parser = humantime::parse_duration)]
+    pub inject_latency: Option<std::time::Duration>,
+
+    /// fail given fraction(0 to 1) of the requests without sending them
+    #[arg(long("inject-abort-rate"), value_parser = agent::http::parse_rate)]
+    pub inject_abort_rate: Option<f64>,
+
+    /// fail given fraction(0 to 1) of the requests as timed out after waiting for their timeout, without sending them
+    #[arg(long("inject-timeout-rate"), value_parser = agent::http::parse_rate)]
+    pub inject_timeout_rate: Option<f64>,
+
+    /// warn when a query is slower than its latency baseline by more than given percentage, ex: 50%
+    #[arg(long("perf-guard"), value_parser = baseline::parse_percent)]
+    pub perf_guard: Option<f64>,
+
+    /// print remote address, http version and peer certificate fingerprint of the connection
+    /// on transport failure prints the complete cause of the error
+    #[arg(long("trace-transport"))]
+    pub trace_transport: bool,
+
+    /// stop before pre hook and write pre hook data to stdout. Useful for developing pre-hook
+    #[arg(long = "inspect-request", conflicts_with_all(["skip_hooks", "skip_prehook"]))]
+    pub inspect_request: bool,
+
+    /// stop before post hook and write post hook data to stdout. Useful for developing post-hook
+    #[arg(long = "inspect-response", conflicts_with_all(["skip_hooks", "skip_posthook"]))]
+    pub inspect_response: bool,
+
+    /// output collected services as json output
+    #[arg(long("list-json"), conflicts_with("list"))]
+    pub list_json: bool,
+
+    /// run all the queries of the group and its sub groups
+    #[arg(long("run-group"), conflicts_with_all(["list", "list_json"]))]
+    pub run_group: bool,
+
+    /// run the health check queries of the group or the query and print the results as table
+    #[arg(long, conflicts_with_all(["list", "list_json", "run_group"]))]
+    pub health: bool,
+
+    /// fail if the request has unresolved `${...}` after substitution and warn about store keys not used by the query
+    #[arg(long)]
+    pub strict: bool,
+
+    /// print effective values of the query(host, headers, store values...) and where each of them came from
+    #[arg(long, conflicts_with_all(["list", "list_json", "run_group", "health", "race"]))]
+    pub explain: bool,
+
+    /// check all the group files for common mistakes(auth headers, invalid content types, unused environments...)
+    #[arg(long)]
+    pub lint: bool,
+
+    /// apply safe fixes of lint findings to the group files
+    #[arg(long, requires("lint"))]
+    pub fix: bool,
+
+    /// print the query with its merged environment, variables and expectations as json, values are not substituted
+    #[arg(long, conflicts_with_all(["list", "list_json", "run_group", "health", "race", "explain"]))]
+    pub describe: bool,
+
+    /// send the query to all of its environments concurrently, report which responded first and whether bodies match
+    #[arg(long, conflicts_with_all(["list", "list_json", "run_group", "health"]))]
+    pub race: bool,
+
+    /// with health, run the health checks in all the environments of the query
+    #[arg(long, requires("health"))]
+    pub all_envs: bool,
+
+    /// with run group or health, write summary metrics of the run to given file in openmetrics format
+    #[arg(long, requires("summarized"))]
+    pub metrics_file: Option<std::path::PathBuf>,
+
+    /// with run group or health, push summary metrics of the run to given prometheus pushgateway
+    #[arg(long, requires("summarized"))]
+    pub push_metrics: Option<reqwest::Url>,
+
+    /// with run group or health, write junit xml report of the run to given file
+    #[arg(long, requires("summarized"))]
+    pub report: Option<std::path::PathBuf>,
+
+    /// with run group, write the waterfall(start offset and duration of each query) of the run to given file as html
+    #[arg(long, requires("run_group"))]
+    pub waterfall: Option<std::path::PathBuf>,
+
+    /// search queries whose name, path, description or url contains given pattern
+    #[arg(long, conflicts_with_all(["list", "list_json", "run_group"]))]
+    pub search: Option<String>,
+
+    /// run a proxy on given port of localhost which forwards requests to the environment of the group
+    #[arg(long, conflicts_with_all(["list", "list_json", "run_group", "search"]))]
+    pub proxy: Option<u16>,
+
+    /// with proxy, record all the exchanges to given directory
+    #[arg(long, requires("proxy"), conflicts_with("replay"))]
+    pub record: Option<std::path::PathBuf>,
+
+    /// with proxy, serve exchanges recorded in given directory instead of forwarding
+    #[arg(long, requires("proxy"))]
+    pub replay: Option<std::path::PathBuf>,
+
+    /// only list/run queries which contains given tag, can be given multiple times
+    #[arg(long("tag"))]
+    pub tags: Vec<String>,
+
+    #[arg(required_unless_present_any(["list", "list_json", "get", "set", "store_log", "test_hook", "scaffold_hook", "run_group", "health", "search", "proxy", "lint", "upgrade_config", "list_agents", "hook_schema"]))]
+    pub endpoint: Vec<String>,
+    /// arguments for hooks, note to make it unamgious add -- before providing any flags
+    /// add another -- separator to separate between prehook flags and post hook flags
+    #[arg(allow_hyphen_values(true), last(true))]
+    pub args: Vec<String>,
+}
+
+fn parse_var(key_val: &str) -> Result<(String, String), String> {
+    key_val
+        .split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected key=value, found {key_val}"))
+}
+
+/// store of the environment along with its seed values
+pub fn open_store(
+    config: &parser::Config,
+    env: &str,
+    no_persistent: bool,
+) -> miette::Result<store::Store> {
+    let mut config_store = store::Store::with_env(&config.project, env.to_string())
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Couldn't read store values of {}", config.project))?;
+
+    config_store.persistent(!no_persistent);
+
+    if let Some(seed) = &config.seed {
+        config_store.seed(seed.read(env).wrap_err("Couldn't read store seed")?);
+    }
+    Ok(config_store)
+}
+
+/// how [`Project::execute`] runs a query, default is same as the binary without any flags
+#[derive(Debug, Default, Clone)]
+pub struct Options {
+    /// same as `--var`, takes priority over store and environment variables
+    pub vars: Vec<(String, String)>,
+    /// same as `--skip-hooks`
+    pub skip_hooks: bool,
+    /// same as `--strict`
+    pub strict: bool,
+    /// same as `--dry-run`
+    pub dry_run: bool,
+    /// arguments of the hooks, same as the ones after `--`
+    pub hook_args: Vec<String>,
+}
+
+/// collection loaded from its config file, runs queries the way binary does
+///
+/// ```no_run
+/// # async fn users() -> miette::Result<()> {
+/// let mut project = qwicket::Project::load("qwicket.toml", "staging")?;
+/// let options = qwicket::Options {
+///     vars: vec![("user_id".to_string(), "42".to_string())],
+///     ..Default::default()
+/// };
+/// if let Some(response) = project.execute(&["users", "get"], &options).await? {
+///     println!("{:?}", response.status);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct Project {
+    config: parser::Config,
+    groups: parser::Group,
+    environment: String,
+    store: store::Store,
+}
+
+impl Project {
+    /// read the config, groups of its api directory and store of the environment
+    pub fn load(
+        config_file: impl AsRef<std::path::Path>,
+        environment: &str,
+    ) -> miette::Result<Self> {
+        let config = parser::Config::open(&config_file.as_ref())?;
+        let groups = parser::Group::from_dir(&config.api_directory, &config.traversal, false)?;
+        let store = open_store(&config, environment, false)?;
+        Ok(Self {
+            config,
+            groups,
+            environment: environment.to_string(),
+            store,
+        })
+    }
+
+    pub fn config(&self) -> &parser::Config {
+        &self.config
+    }
+
+    /// store values of the environment, changes made by queries are saved back to disk
+    pub fn store_mut(&mut self) -> &mut store::Store {
+        &mut self.store
+    }
+
+    /// query at given path(ex: ["users", "get"]) with environments of its groups merged
+    pub fn find_query(&self, path: &[impl AsRef<str>]) -> Option<parser::QuerySearchResult> {
+        self.groups.find(path).and_then(|result| result.query)
+    }
+
+    /// run the query, body is returned instead of being presented
+    pub async fn execute(
+        &mut self,
+        path: &[impl AsRef<str>],
+        options: &Options,
+    ) -> miette::Result<Option<output::QueryResponse>> {
+        let endpoint: Vec<_> = path.iter().map(|part| part.as_ref().to_string()).collect();
+        let name = endpoint.join(constants::PATH_SEPARATOR);
+        let Some(query) = self.find_query(path) else {
+            miette::bail!("Couldn't find query {name}")
+        };
+        let Options {
+            vars,
+            skip_hooks,
+            strict,
+            dry_run,
+            hook_args,
+        } = options.clone();
+        let args = Arguments {
+            quiet: true,
+            endpoint,
+            vars,
+            skip_hooks,
+            strict,
+            dry_run,
+            args: hook_args,
+            ..Default::default()
+        };
+        query
+            .exec_with_args(
+                &name,
+                &args,
+                &self.environment,
+                &mut self.store,
+                &self.config,
+                None,
+            )
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// server which answers one request with its request line as body
+    async fn server() -> u16 {
+        let listener = tokio::net::TcpListener::bind(("127.0.0.1", 0))
+            .await
+            .unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut chunk = [0; 1024];
+            while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                let read = stream.read(&mut chunk).await.unwrap();
+                request.extend_from_slice(&chunk[..read]);
+            }
+            let request = String::from_utf8_lossy(&request);
+            let line = request.lines().next().unwrap();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: text/plain\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{line}",
+                line.len()
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn project_executes_query() {
+        let dir = std::env::temp_dir().join(format!("qwicket-project-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("api")).unwrap();
+        let name = format!("test_project_{}", std::process::id());
+        std::fs::write(
+            dir.join("qwicket.toml"),
+            format!(
+                "version = {:?}\nproject = {name:?}\napi_directory = {:?}",
+                env!("CARGO_PKG_VERSION"),
+                dir.join("api")
+            ),
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("api").join("users.toml"),
+            format!(
+                "type = \"http\"\n[environment.dev]\nscheme = \"http\"\nhost = \"127.0.0.1\"\nport = {}\n\n[query.get]\npath = \"/users/${{id}}\"\nmethod = \"GET\"",
+                server().await
+            ),
+        )
+        .unwrap();
+
+        let mut project = Project::load(dir.join("qwicket.toml"), "dev").unwrap();
+        project.store_mut().persistent(false);
+        assert!(project
+            .execute(&["users", "missing"], &Options::default())
+            .await
+            .is_err());
+        let options = Options {
+            vars: vec![("id".to_string(), "42".to_string())],
+            ..Default::default()
+        };
+        let response = project
+            .execute(&["users", "get"], &options)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(response.status, Some(200));
+        assert_eq!(response.body, b"GET /users/42 HTTP/1.1");
+        drop(project);
+        std::fs::remove_dir_all(dir).unwrap();
+        let store = dirs::cache_dir().unwrap().join("qwicket").join(name);
+        let _ = std::fs::remove_file(store);
+    }
+}
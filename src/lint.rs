@@ -0,0 +1,202 @@
+//! common mistakes in group files, reported with `--lint` and fixed with `--fix`
+
+use std::path::{Path, PathBuf};
+
+use miette::{Context, IntoDiagnostic};
+use tracing::{debug, info};
+
+/// safe rewrite of a finding
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fix {
+    /// move bearer token from the header to `bearer_auth` of the query
+    BearerAuth { header: String, token: String },
+}
+
+#[derive(Debug)]
+pub struct Finding {
+    /// file in which the group is defined, None for directories without index file
+    file: Option<PathBuf>,
+    /// path of the value in the file, ex: ["group", "users", "query", "create"]
+    key: Vec<String>,
+    message: String,
+    help: String,
+    fix: Option<Fix>,
+}
+
+#[derive(Debug, Default)]
+pub struct Lints {
+    findings: Vec<Finding>,
+}
+
+impl Lints {
+    pub fn add(
+        &mut self,
+        file: Option<&Path>,
+        key: Vec<String>,
+        message: impl Into<String>,
+        help: impl Into<String>,
+        fix: Option<Fix>,
+    ) {
+        self.findings.push(Finding {
+            file: file.map(Path::to_path_buf),
+            key,
+            message: message.into(),
+            help: help.into(),
+            fix,
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.findings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.findings.is_empty()
+    }
+
+    /// print the findings along with the part of the file they are found in
+    pub fn print(&self) {
+        for finding in &self.findings {
+            eprintln!("{:?}", finding.report());
+        }
+    }
+
+    /// apply fixes to the files, findings which are fixed are removed
+    pub fn fix(&mut self) -> miette::Result<usize> {
+        let (fixable, rest): (Vec<_>, Vec<_>) = std::mem::take(&mut self.findings)
+            .into_iter()
+            .partition(|finding| finding.fix.is_some() && finding.file.is_some());
+        self.findings = rest;
+        let mut files: Vec<&Path> = fixable
+            .iter()
+            .filter_map(|finding| finding.file.as_deref())
+            .collect();
+        files.sort_unstable();
+        files.dedup();
+        for file in &files {
+            let content = std::fs::read_to_string(file)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Couldn't read {file:?}"))?;
+            let mut document: toml_edit::DocumentMut = content
+                .parse()
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Couldn't parse {file:?}"))?;
+            for finding in fixable
+                .iter()
+                .filter(|finding| finding.file.as_deref() == Some(*file))
+            {
+                let fix = finding.fix.as_ref().expect("only fixable findings");
+                apply(&mut document, &finding.key, fix)
+                    .wrap_err_with(|| format!("Couldn't fix {file:?}"))?;
+                info!("fixed {}: {}", file.display(), finding.message);
+            }
+            std::fs::write(file, document.to_string())
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Couldn't write {file:?}"))?;
+        }
+        Ok(fixable.len())
+    }
+}
+
+impl Finding {
+    fn report(&self) -> miette::Report {
+        let location = self.key.join(".");
+        let help = match &self.fix {
+            Some(_) => format!("{}, fixable with --fix", self.help),
+            None => self.help.clone(),
+        };
+        let Some(file) = &self.file else {
+            return miette::miette!(
+                severity = miette::Severity::Warning,
+                help = help,
+                "{location}: {}",
+                self.message
+            );
+        };
+        let content = std::fs::read_to_string(file).unwrap_or_default();
+        let labels = span(&content, &self.key)
+            .map(|span| vec![miette::LabeledSpan::at(span, self.message.clone())])
+            .unwrap_or_default();
+        miette::miette!(
+            severity = miette::Severity::Warning,
+            labels = labels,
+            help = help,
+            "{location}: {}",
+            self.message
+        )
+        .with_source_code(miette::NamedSource::new(
+            file.display().to_string(),
+            content,
+        ))
+    }
+}
+
+/// location of the key in the toml document
+fn span(content: &str, key: &[String]) -> Option<std::ops::Range<usize>> {
+    let document = toml_edit::ImDocument::parse(content)
+        .inspect_err(|e| debug!("couldn't parse file for locating the finding: {e}"))
+        .ok()?;
+    let (last, parents) = key.split_last()?;
+    let mut item = document.as_item();
+    for part in parents {
+        item = item.get(part)?;
+    }
+    let (key, value) = item.as_table_like()?.get_key_value(last)?;
+    key.span().or_else(|| value.span())
+}
+
+fn apply(document: &mut toml_edit::DocumentMut, key: &[String], fix: &Fix) -> miette::Result<()> {
+    let mut item = document.as_item_mut();
+    for part in key {
+        item = item
+            .get_mut(part)
+            .ok_or_else(|| miette::miette!("Couldn't find {}", key.join(".")))?;
+    }
+    let table = item
+        .as_table_like_mut()
+        .ok_or_else(|| miette::miette!("{} is not a table", key.join(".")))?;
+    match fix {
+        Fix::BearerAuth { header, token } => {
+            let headers = table
+                .get_mut("headers")
+                .and_then(toml_edit::Item::as_table_like_mut)
+                .ok_or_else(|| miette::miette!("Couldn't find headers of {}", key.join(".")))?;
+            headers.remove(header);
+            if headers.is_empty() {
+                table.remove("headers");
+            }
+            table.insert("bearer_auth", toml_edit::value(token.as_str()));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bearer_header_fix() {
+        let content = r#"type = "http"
+
+[query.me]
+method = "GET"
+path = "/me"
+headers = { Authorization = "Bearer ${token}", Accept = "application/json" }
+"#;
+        let key = vec!["query".to_string(), "me".to_string()];
+        let line = content.find("[query.me]").unwrap();
+        assert_eq!(span(content, &key).map(|span| span.start), Some(line + 7));
+
+        let mut document: toml_edit::DocumentMut = content.parse().unwrap();
+        let fix = Fix::BearerAuth {
+            header: "Authorization".to_string(),
+            token: "${token}".to_string(),
+        };
+        apply(&mut document, &key, &fix).unwrap();
+        let fixed = document.to_string();
+        assert!(!fixed.contains("Authorization"), "{fixed}");
+        assert!(fixed.contains(r#"bearer_auth = "${token}""#), "{fixed}");
+        assert!(fixed.contains(r#"Accept = "application/json""#), "{fixed}");
+    }
+}
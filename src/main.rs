@@ -1,98 +1,26 @@
-mod agent;
-mod constants;
-mod hook;
-mod parser;
-mod store;
-
-use std::io::{IsTerminal, Read, Write};
+use std::io::{IsTerminal, Read};
 
 use clap::Parser;
 use miette::{Context, IntoDiagnostic};
 use tracing::{debug, info, warn};
 use tracing_subscriber::filter::LevelFilter;
+use yansi::Paint;
 
-#[derive(Debug, clap::Parser)]
-#[command(author, version, about, long_about)]
-/// make rest queries, automate
-///
-/// qwicket  Copyright (C) 2024  hardfau1t
-/// This program comes with ABSOLUTELY NO WARRANTY; for details type `show w'.
-/// This is free software, and you are welcome to redistribute it
-/// under certain conditions; type `show c' for details.
-struct Arguments {
-    #[arg(short, long, global=true, action=clap::ArgAction::Count)]
-    verbose: u8,
-    /// configuration file containing queries
-    #[arg(short, long, default_value = "./qwicket.toml")]
-    config_file: std::path::PathBuf,
-
-    /// set store variable(doesn't set in current shell)
-    /// example: --set key=value
-    /// to unset a value, just don't include value
-    /// example: --set key
-    #[arg(long)]
-    set: Option<String>,
-
-    /// get store variable
-    #[arg(long)]
-    get: Option<String>,
-
-    /// don't store changes to config store back to disk
-    #[arg(short('p'), long("no-persistent"))]
-    no_persistent: bool,
-
-    // write output to given file
-    #[arg(short, long)]
-    output: Option<std::path::PathBuf>,
-
-    /// list available options (services/endpoints)
-    #[arg(short, long)]
-    list: bool,
-
-    /// use given environment
-    #[arg(short, long)]
-    environment: Option<String>,
-
-    /// don't run the query just run till pre-hook
-    /// use with --verbose(-v) to be useful
-    #[arg(short = 'n', long = "dry-run")]
-    dry_run: bool,
-
-    /// don't run any hooks
-    #[arg(short = 's', long = "skip-hooks")]
-    skip_hooks: bool,
-
-    /// don't run pre request hook
-    #[arg(long = "skip-prehook", conflicts_with("skip_hooks"))]
-    skip_prehook: bool,
-
-    /// don't run post responnse hook
-    #[arg(long = "skip-posthook", conflicts_with("skip_hooks"))]
-    skip_posthook: bool,
-
-    /// stop before pre hook and write pre hook data to stdout. Useful for developing pre-hook
-    #[arg(long = "inspect-request", conflicts_with_all(["skip_hooks", "skip_prehook"]))]
-    inspect_request: bool,
-
-    /// stop before post hook and write post hook data to stdout. Useful for developing post-hook
-    #[arg(long = "inspect-response", conflicts_with_all(["skip_hooks", "skip_posthook"]))]
-    inspect_response: bool,
-
-    /// output collected services as json output
-    #[arg(long("list-json"), conflicts_with("list"))]
-    list_json: bool,
-
-    #[arg(required_unless_present_any(["list", "list_json", "get", "set"]))]
-    endpoint: Vec<String>,
-    /// arguments for hooks, note to make it unamgious add -- before providing any flags
-    /// add another -- separator to separate between prehook flags and post hook flags
-    #[arg(allow_hyphen_values(true), last(true))]
-    args: Vec<String>,
-}
+use qwicket::{
+    agent, constants, explain, hook, lint, metrics, open_store, output, parser, proxy, report,
+    sink, store, upgrade, waterfall, wizard, Arguments,
+};
 
 #[tokio::main]
 async fn main() -> miette::Result<()> {
-    let args = Arguments::parse();
+    let result = run().await;
+    // summaries are written in background, don't lose them
+    sink::flush().await;
+    result
+}
+
+async fn run() -> miette::Result<()> {
+    let mut args = Arguments::parse();
     let log_level = match args.verbose {
         0 => LevelFilter::WARN,
         1 => LevelFilter::INFO,
@@ -115,6 +43,34 @@ async fn main() -> miette::Result<()> {
 
     debug!(extra_args=?args.args, "Arguments for the scripts");
 
+    if let (Some(script), Some(fixture)) = (&args.test_hook, &args.fixture) {
+        return agent::http::test_hook(
+            script,
+            args.hook_format,
+            fixture,
+            &args.args,
+            args.keep_temp,
+        );
+    }
+
+    if let Some(file) = &args.scaffold_hook {
+        return hook::scaffold(args.hook_language, file);
+    }
+
+    if args.hook_schema {
+        println!("{}", hook::SCHEMA.trim_end());
+        return Ok(());
+    }
+
+    if args.list_agents {
+        agent::plugin::print_all().await;
+        return Ok(());
+    }
+
+    if args.upgrade_config {
+        return upgrade::config(&args.config_file);
+    }
+
     let config = parser::Config::open(&args.config_file)?;
 
     let env = match args.environment {
@@ -129,11 +85,7 @@ async fn main() -> miette::Result<()> {
             })?,
     };
 
-    let mut config_store = crate::store::Store::with_env(&config.project, env.clone())
-        .into_diagnostic()
-        .wrap_err_with(|| format!("Couldn't read store values of {}", config.project))?;
-
-    config_store.persistent(!args.no_persistent);
+    let mut config_store = open_store(&config, &env, args.no_persistent)?;
 
     debug!("current config: {config_store:?}");
 
@@ -147,9 +99,10 @@ async fn main() -> miette::Result<()> {
         let key = key_val_split
             .next()
             .ok_or(miette::miette!("Empty key value set"))?;
+        config_store.begin("--set");
         if let Some(value) = key_val_split.next() {
             info!("Setting \"{key}=\"=\"{value}\"");
-            config_store.insert(key.to_string(), value.to_string());
+            config_store.set(key.to_string(), value.to_string());
         } else {
             if let Some(value) = config_store.remove(key) {
                 info!("Removed \"{key}\" = \"{value}\"");
@@ -157,26 +110,160 @@ async fn main() -> miette::Result<()> {
                 warn!("Value for {key} not found, not removing")
             }
         }
+        config_store.commit();
+    } else if args.store_log {
+        print_store_log(&config_store, &env)?;
     } else {
-        let groups = parser::Group::from_dir(config.api_directory)?;
+        let groups = parser::Group::from_dir(
+            &config.api_directory,
+            &config.traversal,
+            args.allow_shadowing,
+        )?;
 
         debug!(query_set=?groups, "parsed services");
+        args.endpoint = groups.split_dotted(std::mem::take(&mut args.endpoint));
+
+        // new query is run by reading the groups again
+        let groups = if args.new_query {
+            if !wizard::new_query(&groups, &args.endpoint)? {
+                return Ok(());
+            }
+            parser::Group::from_dir(
+                &config.api_directory,
+                &config.traversal,
+                args.allow_shadowing,
+            )?
+        } else {
+            groups
+        };
+
+        if args.lint {
+            let mut lints = lint::Lints::default();
+            groups.lint(&[], &mut lints);
+            if args.fix {
+                let fixed = lints.fix()?;
+                eprintln!("fixed {fixed} problems");
+            }
+            lints.print();
+            if !lints.is_empty() {
+                miette::bail!("{} problems found", lints.len())
+            }
+            return Ok(());
+        }
+
+        if let Some(pattern) = &args.search {
+            let matches = groups.search(pattern);
+            if matches.is_empty() {
+                miette::bail!("no queries matching {pattern}")
+            }
+            for search_match in matches {
+                let source = search_match
+                    .source
+                    .map(|source| source.display().to_string())
+                    .unwrap_or_default();
+                println!(
+                    "{source}: {} ({})",
+                    search_match.path.join(constants::PATH_SEPARATOR),
+                    search_match.field
+                );
+            }
+            return Ok(());
+        }
+
+        if let Some(port) = args.proxy {
+            let environment = groups
+                .find_environment(&args.endpoint, &env)
+                .ok_or_else(|| miette::miette!("Couldn't find environment {env} for the group"))?;
+            let mode = match args.replay {
+                Some(directory) => proxy::Mode::Replay(directory),
+                None => proxy::Mode::Forward {
+                    record: args.record,
+                },
+            };
+            return proxy::serve(port, environment, &config_store, &config, mode).await;
+        }
 
-        let query_set = groups
-            .find(&args.endpoint)
-            .ok_or_else(|| miette::miette!("no such query or group found"))?;
+        let query_set = groups.find(&args.endpoint).ok_or_else(|| {
+            let suggestions: Vec<_> = groups
+                .suggest(&args.endpoint)
+                .into_iter()
+                .map(|path| format!("`{}`", path.join(constants::PATH_SEPARATOR)))
+                .collect();
+            if suggestions.is_empty() {
+                miette::miette!("no such query or group found")
+            } else {
+                miette::miette!(
+                    help = format!("did you mean {}?", suggestions.join(" or ")),
+                    "no such query or group found"
+                )
+            }
+        })?;
 
         if args.list || args.list_json {
             debug!(found=?query_set, "found query/group");
             if args.list_json {
                 query_set.json_print()?;
+            } else if args.plain {
+                query_set.plain_print(&args.endpoint, &args.tags);
             } else {
-                query_set.format_print();
+                query_set.format_print(&args.endpoint, &args.tags);
             }
+        } else if args.run_group {
+            let Some(group) = query_set.group else {
+                miette::bail!(
+                    "{} is not a group",
+                    args.endpoint.join(constants::PATH_SEPARATOR)
+                )
+            };
+            run_group(
+                &groups,
+                group.query_paths(&args.tags),
+                &args,
+                &env,
+                &mut config_store,
+                &config,
+            )
+            .await?;
+        } else if args.health {
+            let query_paths = match (&query_set.query, query_set.group) {
+                (Some(_), _) => vec![Vec::new()],
+                (None, Some(group)) => group.query_paths(&args.tags),
+                (None, None) => miette::bail!("Couldn't find query"),
+            };
+            run_health(
+                &groups,
+                query_paths,
+                &args,
+                &env,
+                &mut config_store,
+                &config,
+            )
+            .await?;
+        } else if args.explain {
+            explain::query(&groups, &args, &env, &config_store, &config)?.print(&config.redact);
+        } else if args.describe {
+            let name = args.endpoint.join(constants::PATH_SEPARATOR);
+            let Some(query) = query_set.query else {
+                miette::bail!("{name} is not a query")
+            };
+            let mut description = query
+                .describe(&name, &env)
+                .into_diagnostic()
+                .wrap_err("Couldn't serialize query")?;
+            config.redact.json(&mut description);
+            serde_json::to_writer_pretty(std::io::stdout(), &description)
+                .into_diagnostic()
+                .wrap_err("Couldn't write query description")?;
+            println!();
+        } else if args.race {
+            run_race(&groups, &args, &env, &mut config_store, &config).await?;
         } else {
             let Some(query_result) = query_set.query else {
-                if let Some(name) = query_set.name {
-                    miette::bail!("{name} is not an query")
+                if query_set.name.is_some() {
+                    miette::bail!(
+                        "{} is not an query",
+                        args.endpoint.join(constants::PATH_SEPARATOR)
+                    )
                 } else {
                     miette::bail!("Couldn't find query")
                 }
@@ -198,25 +285,350 @@ async fn main() -> miette::Result<()> {
             } else {
                 None
             };
+            let query_name = args.endpoint.join(constants::PATH_SEPARATOR);
+            let started = std::time::Instant::now();
             let response_body = query_result
-                .exec_with_args(&args, &env, &mut config_store, stdin_body)
-                .await?;
-
-            if let Some(body) = response_body {
-                if let Some(output_file) = args.output {
-                    std::fs::write(&output_file, body)
-                        .into_diagnostic()
-                        .wrap_err_with(|| {
-                            format!("Failed to write response body to {output_file:?}")
-                        })?
+                .exec_with_args(
+                    &query_name,
+                    &args,
+                    &env,
+                    &mut config_store,
+                    &config,
+                    stdin_body,
+                )
+                .await;
+            if args.notify {
+                output::notify(&query_name, &response_body, started.elapsed());
+            }
+            let response_body = response_body?;
+
+            if let Some(response) = response_body {
+                let response = match args.render {
+                    Some(render) => output::render(response, render, &args.fields)?,
+                    None => response,
+                };
+                if args.copy {
+                    output::copy(&response.body)?
+                }
+                if let Some(output_file) = response.output.clone().or_else(|| args.output.clone()) {
+                    output::write_file(response, &output_file, args.resume)?
                 } else {
-                    std::io::stdout()
-                        .write_all(&body)
-                        .into_diagnostic()
-                        .wrap_err("Failed to write body to stdout")?
+                    output::present(response, &config.output, args.open, args.raw)?
                 }
             }
         }
     }
     Ok(())
 }
+
+/// print changes done to the store values of given environment, oldest first
+fn print_store_log(store: &store::Store, env: &str) -> miette::Result<()> {
+    let entries = store
+        .audit_log()
+        .into_diagnostic()
+        .wrap_err("Couldn't read store audit log")?;
+    let mut table = parser::default_table_structure();
+    table.set_header(["time", "source", "key", "old", "new"]);
+    let unset = || "-".to_string();
+    table.add_rows(
+        entries
+            .into_iter()
+            .filter(|entry| entry.env == env)
+            .map(|entry| {
+                [
+                    entry.time,
+                    entry.source,
+                    entry.key,
+                    entry.old.unwrap_or_else(unset),
+                    entry.new.unwrap_or_else(unset),
+                ]
+            }),
+    );
+    println!("{table}");
+    Ok(())
+}
+
+/// write/push the metrics of the run if asked for
+async fn report_metrics(
+    metrics: &metrics::Metrics,
+    args: &Arguments,
+    config: &parser::Config,
+) -> miette::Result<()> {
+    if let Some(path) = &args.metrics_file {
+        metrics.write(path)?;
+    }
+    if let Some(gateway) = &args.push_metrics {
+        metrics.push(gateway, &config.project).await?;
+    }
+    Ok(())
+}
+
+/// send the query to all of its environments at once and print them in the order of response
+async fn run_race(
+    groups: &parser::Group,
+    args: &Arguments,
+    env: &str,
+    store: &mut store::Store,
+    config: &parser::Config,
+) -> miette::Result<()> {
+    let name = args.endpoint.join(constants::PATH_SEPARATOR);
+    let find_query = || groups.find(&args.endpoint).and_then(|result| result.query);
+    let Some(query) = find_query() else {
+        miette::bail!("{name} is not a query")
+    };
+    let environments = query.environment_names();
+    if environments.len() < 2 {
+        miette::bail!("{name} needs at least 2 environments to race, found {environments:?}")
+    }
+    let mut other_stores = Vec::new();
+    for environment in environments
+        .iter()
+        .filter(|environment| *environment != env)
+    {
+        other_stores.push((
+            environment.as_str(),
+            open_store(config, environment, args.no_persistent)?,
+        ));
+    }
+    let mut stores: Vec<_> = other_stores
+        .iter_mut()
+        .map(|(environment, store)| (*environment, store))
+        .collect();
+    if environments.iter().any(|environment| environment == env) {
+        stores.push((env, store));
+    }
+
+    let started = std::time::Instant::now();
+    let racers = stores.into_iter().map(|(environment, store)| {
+        let query = find_query();
+        let name = &name;
+        async move {
+            let result = match query {
+                Some(query) => {
+                    query
+                        .exec_with_args(name, args, environment, store, config, None)
+                        .await
+                }
+                None => Err(miette::miette!("Couldn't find query {name}")),
+            };
+            (environment, started.elapsed(), result)
+        }
+    });
+    let mut results = futures_util::future::join_all(racers).await;
+    results.sort_by_key(|(_, elapsed, _)| *elapsed);
+
+    let mut table = parser::default_table_structure();
+    table.set_header(["#", "environment", "status", "latency", "body"]);
+    // bodies are compared against the first successful response
+    let mut first_body = None;
+    let mut failed = 0;
+    for (position, (environment, elapsed, result)) in results.iter().enumerate() {
+        let (status, body) = match result {
+            Ok(Some(response)) => {
+                let matches = first_body.get_or_insert(&response.body) == &&response.body;
+                if !matches {
+                    failed += 1;
+                }
+                let body = if matches {
+                    comfy_table::Cell::new("same").fg(comfy_table::Color::Green)
+                } else {
+                    comfy_table::Cell::new("differs").fg(comfy_table::Color::Red)
+                };
+                (
+                    response.status.map(|s| s.to_string()).unwrap_or_default(),
+                    body,
+                )
+            }
+            Ok(None) => (String::new(), comfy_table::Cell::new("")),
+            Err(e) => {
+                failed += 1;
+                (
+                    String::new(),
+                    comfy_table::Cell::new(format!("FAIL {e}")).fg(comfy_table::Color::Red),
+                )
+            }
+        };
+        table.add_row([
+            comfy_table::Cell::new(position + 1),
+            comfy_table::Cell::new(environment),
+            comfy_table::Cell::new(status),
+            comfy_table::Cell::new(format!("{} ms", elapsed.as_millis())),
+            body,
+        ]);
+    }
+    println!("{table}");
+    if failed > 0 {
+        miette::bail!("{failed} environments failed or responded differently")
+    }
+    Ok(())
+}
+
+/// run the health check queries among given queries and print status and latency of each environment as table
+async fn run_health(
+    groups: &parser::Group,
+    query_paths: Vec<Vec<String>>,
+    args: &Arguments,
+    env: &str,
+    store: &mut store::Store,
+    config: &parser::Config,
+) -> miette::Result<()> {
+    let mut table = parser::default_table_structure();
+    table.set_header(["query", "environment", "status", "latency", "result"]);
+    let mut other_stores = std::collections::HashMap::new();
+    let mut metrics = metrics::Metrics::default();
+    let mut report = report::Report::default();
+    let mut failed = 0;
+    for query_path in query_paths {
+        let full_path: Vec<_> = args.endpoint.iter().cloned().chain(query_path).collect();
+        let name = full_path.join(constants::PATH_SEPARATOR);
+        let find_query = || groups.find(&full_path).and_then(|result| result.query);
+        let Some(query) = find_query() else {
+            miette::bail!("Couldn't find query {name}")
+        };
+        let Some(health) = query.health_check() else {
+            continue;
+        };
+        let environments = if args.all_envs {
+            query.environment_names()
+        } else {
+            vec![env.to_string()]
+        };
+        for environment in environments {
+            let env_store = if environment == env {
+                &mut *store
+            } else {
+                match other_stores.entry(environment.clone()) {
+                    std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+                    std::collections::hash_map::Entry::Vacant(entry) => {
+                        entry.insert(open_store(config, &environment, args.no_persistent)?)
+                    }
+                }
+            };
+            let Some(query) = find_query() else {
+                miette::bail!("Couldn't find query {name}")
+            };
+            info!("checking {name} in {environment}");
+            let started = std::time::Instant::now();
+            let result = query
+                .exec_with_args(&name, args, &environment, env_store, config, None)
+                .await;
+            let latency = started.elapsed();
+            let status = result
+                .as_ref()
+                .ok()
+                .and_then(|r| r.as_ref())
+                .and_then(|r| r.status);
+            let verdict = match &result {
+                Ok(_) => health.evaluate(status, latency),
+                Err(e) => Err(e.to_string()),
+            };
+            metrics.record(&name, &environment, verdict.is_ok(), latency);
+            report.record(&name, &environment, latency, verdict.clone().err());
+            // comfy table miscalculates width of ansi styled text, so cells are styled instead
+            let verdict = match verdict {
+                Ok(()) => comfy_table::Cell::new("PASS").fg(comfy_table::Color::Green),
+                Err(reason) => {
+                    failed += 1;
+                    comfy_table::Cell::new(format!("FAIL {reason}")).fg(comfy_table::Color::Red)
+                }
+            };
+            table.add_row([
+                comfy_table::Cell::new(&name),
+                comfy_table::Cell::new(environment),
+                comfy_table::Cell::new(status.map(|s| s.to_string()).unwrap_or_default()),
+                comfy_table::Cell::new(format!("{} ms", latency.as_millis())),
+                verdict,
+            ]);
+        }
+    }
+    if table.row_count() == 0 {
+        warn!("no health check queries found");
+        return Ok(());
+    }
+    println!("{table}");
+    report_metrics(&metrics, args, config).await?;
+    if let Some(path) = &args.report {
+        report.write(path, &config.project)?;
+    }
+    if failed > 0 {
+        miette::bail!("{failed} health checks failed")
+    }
+    Ok(())
+}
+
+/// run given queries one after the other and print the summary
+/// fails if any of the query fails or responds with error status
+async fn run_group(
+    groups: &parser::Group,
+    query_paths: Vec<Vec<String>>,
+    args: &Arguments,
+    env: &str,
+    store: &mut store::Store,
+    config: &parser::Config,
+) -> miette::Result<()> {
+    if query_paths.is_empty() {
+        warn!("no queries found to run");
+    }
+    let mut metrics = metrics::Metrics::default();
+    let mut report = report::Report::default();
+    let mut waterfall = waterfall::Waterfall::default();
+    let mut failed = 0;
+    let run_started = std::time::Instant::now();
+    for query_path in query_paths {
+        let full_path: Vec<_> = args.endpoint.iter().cloned().chain(query_path).collect();
+        let name = full_path.join(constants::PATH_SEPARATOR);
+        let Some(query) = groups.find(&full_path).and_then(|result| result.query) else {
+            miette::bail!("Couldn't find query {name}")
+        };
+        info!("running {name}");
+        let started = std::time::Instant::now();
+        let result = query
+            .exec_with_args(&name, args, env, store, config, None)
+            .await;
+        let latency = started.elapsed();
+        let (failure, status_str) = match result {
+            Ok(response) => {
+                let status = response.and_then(|r| r.status);
+                let status_str = status.map(|s| s.to_string()).unwrap_or_default();
+                if status.is_some_and(|status| status >= 400) {
+                    eprintln!("{} {name}: {status_str}", "FAIL".red().bold());
+                    (Some(format!("unexpected status {status_str}")), status_str)
+                } else {
+                    eprintln!("{} {name}: {status_str}", "PASS".green().bold());
+                    (None, status_str)
+                }
+            }
+            Err(e) => {
+                eprintln!("{} {name}: {e:?}", "FAIL".red().bold());
+                let causes: Vec<_> = e.chain().map(ToString::to_string).collect();
+                (Some(causes.join(": ")), "error".to_string())
+            }
+        };
+        if failure.is_some() {
+            failed += 1;
+        }
+        waterfall.record(
+            &name,
+            started - run_started,
+            latency,
+            status_str,
+            failure.is_some(),
+        );
+        metrics.record(&name, env, failure.is_none(), latency);
+        report.record(&name, env, latency, failure);
+    }
+    if !waterfall.is_empty() {
+        eprintln!("{}", waterfall.table());
+    }
+    report_metrics(&metrics, args, config).await?;
+    if let Some(path) = &args.report {
+        report.write(path, &config.project)?;
+    }
+    if let Some(path) = &args.waterfall {
+        waterfall.write(path, &config.project)?;
+    }
+    if failed > 0 {
+        miette::bail!("{failed} queries failed")
+    }
+    Ok(())
+}
@@ -0,0 +1,18 @@
+//! helpers shared by the generated text files, openmetrics, junit xml and html waterfall
+
+/// text written by the writer, writing to string never fails
+pub fn render(write: impl FnOnce(&mut String) -> std::fmt::Result) -> String {
+    let mut text = String::new();
+    let _ = write(&mut text);
+    text
+}
+
+/// value escaped for xml/html text and attributes
+pub fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\n', "&#10;")
+}
@@ -0,0 +1,184 @@
+//! summary of group and health check runs for prometheus, written as openmetrics file or pushed to pushgateway
+
+use std::{collections::BTreeMap, fmt::Write};
+
+use miette::{Context, IntoDiagnostic};
+use tracing::{debug, info};
+
+/// upper bounds of the latency histogram in seconds
+const BUCKETS: [f64; 8] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// count of observations in each bucket, not cumulative
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+/// results of the queries in a run, labelled by query name and environment
+#[derive(Debug, Default)]
+pub struct Metrics {
+    success: u64,
+    failure: u64,
+    latency: BTreeMap<(String, String), Histogram>,
+}
+
+impl Metrics {
+    pub fn record(&mut self, query: &str, env: &str, success: bool, latency: std::time::Duration) {
+        if success {
+            self.success += 1;
+        } else {
+            self.failure += 1;
+        }
+        let histogram = self
+            .latency
+            .entry((query.to_string(), env.to_string()))
+            .or_default();
+        let seconds = latency.as_secs_f64();
+        if let Some(bucket) = BUCKETS.iter().position(|bound| seconds <= *bound) {
+            histogram.buckets[bucket] += 1;
+        }
+        histogram.sum += seconds;
+        histogram.count += 1;
+    }
+
+    /// metrics in prometheus text format, which is also valid openmetrics without the `# EOF` marker
+    fn to_text(&self) -> String {
+        crate::markup::render(|text| self.write_text(text))
+    }
+
+    fn write_text(&self, text: &mut String) -> std::fmt::Result {
+        writeln!(
+            text,
+            "# HELP qwicket_run_queries number of queries in the run by result"
+        )?;
+        writeln!(text, "# TYPE qwicket_run_queries gauge")?;
+        writeln!(
+            text,
+            "qwicket_run_queries{{result=\"success\"}} {}",
+            self.success
+        )?;
+        writeln!(
+            text,
+            "qwicket_run_queries{{result=\"failure\"}} {}",
+            self.failure
+        )?;
+        writeln!(
+            text,
+            "# HELP qwicket_query_duration_seconds latency of the query"
+        )?;
+        writeln!(text, "# TYPE qwicket_query_duration_seconds histogram")?;
+        for ((query, env), histogram) in &self.latency {
+            let labels = format!("query=\"{}\",env=\"{}\"", escape(query), escape(env));
+            let mut cumulative = 0;
+            for (bound, count) in BUCKETS.iter().zip(histogram.buckets) {
+                cumulative += count;
+                writeln!(
+                    text,
+                    "qwicket_query_duration_seconds_bucket{{{labels},le=\"{bound}\"}} {cumulative}"
+                )?;
+            }
+            writeln!(
+                text,
+                "qwicket_query_duration_seconds_bucket{{{labels},le=\"+Inf\"}} {}",
+                histogram.count
+            )?;
+            writeln!(
+                text,
+                "qwicket_query_duration_seconds_sum{{{labels}}} {}",
+                histogram.sum
+            )?;
+            writeln!(
+                text,
+                "qwicket_query_duration_seconds_count{{{labels}}} {}",
+                histogram.count
+            )?;
+        }
+        Ok(())
+    }
+
+    /// write metrics to given file in openmetrics format
+    pub fn write(&self, path: &std::path::Path) -> miette::Result<()> {
+        debug!("writing metrics to {path:?}");
+        let mut text = self.to_text();
+        text.push_str("# EOF\n");
+        std::fs::write(path, text)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Couldn't write metrics to {path:?}"))
+    }
+
+    /// replace the metrics of the job in pushgateway
+    pub async fn push(&self, gateway: &reqwest::Url, job: &str) -> miette::Result<()> {
+        let url = push_url(gateway, job)?;
+        info!("pushing metrics to {url}");
+        reqwest::Client::new()
+            .put(url.clone())
+            .header(reqwest::header::CONTENT_TYPE, "text/plain; version=0.0.4")
+            .body(self.to_text())
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Couldn't push metrics to {url}"))?;
+        Ok(())
+    }
+}
+
+/// `metrics/job/<job>` under the gateway url, job is percent encoded
+fn push_url(gateway: &reqwest::Url, job: &str) -> miette::Result<reqwest::Url> {
+    let mut url = gateway.clone();
+    url.path_segments_mut()
+        .map_err(|()| miette::miette!("Invalid pushgateway url {gateway}"))?
+        .pop_if_empty()
+        .extend(["metrics", "job", job]);
+    Ok(url)
+}
+
+/// label values can't contain raw `\`, `"` and newlines
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_is_cumulative() {
+        let mut metrics = Metrics::default();
+        metrics.record(
+            "users.list",
+            "dev",
+            true,
+            std::time::Duration::from_millis(80),
+        );
+        metrics.record(
+            "users.list",
+            "dev",
+            false,
+            std::time::Duration::from_secs(20),
+        );
+        let text = metrics.to_text();
+        assert!(text.contains("qwicket_run_queries{result=\"success\"} 1"));
+        assert!(text.contains("qwicket_run_queries{result=\"failure\"} 1"));
+        let labels = "query=\"users.list\",env=\"dev\"";
+        assert!(text.contains(&format!("_bucket{{{labels},le=\"0.05\"}} 0")));
+        assert!(text.contains(&format!("_bucket{{{labels},le=\"0.1\"}} 1")));
+        assert!(text.contains(&format!("_bucket{{{labels},le=\"10\"}} 1")));
+        assert!(text.contains(&format!("_bucket{{{labels},le=\"+Inf\"}} 2")));
+        assert!(text.contains(&format!("_count{{{labels}}} 2")));
+    }
+
+    #[test]
+    fn job_is_encoded_in_push_url() {
+        let gateway = reqwest::Url::parse("http://localhost:9091/gateway/").unwrap();
+        assert_eq!(
+            push_url(&gateway, "api tests/nightly").unwrap().as_str(),
+            "http://localhost:9091/gateway/metrics/job/api%20tests%2Fnightly"
+        );
+    }
+}
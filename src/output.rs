@@ -0,0 +1,497 @@
+//! decides how a query response is presented to the user depending on its content type
+
+use std::{collections::HashMap, io::Write};
+
+use miette::{Context, IntoDiagnostic};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, trace, warn};
+
+/// what to do with a response body of given content type
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum Handler {
+    /// write the body to stdout as it is
+    Raw,
+    /// pretty print json body, falls back to raw if body is not valid json
+    Pretty,
+    /// write the body to a temporary file and print its path
+    Save,
+    /// write the body to a temporary file and open it with system opener
+    Open,
+    /// pipe the body to given command, ex: `["w3m", "-T", "text/html"]`
+    Command(Vec<String>),
+}
+
+/// format the response body is converted to before it is presented
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Render {
+    /// json array of objects as csv, nested fields are flattened as `a.b`
+    Csv,
+}
+
+/// response body along with its content type, used to decide how to present it
+#[derive(Debug)]
+pub struct QueryResponse {
+    /// protocol specific status code, ex: http status
+    pub status: Option<u16>,
+    pub content_type: Option<String>,
+    pub body: Vec<u8>,
+    /// file to write the body to, `--output` resolved in the output directory of the environment
+    pub output: Option<std::path::PathBuf>,
+}
+
+impl QueryResponse {
+    /// response which doesn't have any content type, always written as it is
+    pub fn raw(body: Vec<u8>) -> Self {
+        Self {
+            status: None,
+            content_type: None,
+            body,
+            output: None,
+        }
+    }
+}
+
+/// convert the body to given format
+/// fields select and order the columns, by default all the fields are used in sorted order
+pub fn render(
+    response: QueryResponse,
+    render: Render,
+    fields: &[String],
+) -> miette::Result<QueryResponse> {
+    match render {
+        Render::Csv => {
+            let value = serde_json::from_slice::<serde_json::Value>(&response.body)
+                .into_diagnostic()
+                .wrap_err("Couldn't render body as csv, body is not json")?;
+            let body = to_csv(&value, fields)?;
+            Ok(QueryResponse {
+                content_type: Some("text/csv".to_string()),
+                body,
+                ..response
+            })
+        }
+    }
+}
+
+fn to_csv(value: &serde_json::Value, fields: &[String]) -> miette::Result<Vec<u8>> {
+    let serde_json::Value::Array(items) = value else {
+        miette::bail!("Couldn't render body as csv, expected json array")
+    };
+    let rows: Vec<_> = items
+        .iter()
+        .map(|item| {
+            let mut row = std::collections::BTreeMap::new();
+            flatten(item, String::new(), &mut row);
+            row
+        })
+        .collect();
+    let columns: Vec<String> = if fields.is_empty() {
+        let columns: std::collections::BTreeSet<_> =
+            rows.iter().flat_map(|row| row.keys().cloned()).collect();
+        columns.into_iter().collect()
+    } else {
+        fields.to_vec()
+    };
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer
+        .write_record(&columns)
+        .into_diagnostic()
+        .wrap_err("Couldn't write csv header")?;
+    for row in &rows {
+        writer
+            .write_record(
+                columns
+                    .iter()
+                    .map(|column| row.get(column).map(String::as_str).unwrap_or_default()),
+            )
+            .into_diagnostic()
+            .wrap_err("Couldn't write csv row")?;
+    }
+    writer
+        .into_inner()
+        .into_diagnostic()
+        .wrap_err("Couldn't write csv")
+}
+
+/// nested objects are flattened with dotted keys, arrays are kept as json
+fn flatten(
+    value: &serde_json::Value,
+    prefix: String,
+    row: &mut std::collections::BTreeMap<String, String>,
+) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map {
+                let key = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{prefix}.{key}")
+                };
+                flatten(value, key, row);
+            }
+        }
+        serde_json::Value::String(text) => {
+            row.insert(prefix, text.clone());
+        }
+        serde_json::Value::Null => {
+            row.insert(prefix, String::new());
+        }
+        other => {
+            row.insert(prefix, other.to_string());
+        }
+    }
+}
+
+/// find the handler for given content type
+/// lookup order is exact match(`image/png`), type match(`image/*`), then `*/*`
+fn configured_handler<'h>(
+    handlers: &'h HashMap<String, Handler>,
+    content_type: &mime::Mime,
+) -> Option<&'h Handler> {
+    handlers
+        .get(content_type.essence_str())
+        .or_else(|| handlers.get(&format!("{}/*", content_type.type_())))
+        .or_else(|| handlers.get("*/*"))
+}
+
+/// handler used when nothing is configured for the content type
+fn default_handler(content_type: &mime::Mime) -> Handler {
+    if content_type.subtype() == mime::JSON || content_type.suffix() == Some(mime::JSON) {
+        Handler::Pretty
+    } else if content_type.type_() == mime::TEXT
+        || content_type.subtype() == mime::XML
+        || content_type.suffix() == Some(mime::XML)
+    {
+        Handler::Raw
+    } else {
+        Handler::Save
+    }
+}
+
+/// write response to the terminal/other programs according to the handlers
+///
+/// when stdout is not a terminal, body is written as it is so that it can be piped to other programs
+pub fn present(
+    response: QueryResponse,
+    handlers: &HashMap<String, Handler>,
+    open: bool,
+    raw: bool,
+) -> miette::Result<()> {
+    let content_type = response
+        .content_type
+        .as_deref()
+        .and_then(|c| c.parse::<mime::Mime>().ok());
+
+    let handler = match &content_type {
+        _ if open => Handler::Open,
+        Some(_) if !std::io::IsTerminal::is_terminal(&std::io::stdout()) => Handler::Raw,
+        Some(content_type) => configured_handler(handlers, content_type)
+            .cloned()
+            .unwrap_or_else(|| default_handler(content_type)),
+        None => Handler::Raw,
+    };
+    debug!(?content_type, ?handler, "presenting response");
+    let detected = infer::get(&response.body);
+
+    match handler {
+        Handler::Raw
+            if !raw
+                && std::io::IsTerminal::is_terminal(&std::io::stdout())
+                && std::str::from_utf8(&response.body).is_err() =>
+        {
+            let detected = detected
+                .map(|kind| {
+                    format!(
+                        "({}, suggested extension .{})",
+                        kind.mime_type(),
+                        kind.extension()
+                    )
+                })
+                .unwrap_or_default();
+            miette::bail!(
+                help = "save it with --output <file> or use --raw to write it anyway",
+                "Refusing to write binary body{detected} to terminal"
+            )
+        }
+        Handler::Raw => write_stdout(&response.body),
+        Handler::Pretty => {
+            let Ok(value) = serde_json::from_slice::<serde_json::Value>(&response.body) else {
+                debug!("body is not a valid json, writing raw body");
+                return write_stdout(&response.body);
+            };
+            let mut pretty = serde_json::to_vec_pretty(&value)
+                .into_diagnostic()
+                .wrap_err("Couldn't format json body")?;
+            pretty.push(b'\n');
+            write_stdout(&pretty)
+        }
+        Handler::Save => {
+            let path = save_temp(&response.body, content_type.as_ref(), detected)?;
+            match detected {
+                Some(kind) => eprintln!(
+                    "response body({}) saved to {}",
+                    kind.mime_type(),
+                    path.display()
+                ),
+                None => eprintln!("response body saved to {}", path.display()),
+            }
+            Ok(())
+        }
+        Handler::Open => {
+            let path = save_temp(&response.body, content_type.as_ref(), detected)?;
+            open_file(&path)
+        }
+        Handler::Command(command) => pipe_to_command(&command, &response.body),
+    }
+}
+
+/// write the response body to given file
+/// with resume, partial content(206) is appended to the file, any other response overwrites it
+pub fn write_file(
+    response: QueryResponse,
+    path: &std::path::Path,
+    resume: bool,
+) -> miette::Result<()> {
+    const PARTIAL_CONTENT: u16 = 206;
+    const RANGE_NOT_SATISFIABLE: u16 = 416;
+    let append = match response.status {
+        Some(PARTIAL_CONTENT) if resume => true,
+        Some(RANGE_NOT_SATISFIABLE) if resume => {
+            info!("range is not satisfiable, {path:?} is already complete");
+            return Ok(());
+        }
+        _ if resume => {
+            warn!("server didn't respond with partial content, overwriting {path:?}");
+            false
+        }
+        _ => false,
+    };
+    if let Some(parent) = path.parent().filter(|parent| !parent.exists()) {
+        debug!("creating output directory {parent:?}");
+        std::fs::create_dir_all(parent)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Couldn't create output directory {parent:?}"))?;
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Couldn't open {path:?}"))?;
+    file.write_all(&response.body)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to write response body to {path:?}"))
+}
+
+/// put the body on system clipboard, only text bodies can be copied
+/// binary body is only warned about, so that it is still presented
+pub fn copy(body: &[u8]) -> miette::Result<()> {
+    let Ok(text) = std::str::from_utf8(body) else {
+        warn!("body is not text, not copying it to clipboard");
+        return Ok(());
+    };
+    arboard::Clipboard::new()
+        .and_then(|mut clipboard| clipboard.set_text(text))
+        .into_diagnostic()
+        .wrap_err("Couldn't copy body to clipboard")?;
+    info!("copied {} bytes to clipboard", body.len());
+    Ok(())
+}
+
+/// desktop notification with status and duration of the finished query, failures are only logged
+pub fn notify(
+    query: &str,
+    result: &miette::Result<Option<QueryResponse>>,
+    elapsed: std::time::Duration,
+) {
+    let status = match result {
+        Ok(Some(QueryResponse {
+            status: Some(status),
+            ..
+        })) => http::StatusCode::from_u16(*status)
+            .map(|status| status.to_string())
+            .unwrap_or_else(|_| status.to_string()),
+        Ok(_) => "completed".to_string(),
+        Err(_) => "failed".to_string(),
+    };
+    let elapsed = std::time::Duration::from_millis(elapsed.as_millis() as u64);
+    let body = format!("{status} in {}", humantime::format_duration(elapsed));
+    debug!("sending notification: {query} {body}");
+    if let Err(e) = notify_rust::Notification::new()
+        .appname(env!("CARGO_PKG_NAME"))
+        .summary(query)
+        .body(&body)
+        .show()
+    {
+        warn!("Couldn't send notification: {e}");
+    }
+}
+
+fn write_stdout(body: &[u8]) -> miette::Result<()> {
+    std::io::stdout()
+        .write_all(body)
+        .into_diagnostic()
+        .wrap_err("Failed to write body to stdout")
+}
+
+/// guess file extension from the content type, `image/svg+xml` becomes `svg`
+/// type detected from the content is used if content type is missing or generic
+fn extension(content_type: Option<&mime::Mime>, detected: Option<infer::Type>) -> &str {
+    content_type
+        .map(|c| c.subtype().as_str())
+        .filter(|subtype| *subtype != "octet-stream")
+        .or(detected.map(|kind| kind.extension()))
+        .unwrap_or("bin")
+}
+
+fn save_temp(
+    body: &[u8],
+    content_type: Option<&mime::Mime>,
+    detected: Option<infer::Type>,
+) -> miette::Result<std::path::PathBuf> {
+    let path = std::env::temp_dir().join(format!(
+        "{}-{}.{}",
+        env!("CARGO_PKG_NAME"),
+        std::process::id(),
+        extension(content_type, detected)
+    ));
+    trace!("writing response body to {path:?}");
+    std::fs::write(&path, body)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Couldn't write response body to {path:?}"))?;
+    Ok(path)
+}
+
+fn open_file(path: &std::path::Path) -> miette::Result<()> {
+    let opener = if cfg!(target_os = "macos") {
+        "open"
+    } else {
+        "xdg-open"
+    };
+    info!("opening {path:?} with {opener}");
+    let status = std::process::Command::new(opener)
+        .arg(path)
+        .status()
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Couldn't run {opener}"))?;
+    if !status.success() {
+        miette::bail!("{opener} exited with error: {status}")
+    }
+    Ok(())
+}
+
+fn pipe_to_command(command: &[String], body: &[u8]) -> miette::Result<()> {
+    let Some((program, args)) = command.split_first() else {
+        miette::bail!("output handler command is empty")
+    };
+    debug!("piping response body to {program}");
+    let mut child = std::process::Command::new(program)
+        .args(args)
+        .stdin(std::process::Stdio::piped())
+        .spawn()
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Couldn't run output handler {program}"))?;
+    child
+        .stdin
+        .take()
+        .expect("Childs stdin is not open, eventhough it is piped")
+        .write_all(body)
+        .into_diagnostic()
+        .wrap_err("Failed to send body to output handler")?;
+    let status = child
+        .wait()
+        .into_diagnostic()
+        .wrap_err("Failed to wait for output handler")?;
+    if !status.success() {
+        miette::bail!("output handler exited with error: {status}")
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handler_lookup_order() {
+        let handlers = HashMap::from([
+            ("image/png".to_string(), Handler::Open),
+            ("image/*".to_string(), Handler::Save),
+            ("*/*".to_string(), Handler::Raw),
+        ]);
+        let png = "image/png".parse().unwrap();
+        let jpeg = "image/jpeg".parse().unwrap();
+        let html = "text/html; charset=utf-8".parse().unwrap();
+        assert_eq!(configured_handler(&handlers, &png), Some(&Handler::Open));
+        assert_eq!(configured_handler(&handlers, &jpeg), Some(&Handler::Save));
+        assert_eq!(configured_handler(&handlers, &html), Some(&Handler::Raw));
+    }
+
+    #[test]
+    fn render_csv() {
+        let value = serde_json::json!([
+            {"id": 1, "name": "a, b", "owner": {"id": 7}, "tags": ["x"]},
+            {"id": 2, "note": null}
+        ]);
+        let csv = to_csv(&value, &[]).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "id,name,note,owner.id,tags\n1,\"a, b\",,7,\"[\"\"x\"\"]\"\n2,,,,\n"
+        );
+        let fields = ["owner.id".to_string(), "id".to_string()];
+        let csv = to_csv(&value, &fields).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "owner.id,id\n7,1\n,2\n");
+        assert!(to_csv(&serde_json::json!({"id": 1}), &[]).is_err());
+    }
+
+    #[test]
+    fn extension_from_content() {
+        let zip = b"PK\x03\x04\x14\x00\x00\x00";
+        let octet_stream = "application/octet-stream".parse().unwrap();
+        let png = "image/png".parse().unwrap();
+        assert_eq!(extension(Some(&octet_stream), infer::get(zip)), "zip");
+        assert_eq!(extension(None, infer::get(zip)), "zip");
+        assert_eq!(extension(Some(&png), infer::get(zip)), "png");
+        assert_eq!(extension(None, infer::get(b"data")), "bin");
+    }
+
+    #[test]
+    fn default_handlers() {
+        let json = "application/problem+json".parse().unwrap();
+        let text = "text/plain".parse().unwrap();
+        let zip = "application/zip".parse().unwrap();
+        assert_eq!(default_handler(&json), Handler::Pretty);
+        assert_eq!(default_handler(&text), Handler::Raw);
+        assert_eq!(default_handler(&zip), Handler::Save);
+    }
+
+    #[test]
+    fn binary_body_not_copied() {
+        // clipboard isn't touched, so that the body is presented as usual
+        assert!(copy(&[0x89, b'P', b'N', b'G', 0xff, 0x00]).is_ok());
+    }
+
+    #[test]
+    fn resume_appends_partial_content() {
+        let path = std::env::temp_dir().join(format!("qwicket-resume-{}", std::process::id()));
+        std::fs::write(&path, "abc").unwrap();
+        let response = |status, body: &str| QueryResponse {
+            status: Some(status),
+            content_type: None,
+            body: body.as_bytes().to_vec(),
+            output: None,
+        };
+        write_file(response(206, "def"), &path, true).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "abcdef");
+        // already complete
+        write_file(response(416, "ignored"), &path, true).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "abcdef");
+        // server ignored the range, whole body is sent again
+        write_file(response(200, "full"), &path, true).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "full");
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,3 +1,4 @@
+use indexmap::IndexMap;
 use miette::{Context, IntoDiagnostic};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -14,6 +15,43 @@ pub struct Config {
     pub project: String,
     /// where to find for api's
     pub api_directory: std::path::PathBuf,
+    /// how to present responses, map of content type to output handler
+    #[serde(default)]
+    pub output: HashMap<String, crate::output::Handler>,
+    /// encrypted default store values shared with the project
+    pub seed: Option<crate::store::Seed>,
+    /// enable/disable hooks of all the queries
+    #[serde(default)]
+    pub hooks: crate::hook::Settings,
+    /// commands which provide values of the variables
+    #[serde(default)]
+    pub provider: HashMap<String, crate::secret::Provider>,
+    /// header, param and json field names masked in logs and inspected payloads
+    #[serde(default)]
+    pub redact: crate::redact::Redact,
+    /// keep a copy of the responses
+    #[serde(default)]
+    pub archive: crate::archive::Settings,
+    /// settings of all the queries, overridden by environments and queries
+    #[serde(default)]
+    pub defaults: Defaults,
+    /// which entries of the api directory are read
+    #[serde(default)]
+    pub traversal: crate::traversal::Settings,
+    /// where summaries of the executed queries are written
+    #[serde(default)]
+    pub sinks: Vec<crate::sink::Sink>,
+}
+
+/// project wide query settings
+#[derive(Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Defaults {
+    /// used when query doesn't have a timeout
+    pub timeout: Option<std::time::Duration>,
+    /// headers of all the queries, environment and query headers with same name take priority
+    #[serde(default)]
+    pub headers: IndexMap<String, String>,
 }
 
 impl Config {
@@ -22,17 +60,39 @@ impl Config {
         let current_package_version =
             semver::Version::parse(env!("CARGO_PKG_VERSION")).expect("cargo pkg is not semver?");
         debug!(version=?current_package_version, "current binary version");
-        let config = toml::from_str::<Self>(
-            &std::fs::read_to_string(file_path.as_ref())
+        let content = std::fs::read_to_string(file_path.as_ref())
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Couldn't read {:?}", file_path.as_ref()))?;
+        let mut config = match user_config()? {
+            Some(mut user_config) => {
+                let project_config = toml::from_str::<toml::Table>(&content)
+                    .into_diagnostic()
+                    .wrap_err("Couldn't deserialize config file")?;
+                merge_tables(&mut user_config, project_config);
+                toml::Value::Table(user_config)
+                    .try_into::<Self>()
+                    .into_diagnostic()
+                    .wrap_err("Couldn't deserialize config file merged with user config")?
+            }
+            None => toml::from_str::<Self>(&content)
                 .into_diagnostic()
-                .wrap_err_with(|| format!("Couldn't read {:?}", file_path.as_ref()))?,
-        )
-        .into_diagnostic()
-        .wrap_err("Couldn't deserialize config file")?;
+                .wrap_err("Couldn't deserialize config file")?,
+        };
+
+        let directory = file_path
+            .as_ref()
+            .parent()
+            .unwrap_or(std::path::Path::new(""));
+        for provider in config.provider.values_mut() {
+            provider.relative_to(directory);
+        }
 
         if current_package_version.major != config.version.major {
             error!(binary_version=?current_package_version, config_version=?config.version, "major versions of binary and config are not matching");
-            miette::bail!("Unsupported config set")
+            miette::bail!(
+                help = "if the config is older, --upgrade-config rewrites it for this version",
+                "Unsupported config set"
+            )
         }
 
         if current_package_version.major == 0
@@ -40,7 +100,10 @@ impl Config {
         {
             // 0 major version is beta stage so breaking changes are expected at minor versions
             error!(binary_version=?current_package_version, config_version=?config.version, "binary version is beta version and minor versions are not matching");
-            miette::bail!("Unsupported config set")
+            miette::bail!(
+                help = "if the config is older, --upgrade-config rewrites it for this version",
+                "Unsupported config set"
+            )
         }
         if current_package_version < config.version {
             warn!(binary_version=?current_package_version, config_version=?config.version, "binary version is smaller than config, things may not work as expected");
@@ -49,80 +112,399 @@ impl Config {
     }
 }
 
+/// user preferences from `<config dir>/qwicket/config.toml`, project config takes priority over these
+fn user_config() -> miette::Result<Option<toml::Table>> {
+    let Some(mut path) = dirs::config_dir() else {
+        return Ok(None);
+    };
+    path.push(env!("CARGO_PKG_NAME"));
+    path.push("config.toml");
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(e)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Couldn't read user config {path:?}"))
+        }
+    };
+    debug!("merging user config from {path:?}");
+    toml::from_str(&content)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Couldn't deserialize user config {path:?}"))
+        .map(Some)
+}
+
+/// tables are merged recursively, any other value of overrides replaces the base value
+/// keys are in the order of overrides followed by the keys only in base, so that project headers keep their order
+fn merge_tables(base: &mut toml::Table, overrides: toml::Table) {
+    let mut base_entries: IndexMap<String, toml::Value> =
+        std::mem::take(base).into_iter().collect();
+    for (key, value) in overrides {
+        let value = match (base_entries.shift_remove(&key), value) {
+            (Some(toml::Value::Table(mut base_table)), toml::Value::Table(table)) => {
+                merge_tables(&mut base_table, table);
+                toml::Value::Table(base_table)
+            }
+            (_, value) => value,
+        };
+        base.insert(key, value);
+    }
+    base.extend(base_entries);
+}
+
 #[derive(Debug, Deserialize, PartialEq, Eq, Clone, Serialize)]
-#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+#[serde(
+    remote = "Self",
+    tag = "type",
+    rename_all = "snake_case",
+    deny_unknown_fields
+)]
 enum GroupContent {
-    Http {
-        #[serde(default, rename = "query")]
-        queries: HashMap<String, agent::http::Query>,
-        #[serde(default, rename = "environment")]
-        environments: HashMap<String, agent::http::Environment>,
-    },
-    Generic,
+    Http(Content<agent::http::Http>),
+    /// queries run by external agents, see `agent::plugin`
+    Plugin(Content<agent::plugin::Plugin>),
+    Generic(Generic),
+}
+
+impl Default for GroupContent {
+    fn default() -> Self {
+        Self::Generic(Generic::default())
+    }
+}
+
+/// `type` is optional for generic groups
+impl<'de> Deserialize<'de> for GroupContent {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut table = toml::Table::deserialize(deserializer)?;
+        if !table.contains_key("type") {
+            table.insert("type".to_string(), "generic".into());
+        }
+        GroupContent::deserialize(toml::Value::Table(table)).map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for GroupContent {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        GroupContent::serialize(self, serializer)
+    }
+}
+
+/// group containing only other groups
+#[derive(Debug, Deserialize, PartialEq, Eq, Clone, Serialize, Default)]
+#[serde(deny_unknown_fields)]
+struct Generic {
+    /// shown while listing and matched while searching
+    description: Option<String>,
+    /// values common to the environments of all the sub groups(ex: `store` or `host`) irrespective of their type
+    /// values are applied to the queries which don't have them, like environments of the parent group
+    #[serde(default, rename = "environment")]
+    environments: IndexMap<String, serde_json::Map<String, serde_json::Value>>,
+}
+
+impl Generic {
+    /// environments which can be read as the environment of given type, rest are skipped with a warning
+    fn environments_as<E: serde::de::DeserializeOwned>(&self) -> IndexMap<String, E> {
+        self.environments
+            .iter()
+            .filter_map(|(name, values)| {
+                serde_json::from_value(serde_json::Value::Object(values.clone()))
+                    .inspect_err(|e| warn!("skipping generic environment {name}: {e}"))
+                    .ok()
+                    .map(|environment| (name.clone(), environment))
+            })
+            .collect()
+    }
+
+    fn format_print(&self, my_name: &Option<impl std::fmt::Debug>) {
+        if let Some(description) = &self.description {
+            match my_name {
+                Some(name) => eprintln!("{:?}: {description}", name.bold().green().bright()),
+                None => eprintln!("{description}"),
+            }
+        }
+        if !self.environments.is_empty() {
+            let names: Vec<_> = self.environments.keys().map(String::as_str).collect();
+            eprintln!("Shared environments: {}", names.join(", "));
+        }
+    }
+}
+
+/// queries and environments of a group whose protocol is handled by the agent
+#[derive(Debug, Deserialize, PartialEq, Eq, Clone, Serialize)]
+#[serde(bound = "", deny_unknown_fields)]
+struct Content<A: agent::Agent> {
+    #[serde(default, rename = "query")]
+    queries: IndexMap<String, A::Query>,
+    #[serde(default, rename = "environment")]
+    environments: IndexMap<String, A::Environment>,
+}
+
+impl<A: agent::Agent> Content<A> {
+    fn find_query(&self, name: &str) -> Option<Found<A>> {
+        let query = self.queries.get(name)?;
+        Some(Found {
+            environments: self.environments.clone(),
+            query: query.clone(),
+        })
+    }
+
+    /// queries which are not hidden and contains all the given tags along with their row, in file order
+    fn visible_queries(&self, tags: &[String]) -> Vec<(&str, Vec<String>)> {
+        self.queries
+            .iter()
+            .filter(|(_, query)| !A::is_hidden(query) && A::has_tags(query, tags))
+            .map(|(name, query)| (name.as_str(), A::query_row(query)))
+            .collect()
+    }
+
+    fn search(&self, pattern: &str) -> Vec<(&str, &'static str)> {
+        let mut matches: Vec<_> = self
+            .queries
+            .iter()
+            .filter_map(|(name, query)| {
+                let field = if name.to_lowercase().contains(pattern) {
+                    Some("name")
+                } else {
+                    A::matching_field(query, pattern, self.environments.values())
+                };
+                field.map(|field| (name.as_str(), field))
+            })
+            .collect();
+        matches.sort_unstable();
+        matches
+    }
+
+    /// rows are named by their dotted path, so that they can be copied as it is to run them
+    fn format_print(
+        &self,
+        my_name: &Option<impl std::fmt::Debug>,
+        prefix: &[String],
+        tags: &[String],
+    ) {
+        if self.queries.is_empty() {
+            return;
+        }
+        let mut subq_table = default_table_structure();
+        if let Some(name) = my_name {
+            eprintln!("{:?} Sub Queries", name.bold().green().bright());
+        } else {
+            eprintln!("Sub Queries");
+        }
+        let headers = ["name"].iter().chain(A::query_columns());
+        subq_table.set_header(headers);
+
+        let query_rows = self
+            .queries
+            .iter()
+            .filter(|(_, query)| !A::is_hidden(query) && A::has_tags(query, tags))
+            .map(|(name, query)| {
+                let name = dotted(prefix, name);
+                let name = if A::is_deprecated(query) {
+                    name.strike().to_string()
+                } else {
+                    name
+                };
+                [name].into_iter().chain(A::query_row(query))
+            });
+        subq_table.add_rows(query_rows);
+        eprintln!("{subq_table}");
+    }
+
+    /// used is false if neither this group nor its sub groups have queries
+    fn lint(
+        &self,
+        file: Option<&std::path::Path>,
+        key: &[String],
+        used: bool,
+        lints: &mut crate::lint::Lints,
+    ) {
+        let key_of = |kind: &str, name: &str| {
+            key.iter()
+                .cloned()
+                .chain([kind.to_string(), name.to_string()])
+                .collect::<Vec<_>>()
+        };
+        for (name, query) in &self.queries {
+            A::lint(query, file, &key_of("query", name), lints);
+        }
+        if used {
+            return;
+        }
+        for name in self.environments.keys() {
+            lints.add(
+                file,
+                key_of("environment", name),
+                format!("environment {name} is not used by any query"),
+                "remove it or add queries to the group or its sub groups",
+                None,
+            );
+        }
+    }
 }
 
 impl GroupContent {
     fn find_query(&self, name: &str) -> Option<QuerySearchResult> {
         match self {
-            GroupContent::Http {
-                queries,
-                environments,
-            } => {
-                let q = queries.get(name)?;
-                Some(QuerySearchResult::Http {
-                    environments: environments.clone(),
-                    query: q.clone(),
-                })
+            GroupContent::Http(content) => content
+                .find_query(name)
+                .map(|found| QuerySearchResult::Http(Box::new(found))),
+            GroupContent::Plugin(content) => {
+                content.find_query(name).map(QuerySearchResult::Plugin)
             }
-            GroupContent::Generic => None,
+            GroupContent::Generic(_) => None,
         }
     }
-    fn format_print(&self, my_name: &Option<impl std::fmt::Debug>) {
+
+    /// queries which are not hidden and contains all the given tags along with their row, in file order
+    fn visible_queries(&self, tags: &[String]) -> Vec<(&str, Vec<String>)> {
         match self {
-            GroupContent::Http { queries, .. } => {
-                if !queries.is_empty() {
-                    let mut subq_table = default_table_structure();
-                    if let Some(name) = my_name {
-                        eprintln!("{:?} Sub Queries", name.bold().green().bright());
-                    } else {
-                        eprintln!("Sub Queries");
-                    }
-                    let query_headers = agent::http::Query::headers();
-                    let headers = ["name"].iter().chain(query_headers);
-                    subq_table.set_header(headers);
-
-                    let query_rows = queries
-                        .iter()
-                        .map(|(name, query)| [name.clone()].into_iter().chain(query.to_row()));
-                    subq_table.add_rows(query_rows);
-                    eprintln!("{subq_table}");
-                }
-            }
-            GroupContent::Generic => {
-                eprintln!("Generic group there are no queries")
-            }
+            GroupContent::Http(content) => content.visible_queries(tags),
+            GroupContent::Plugin(content) => content.visible_queries(tags),
+            GroupContent::Generic(_) => Vec::new(),
         }
     }
-}
 
-impl Default for GroupContent {
-    fn default() -> Self {
-        Self::Generic
+    /// names of the queries matching the pattern along with the matched field
+    fn search(&self, pattern: &str) -> Vec<(&str, &'static str)> {
+        match self {
+            GroupContent::Http(content) => content.search(pattern),
+            GroupContent::Plugin(content) => content.search(pattern),
+            GroupContent::Generic(_) => Vec::new(),
+        }
+    }
+
+    fn format_print(
+        &self,
+        my_name: &Option<impl std::fmt::Debug>,
+        prefix: &[String],
+        tags: &[String],
+    ) {
+        match self {
+            GroupContent::Http(content) => content.format_print(my_name, prefix, tags),
+            GroupContent::Plugin(content) => content.format_print(my_name, prefix, tags),
+            GroupContent::Generic(generic) => generic.format_print(my_name),
+        }
+    }
+
+    fn lint(
+        &self,
+        file: Option<&std::path::Path>,
+        key: &[String],
+        used: bool,
+        lints: &mut crate::lint::Lints,
+    ) {
+        match self {
+            GroupContent::Http(content) => content.lint(file, key, used, lints),
+            GroupContent::Plugin(content) => content.lint(file, key, used, lints),
+            GroupContent::Generic(_) => {}
+        }
+    }
+
+    fn has_queries(&self) -> bool {
+        match self {
+            GroupContent::Http(content) => !content.queries.is_empty(),
+            GroupContent::Plugin(content) => !content.queries.is_empty(),
+            GroupContent::Generic(_) => false,
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            GroupContent::Http(_) => "http",
+            GroupContent::Plugin(_) => "plugin",
+            GroupContent::Generic(_) => "generic",
+        }
+    }
+
+    fn description(&self) -> Option<&str> {
+        match self {
+            GroupContent::Generic(generic) => generic.description.as_deref(),
+            GroupContent::Http(_) | GroupContent::Plugin(_) => None,
+        }
     }
 }
 
 #[derive(Debug, Deserialize, Default, PartialEq, Eq, Clone, Serialize)]
 pub struct Group {
     #[serde(default, rename = "group")]
-    sub_groups: HashMap<String, Group>,
-    // TODO: This will cause error if the file doesn't have `type`, eventhough default it is generic
+    sub_groups: IndexMap<String, Group>,
     #[serde(flatten)]
     info: GroupContent,
+    /// file in which this group is defined, None for directories without index file
+    #[serde(skip)]
+    source: Option<std::path::PathBuf>,
+}
+
+/// environment defined by one of the groups in the path of a query
+#[derive(Debug)]
+pub struct EnvironmentLayer {
+    /// file in which the group is defined
+    pub source: String,
+    /// name of the matched environment(or default) and the environment
+    pub environment: Option<(String, agent::http::Environment)>,
+}
+
+/// resolved query printed with `--describe` for editor tooling
+#[derive(Debug, Serialize)]
+#[serde(bound = "")]
+pub struct Description<'q, A: agent::Agent> {
+    name: &'q str,
+    /// all the environments query can run in
+    environments: Vec<String>,
+    /// environment used for current environment, None if query can't run in it
+    environment: Option<EnvironmentDescription<'q, A>>,
+    query: &'q A::Query,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(bound = "")]
+struct EnvironmentDescription<'q, A: agent::Agent> {
+    name: &'q str,
+    #[serde(flatten)]
+    environment: &'q A::Environment,
+}
+
+/// query which matched the search pattern
+#[derive(Debug)]
+pub struct SearchMatch {
+    /// path to the query from the top level group
+    pub path: Vec<String>,
+    /// file in which query is defined
+    pub source: Option<std::path::PathBuf>,
+    /// which part of the query matched
+    pub field: &'static str,
 }
 
 impl Group {
-    pub fn from_dir(path: impl AsRef<std::path::Path>) -> miette::Result<Self> {
+    /// read all the groups in the directory, same group defined more than once is an error unless shadowing is allowed
+    pub fn from_dir(
+        path: impl AsRef<std::path::Path>,
+        settings: &crate::traversal::Settings,
+        allow_shadowing: bool,
+    ) -> miette::Result<Self> {
+        let mut traversal = crate::traversal::Traversal::new(path.as_ref(), settings);
+        let mut shadowed = Vec::new();
+        let group = Self::read_dir(path.as_ref(), &mut traversal, &mut shadowed)?;
+        if !(shadowed.is_empty() || allow_shadowing) {
+            miette::bail!(
+                help = "rename one of them or use --allow-shadowing",
+                "Groups defined more than once:\n{}",
+                shadowed.join("\n")
+            )
+        }
+        shadowed.iter().for_each(|shadow| warn!("{shadow}"));
+        Ok(group)
+    }
+
+    /// group of the directory, overwritten groups are added to shadowed
+    fn read_dir(
+        path: impl AsRef<std::path::Path>,
+        traversal: &mut crate::traversal::Traversal,
+        shadowed: &mut Vec<String>,
+    ) -> miette::Result<Self> {
         trace!("reading dir: {:?}", path.as_ref());
+        traversal.enter(path.as_ref())?;
 
         let mut sub_dir_entries = std::fs::read_dir(path.as_ref())
             .into_diagnostic()
@@ -130,43 +512,46 @@ impl Group {
             .collect::<Result<Vec<_>, _>>()
             .into_diagnostic()
             .wrap_err_with(|| format!("Invalid file entry: {:?}", path.as_ref()))?;
+        // directory order is platform dependent, sub groups are kept in the order of names
+        sub_dir_entries.sort_by_key(std::fs::DirEntry::file_name);
 
         let mut group = sub_dir_entries
             .iter()
             .position(|e| e.file_name() == constants::GROUP_FILE_NAME)
-            .map(|file_index| sub_dir_entries.swap_remove(file_index).path()) // this will not panic because it is taken from position
+            .map(|file_index| sub_dir_entries.remove(file_index).path()) // this will not panic because it is taken from position
             .map(Self::from_file)
             .transpose()?
             .unwrap_or_default(); // create generic group
 
-        let subgroups = sub_dir_entries
-            .into_iter()
-            .filter(|entry| {
-                if !entry.path().ends_with("toml") {
-                    true
-                } else {
-                    warn!("ignoring non toml file: {:?}", entry.path());
-                    false
-                }
-            })
-            .map(|file| {
-                let name = file
-                    .path()
-                    .file_stem()
-                    .unwrap_or(file.file_name().as_os_str())
-                    .to_str()
-                    .ok_or(miette::miette!(
-                        "Invalid utf-8 file name: {:?}",
-                        file.file_name()
-                    ))?
-                    .to_string();
-                let subg = Self::from_path(file.path())?;
-                miette::Result::Ok((name, subg))
-            })
-            .collect::<Result<HashMap<_, _>, miette::Error>>()
-            .wrap_err("Couldn't read group")?;
-
-        group.sub_groups.extend(subgroups);
+        sub_dir_entries.retain(|entry| traversal.include(entry));
+        for file in sub_dir_entries {
+            let name = file
+                .path()
+                .file_stem()
+                .unwrap_or(file.file_name().as_os_str())
+                .to_str()
+                .ok_or(miette::miette!(
+                    "Invalid utf-8 file name: {:?}",
+                    file.file_name()
+                ))
+                .wrap_err("Couldn't read group")?
+                .to_string();
+            let subg = Self::from_path(file.path(), traversal, shadowed)
+                .wrap_err("Couldn't read group")?;
+            if let Some(previous) = group.sub_groups.get(&name) {
+                // directories without index file doesn't have source
+                let previous = previous
+                    .source
+                    .clone()
+                    .unwrap_or_else(|| path.as_ref().join(&name));
+                shadowed.push(format!(
+                    "group {name:?} of {previous:?} is shadowed by {:?}",
+                    file.path()
+                ));
+            }
+            group.sub_groups.insert(name, subg);
+        }
+        traversal.leave();
 
         Ok(group)
     }
@@ -179,21 +564,123 @@ impl Group {
             .into_diagnostic()
             .wrap_err_with(|| format!("Couldn't read file: {:?}", path.as_ref()))?;
 
-        let e = toml::from_str(file_content.as_str());
-        match e {
-            Ok(o) => Ok(o),
-            Err(e) => Err(e)
-                .into_diagnostic()
-                .wrap_err_with(|| format!("Couldn't deserialize {:?}", path.as_ref())),
+        let mut group: Self = toml::from_str(file_content.as_str())
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Couldn't deserialize {:?}", path.as_ref()))?;
+        group.set_source(path.as_ref());
+        Ok(group)
+    }
+
+    /// set source file of this group and all the nested groups defined in same file
+    fn set_source(&mut self, path: &std::path::Path) {
+        self.source = Some(path.to_path_buf());
+        self.sub_groups
+            .values_mut()
+            .for_each(|sub_group| sub_group.set_source(path));
+    }
+
+    /// paths of all the groups and non hidden queries in this tree
+    fn paths(&self) -> Vec<Vec<String>> {
+        let mut paths: Vec<_> = self
+            .info
+            .visible_queries(&[])
+            .into_iter()
+            .map(|(name, _)| vec![name.to_string()])
+            .collect();
+        for (name, sub_group) in &self.sub_groups {
+            paths.push(vec![name.clone()]);
+            paths.extend(
+                sub_group
+                    .paths()
+                    .into_iter()
+                    .map(|path| [name.clone()].into_iter().chain(path).collect()),
+            );
+        }
+        paths
+    }
+
+    /// paths in the tree which are close to given path, used to suggest alternatives on typo
+    /// closest match comes first
+    pub fn suggest(&self, search_path: &[impl AsRef<str>]) -> Vec<Vec<String>> {
+        const MIN_SIMILARITY: f64 = 0.8;
+        const MAX_SUGGESTIONS: usize = 3;
+        let search = search_path
+            .iter()
+            .map(AsRef::as_ref)
+            .collect::<Vec<_>>()
+            .join(constants::PATH_SEPARATOR);
+        let mut candidates: Vec<_> = self
+            .paths()
+            .into_iter()
+            .filter_map(|path| {
+                let candidate = path.join(constants::PATH_SEPARATOR);
+                let similarity = if candidate.starts_with(&search) {
+                    1.0
+                } else {
+                    strsim::normalized_damerau_levenshtein(&search, &candidate)
+                };
+                (similarity >= MIN_SIMILARITY).then_some((similarity, path))
+            })
+            .collect();
+        candidates.sort_by(|(s1, p1), (s2, p2)| s2.total_cmp(s1).then_with(|| p1.cmp(p2)));
+        candidates
+            .into_iter()
+            .take(MAX_SUGGESTIONS)
+            .map(|(_, path)| path)
+            .collect()
+    }
+
+    /// find all the queries whose name, path, description or url contains the pattern(case insensitive)
+    /// and generic groups whose description contains it
+    pub fn search(&self, pattern: &str) -> Vec<SearchMatch> {
+        let mut matches = Vec::new();
+        self.search_into(&pattern.to_lowercase(), &mut Vec::new(), &mut matches);
+        matches
+    }
+
+    fn search_into(&self, pattern: &str, prefix: &mut Vec<String>, matches: &mut Vec<SearchMatch>) {
+        // generic groups are matched by their description, top level group has no path to show
+        if !prefix.is_empty()
+            && self
+                .info
+                .description()
+                .is_some_and(|description| description.to_lowercase().contains(pattern))
+        {
+            matches.push(SearchMatch {
+                path: prefix.clone(),
+                source: self.source.clone(),
+                field: "group description",
+            });
+        }
+        matches.extend(
+            self.info
+                .search(pattern)
+                .into_iter()
+                .map(|(name, field)| SearchMatch {
+                    path: prefix.iter().cloned().chain([name.to_string()]).collect(),
+                    source: self.source.clone(),
+                    field,
+                }),
+        );
+        let mut sub_groups: Vec<_> = self.sub_groups.iter().collect();
+        sub_groups.sort_unstable_by_key(|(name, _)| *name);
+        for (name, sub_group) in sub_groups {
+            prefix.push(name.clone());
+            sub_group.search_into(pattern, prefix, matches);
+            prefix.pop();
         }
     }
 
     /// unsure about the path, it could be directory in which case it doesn't contains any environments or queries
     /// or file which can optionally have these
-    pub fn from_path(path: impl AsRef<std::path::Path>) -> miette::Result<Self> {
+    fn from_path(
+        path: impl AsRef<std::path::Path>,
+        traversal: &mut crate::traversal::Traversal,
+        shadowed: &mut Vec<String>,
+    ) -> miette::Result<Self> {
         let path = path.as_ref();
         if path.is_dir() {
-            Self::from_dir(path)
+            Self::read_dir(path, traversal, shadowed)
         } else if path.is_file() {
             Self::from_file(path)
         } else {
@@ -201,6 +688,19 @@ impl Group {
         }
     }
 
+    /// `a.b.c` is same as `a b c`, unless there is a top level query or group named `a.b.c`
+    pub fn split_dotted(&self, endpoint: Vec<String>) -> Vec<String> {
+        match endpoint.as_slice() {
+            [key] if self.info.find_query(key).is_none() && !self.sub_groups.contains_key(key) => {
+                key.split(constants::PATH_SEPARATOR)
+                    .filter(|key| !key.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            }
+            _ => endpoint,
+        }
+    }
+
     /// find given query/group from the tree
     pub fn find<'a, 's>(
         &'a self,
@@ -249,104 +749,364 @@ impl Group {
         }
     }
 
-    fn headers() -> &'static [&'static str] {
-        &["kind"]
+    /// http environment of the group at given path, merged with its parent group environments
+    pub fn find_environment(
+        &self,
+        search_path: &[impl AsRef<str>],
+        env: &str,
+    ) -> Option<agent::http::Environment> {
+        let local_env = match &self.info {
+            GroupContent::Http(content) => content
+                .environments
+                .get(env)
+                .or_else(|| content.environments.get(constants::DEFAULT_ENVIRONMENT))
+                .cloned(),
+            GroupContent::Generic(generic) => {
+                let mut environments = generic.environments_as();
+                environments
+                    .shift_remove(env)
+                    .or_else(|| environments.shift_remove(constants::DEFAULT_ENVIRONMENT))
+            }
+            GroupContent::Plugin(_) => None,
+        };
+        let Some((key, rest)) = search_path.split_first() else {
+            return local_env;
+        };
+        let sub_group_env = self
+            .sub_groups
+            .get(key.as_ref())?
+            .find_environment(rest, env);
+        match (sub_group_env, local_env) {
+            (Some(mut sub_group_env), Some(local_env)) => {
+                agent::http::Environment::apply(&mut sub_group_env, &local_env);
+                Some(sub_group_env)
+            }
+            (sub_group_env, local_env) => sub_group_env.or(local_env),
+        }
     }
-    fn to_row(&self) -> Vec<String> {
-        match &self.info {
-            GroupContent::Http { .. } => {
-                vec!["http".to_string()]
+
+    /// check queries and environments of this group and its sub groups for common mistakes
+    /// key is the path of this group in its file
+    pub fn lint(&self, key: &[String], lints: &mut crate::lint::Lints) {
+        self.info
+            .lint(self.source.as_deref(), key, self.has_queries(), lints);
+        for (name, sub_group) in &self.sub_groups {
+            // sub groups from other files start at the top of their file
+            if sub_group.source.is_some() && sub_group.source != self.source {
+                sub_group.lint(&[], lints);
+            } else {
+                let key: Vec<_> = key
+                    .iter()
+                    .cloned()
+                    .chain(["group".to_string(), name.clone()])
+                    .collect();
+                sub_group.lint(&key, lints);
             }
-            GroupContent::Generic => vec!["generic".to_string()],
         }
     }
+
+    /// file of the http group at given path and the key of the group in that file, to add queries to it
+    pub fn http_location(
+        &self,
+        group_path: &[impl AsRef<str>],
+    ) -> miette::Result<(std::path::PathBuf, Vec<String>)> {
+        let mut group = self;
+        let mut key = Vec::new();
+        for name in group_path {
+            let name = name.as_ref();
+            let Some(sub_group) = group.sub_groups.get(name) else {
+                miette::bail!("no such group {name}")
+            };
+            // sub groups from other files start at the top of their file
+            if sub_group.source != group.source {
+                key = Vec::new();
+            } else {
+                key.extend(["group".to_string(), name.to_string()]);
+            }
+            group = sub_group;
+        }
+        if !matches!(group.info, GroupContent::Http(_)) {
+            miette::bail!(
+                "queries can only be added to http groups, this is {} group",
+                group.info.kind()
+            )
+        }
+        let Some(source) = &group.source else {
+            miette::bail!(
+                "group doesn't have a file, add {}",
+                constants::GROUP_FILE_NAME
+            )
+        };
+        Ok((source.clone(), key))
+    }
+
+    /// whether this group or any of its sub groups has queries
+    fn has_queries(&self) -> bool {
+        self.info.has_queries() || self.sub_groups.values().any(Group::has_queries)
+    }
+
+    /// environments of the group at given path and its parent groups, innermost first
+    pub fn environment_layers(
+        &self,
+        search_path: &[impl AsRef<str>],
+        env: &str,
+    ) -> Vec<EnvironmentLayer> {
+        let mut layers = match search_path.split_first() {
+            Some((key, rest)) => self
+                .sub_groups
+                .get(key.as_ref())
+                .map(|group| group.environment_layers(rest, env))
+                .unwrap_or_default(),
+            None => Vec::new(),
+        };
+        let environments = match &self.info {
+            GroupContent::Http(content) => content.environments.clone(),
+            GroupContent::Generic(generic) => generic.environments_as(),
+            GroupContent::Plugin(_) => IndexMap::new(),
+        };
+        let environment = [env, constants::DEFAULT_ENVIRONMENT]
+            .into_iter()
+            .find_map(|name| Some((name.to_string(), environments.get(name)?.clone())));
+        layers.push(EnvironmentLayer {
+            source: self.source.as_ref().map_or_else(
+                || "directory group".to_string(),
+                |source| source.display().to_string(),
+            ),
+            environment,
+        });
+        layers
+    }
+
+    fn headers() -> &'static [&'static str] {
+        &["kind", "description"]
+    }
+    fn to_row(&self) -> Vec<String> {
+        vec![
+            self.info.kind().to_string(),
+            self.info.description().unwrap_or_default().to_string(),
+        ]
+    }
 }
 
 #[derive(Debug, Serialize)]
 pub enum QuerySearchResult {
-    Http {
-        environments: HashMap<String, agent::http::Environment>,
-        query: agent::http::Query,
-    },
+    Http(Box<Found<agent::http::Http>>),
+    Plugin(Found<agent::plugin::Plugin>),
+}
+
+/// query along with the environments of its group, merged with the environments of the parent groups
+#[derive(Debug, Serialize)]
+#[serde(bound = "")]
+pub struct Found<A: agent::Agent> {
+    pub environments: IndexMap<String, A::Environment>,
+    pub query: A::Query,
+}
+
+impl<A: agent::Agent> Found<A> {
+    fn apply_group_env(&mut self, parent: &IndexMap<String, A::Environment>) {
+        // environments missing in this group are filled from its default environment
+        let default_env = self
+            .environments
+            .get(constants::DEFAULT_ENVIRONMENT)
+            .cloned();
+        parent.iter().for_each(|(key, parent_env)| {
+            self.environments
+                .entry(key.to_owned())
+                .and_modify(|cur_env| A::inherit(cur_env, parent_env)) // if the current env is not empty then just apply missing fields from parent env
+                .or_insert_with(|| match &default_env {
+                    Some(default_env) => {
+                        let mut cur_env = default_env.clone();
+                        A::inherit(&mut cur_env, parent_env);
+                        cur_env
+                    }
+                    None => parent_env.clone(), // there is no such env so just copy parent env
+                });
+        });
+    }
+
+    fn format_print(&self) {
+        eprintln!("{}", self.query);
+
+        eprintln!("Environments:");
+        let mut table = default_table_structure();
+        let headers = ["name"].iter().chain(A::environment_columns());
+
+        table.set_header(headers);
+        let rows = self
+            .environments
+            .iter()
+            .map(|(name, e)| [name.clone()].into_iter().chain(A::environment_row(e)));
+        table.add_rows(rows);
+        eprintln!("{table}");
+    }
+
+    /// row of the query, None if it is hidden or doesn't contain all the tags
+    fn row(&self, tags: &[String]) -> Option<Vec<String>> {
+        (!A::is_hidden(&self.query) && A::has_tags(&self.query, tags))
+            .then(|| A::query_row(&self.query))
+    }
+
+    fn environment_names(&self) -> Vec<String> {
+        let mut names: Vec<_> = self
+            .environments
+            .keys()
+            .filter(|name| *name != constants::DEFAULT_ENVIRONMENT)
+            .cloned()
+            .collect();
+        if names.is_empty()
+            && self
+                .environments
+                .contains_key(constants::DEFAULT_ENVIRONMENT)
+        {
+            names.push(constants::DEFAULT_ENVIRONMENT.to_string());
+        }
+        names.sort_unstable();
+        names
+    }
+
+    fn describe<'q>(&'q self, name: &'q str, env: &'q str) -> Description<'q, A> {
+        let environment = self
+            .environments
+            .get_key_value(env)
+            .or_else(|| {
+                self.environments
+                    .get_key_value(constants::DEFAULT_ENVIRONMENT)
+            })
+            .map(|(name, environment)| EnvironmentDescription { name, environment });
+        Description {
+            name,
+            environments: self.environment_names(),
+            environment,
+            query: &self.query,
+        }
+    }
+
+    async fn execute(
+        mut self,
+        name: &str,
+        args: &crate::Arguments,
+        env: &str,
+        store: &mut crate::store::Store,
+        config: &Config,
+        stdin: Option<&[u8]>,
+    ) -> miette::Result<Option<crate::output::QueryResponse>> {
+        let found_env = self.environments.shift_remove(env).or_else(|| {
+            let default_env = self
+                .environments
+                .shift_remove(constants::DEFAULT_ENVIRONMENT)?;
+            warn!(
+                "Couldn't find environment {env}, using {} environment",
+                constants::DEFAULT_ENVIRONMENT
+            );
+            Some(default_env)
+        });
+        let Some(env) = found_env else {
+            let available_env: Vec<_> = self.environments.keys().collect();
+            miette::bail!(
+                help = format!("set {}", crate::constants::KEY_CURRENT_ENVIRONMENT),
+                "Couldn't find environment {env}, available are {available_env:?}"
+            )
+        };
+        // store changes of the query are applied only if whole query succeeds
+        store.begin(name);
+        let result = A::execute(self.query, name, env, store, args, config, stdin).await;
+        if result.is_ok() {
+            store.commit();
+        } else {
+            store.rollback();
+        }
+        result
+    }
 }
 
 impl QuerySearchResult {
     fn apply_group_env(&mut self, group: &GroupContent) {
         match (self, group) {
-            (
-                QuerySearchResult::Http { environments, .. },
-                GroupContent::Http {
-                    environments: parent_env,
-                    ..
-                },
-            ) => {
-                parent_env.iter().for_each(|(key, parent_env)| {
-                    environments
-                        .entry(key.to_owned())
-                        .and_modify(|cur_env| cur_env.apply(parent_env)) // if the current env is not empty then just apply missing fields from parent env
-                        .or_insert_with(|| parent_env.clone()); // there is no such env so just copy parent env
-                });
+            (QuerySearchResult::Http(found), GroupContent::Http(parent)) => {
+                found.apply_group_env(&parent.environments)
+            }
+            (QuerySearchResult::Plugin(found), GroupContent::Plugin(parent)) => {
+                found.apply_group_env(&parent.environments)
+            }
+            (QuerySearchResult::Http(found), GroupContent::Generic(parent)) => {
+                found.apply_group_env(&parent.environments_as())
+            }
+            (QuerySearchResult::Plugin(found), GroupContent::Generic(parent)) => {
+                found.apply_group_env(&parent.environments_as())
+            }
+            (_, GroupContent::Http(_) | GroupContent::Plugin(_)) => {
+                debug!("parent group is of different type, ignoring its environments")
             }
-            (_, GroupContent::Generic) => debug!("parent group is generic group, ignoring"),
         }
     }
 
     fn format_print(&self) {
         match self {
-            QuerySearchResult::Http {
-                environments,
-                query,
-            } => {
-                let formatted_query = query.to_string();
-                eprintln!("{formatted_query}");
-
-                eprintln!("Environments:");
-                let mut table = default_table_structure();
-                let env_headers = agent::http::Environment::headers();
-                let headers = ["name"].iter().chain(env_headers);
-
-                table.set_header(headers);
-                let rows = environments
-                    .iter()
-                    .map(|(name, e)| [name.clone()].into_iter().chain(e.to_row()));
-                table.add_rows(rows);
-                eprintln!("{table}");
-            }
+            QuerySearchResult::Http(found) => found.format_print(),
+            QuerySearchResult::Plugin(found) => found.format_print(),
+        }
+    }
+
+    /// row of the query, None if it is hidden or doesn't contain all the tags
+    fn row(&self, tags: &[String]) -> Option<Vec<String>> {
+        match self {
+            QuerySearchResult::Http(found) => found.row(tags),
+            QuerySearchResult::Plugin(found) => found.row(tags),
+        }
+    }
+
+    /// thresholds if the query is a health check
+    pub fn health_check(&self) -> Option<agent::http::HealthCheck> {
+        match self {
+            QuerySearchResult::Http(found) => found.query.health_check().cloned(),
+            QuerySearchResult::Plugin(_) => None,
         }
     }
+
+    /// names of the environments the query can run in, `default` is included only if there are no others
+    pub fn environment_names(&self) -> Vec<String> {
+        match self {
+            QuerySearchResult::Http(found) => found.environment_names(),
+            QuerySearchResult::Plugin(found) => found.environment_names(),
+        }
+    }
+
+    /// query with its environment merged from parent groups, values are not substituted
+    /// environment falls back to default like while executing
+    pub fn describe(&self, name: &str, env: &str) -> serde_json::Result<serde_json::Value> {
+        match self {
+            QuerySearchResult::Http(found) => serde_json::to_value(found.describe(name, env)),
+            QuerySearchResult::Plugin(found) => serde_json::to_value(found.describe(name, env)),
+        }
+    }
+
+    /// name is used to record who changed the store values
     pub async fn exec_with_args(
         self,
+        name: &str,
         args: &crate::Arguments,
         env: &str,
         store: &mut crate::store::Store,
+        config: &Config,
         stdin: Option<&[u8]>,
-    ) -> miette::Result<Option<QueryResponse>> {
+    ) -> miette::Result<Option<crate::output::QueryResponse>> {
         match self {
-            QuerySearchResult::Http {
-                mut environments,
-                query,
-            } => {
-                let Some(env) = environments.remove(env) else {
-                    let available_env: Vec<_> = environments.keys().collect();
-                    miette::bail!(
-                        help = format!("set {}", crate::constants::KEY_CURRENT_ENVIRONMENT),
-                        "Couldn't find environment {env}, available are {available_env:?}"
-                    )
-                };
-                query.execute(env, store, args, stdin).await
+            QuerySearchResult::Http(found) => {
+                found.execute(name, args, env, store, config, stdin).await
+            }
+            QuerySearchResult::Plugin(found) => {
+                found.execute(name, args, env, store, config, stdin).await
             }
         }
     }
 }
 
-pub type QueryResponse = Vec<u8>;
-
 /// set of environments and query result
 /// search result can be another group or a query
 #[derive(Debug, Serialize)]
 pub struct GroupSearchResult<'g> {
     /// search result can optionally contain a group
-    sub_groups: &'g HashMap<String, Group>,
+    sub_groups: &'g IndexMap<String, Group>,
     queries: &'g GroupContent,
 }
 
@@ -359,8 +1119,36 @@ impl<'g> From<&'g Group> for GroupSearchResult<'g> {
     }
 }
 
-impl GroupSearchResult<'_> {
-    fn format_print(&self) {
+impl<'g> GroupSearchResult<'g> {
+    /// paths of all the queries in this group and its sub groups, relative to this group
+    pub fn query_paths(&self, tags: &[String]) -> Vec<Vec<String>> {
+        self.queries(tags)
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect()
+    }
+
+    /// all the queries in this group and its sub groups along with their path relative to this group
+    /// queries of the group come first, then sub groups, each in file order
+    fn queries(&self, tags: &[String]) -> Vec<(Vec<String>, Vec<String>)> {
+        let mut queries: Vec<_> = self
+            .queries
+            .visible_queries(tags)
+            .into_iter()
+            .map(|(name, row)| (vec![name.to_string()], row))
+            .collect();
+        for (name, sub_group) in self.sub_groups {
+            queries.extend(
+                GroupSearchResult::from(sub_group)
+                    .queries(tags)
+                    .into_iter()
+                    .map(|(path, row)| ([name.clone()].into_iter().chain(path).collect(), row)),
+            );
+        }
+        queries
+    }
+
+    fn format_print(&self, prefix: &[String]) {
         if !self.sub_groups.is_empty() {
             let mut subg_table = default_table_structure();
 
@@ -370,14 +1158,24 @@ impl GroupSearchResult<'_> {
             let subg_rows = self
                 .sub_groups
                 .iter()
-                .map(|(name, subg)| [name.clone()].into_iter().chain(subg.to_row()));
+                .map(|(name, subg)| [dotted(prefix, name)].into_iter().chain(subg.to_row()));
             subg_table.add_rows(subg_rows);
             eprintln!("{subg_table}");
         }
     }
 }
 
-fn default_table_structure() -> comfy_table::Table {
+/// name under the prefix as it is given on command line, ex: `users.admin.create`
+fn dotted(prefix: &[String], name: &str) -> String {
+    prefix
+        .iter()
+        .map(String::as_str)
+        .chain([name])
+        .collect::<Vec<_>>()
+        .join(constants::PATH_SEPARATOR)
+}
+
+pub fn default_table_structure() -> comfy_table::Table {
     let mut table = comfy_table::Table::new();
     table
         .load_preset(comfy_table::presets::UTF8_FULL)
@@ -393,25 +1191,62 @@ pub struct SearchResult<'g, 'i> {
 }
 
 impl<'i> SearchResult<'_, 'i> {
-    pub fn format_print(&'i self) {
+    /// prefix is the path of this search result, names are printed as dotted paths
+    pub fn format_print(&'i self, prefix: &[String], tags: &[String]) {
+        let path = (!prefix.is_empty()).then(|| prefix.join(constants::PATH_SEPARATOR));
         if let Some(query) = &self.query {
-            let name = self.name.expect("name cannot be None for matched query");
+            let name = path
+                .as_ref()
+                .expect("path cannot be empty for matched query");
             eprintln!("Query: \"{}\"", name.green().bold().bright());
             query.format_print();
         };
         if let Some(group) = &self.group {
             if !group.sub_groups.is_empty() {
-                if let Some(name) = self.name {
+                if let Some(name) = &path {
                     eprintln!("\"{}\" Sub Groups", name.green().bold().bright());
                 } else {
                     eprintln!("Sub Groups");
                 }
-                group.format_print()
+                group.format_print(prefix)
             }
-            group.queries.format_print(&self.name);
+            group.queries.format_print(&path, prefix, tags);
         }
     }
 
+    /// print one uncolored `name METHOD path` line per query, sorted so that the output can be diffed
+    /// prefix is the path of this search result
+    pub fn plain_print(&self, prefix: &[String], tags: &[String]) {
+        for line in self.plain_lines(prefix, tags) {
+            println!("{line}");
+        }
+    }
+
+    /// lines are sorted by the full path of the queries, irrespective of the groups they are in
+    fn plain_lines(&self, prefix: &[String], tags: &[String]) -> Vec<String> {
+        let mut lines = Vec::new();
+        if let Some(row) = self.query.as_ref().and_then(|query| query.row(tags)) {
+            lines.push((prefix.to_vec(), row));
+        }
+        if let Some(group) = &self.group {
+            lines.extend(
+                group
+                    .queries(tags)
+                    .into_iter()
+                    .map(|(path, row)| (prefix.iter().cloned().chain(path).collect(), row)),
+            );
+        }
+        let mut lines: Vec<_> = lines
+            .into_iter()
+            .map(|(path, row)| (path.join(constants::PATH_SEPARATOR), row.join(" ")))
+            .collect();
+        lines.sort_unstable();
+        lines
+            .into_iter()
+            .map(|(path, row)| format!("{path} {row}"))
+            .collect()
+    }
+
     pub fn json_print(&self) -> miette::Result<()> {
         let stdout = std::io::stdout();
         serde_json::to_writer(stdout, self)
@@ -423,6 +1258,57 @@ impl<'i> SearchResult<'_, 'i> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[test]
+    fn user_config_merged_under_project() {
+        let mut user: toml::Table = toml::from_str(
+            r#"
+            redact = ["password"]
+            [defaults.headers]
+            x-user = "me"
+            accept = "text/plain"
+            [output]
+            "text/html" = "open"
+            "application/json" = "raw"
+            "#,
+        )
+        .unwrap();
+        let project: toml::Table = toml::from_str(
+            r#"
+            version = "0.5.0"
+            project = "test"
+            api_directory = "./services"
+            redact = ["token"]
+            [defaults.headers]
+            x-trace = "on"
+            accept = "application/json"
+            x-client = "cli"
+            [output]
+            "application/json" = "pretty"
+            "#,
+        )
+        .unwrap();
+        merge_tables(&mut user, project);
+        let config: Config = toml::Value::Table(user).try_into().unwrap();
+        assert_eq!(config.output["text/html"], crate::output::Handler::Open);
+        assert_eq!(
+            config.output["application/json"],
+            crate::output::Handler::Pretty
+        );
+        assert!(config.redact.is_sensitive("token"));
+        assert!(!config.redact.is_sensitive("password"));
+        // project headers keep their order, headers only in user config follow them
+        assert_eq!(
+            config.defaults.headers.into_iter().collect::<Vec<_>>(),
+            [
+                ("x-trace", "on"),
+                ("accept", "application/json"),
+                ("x-client", "cli"),
+                ("x-user", "me"),
+            ]
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+        );
+    }
+
     #[test]
     fn group_deserialize_empty_generic() {
         let s = "";
@@ -430,8 +1316,9 @@ mod tests {
         assert_eq!(
             g,
             Group {
-                sub_groups: HashMap::new(),
-                info: GroupContent::Generic
+                sub_groups: IndexMap::new(),
+                info: GroupContent::Generic(Generic::default()),
+                source: None,
             }
         )
     }
@@ -442,14 +1329,265 @@ mod tests {
         assert_eq!(
             g,
             Group {
-                sub_groups: HashMap::new(),
-                info: GroupContent::Http {
-                    queries: HashMap::new(),
-                    environments: HashMap::new()
-                }
+                sub_groups: IndexMap::new(),
+                info: GroupContent::Http(Content {
+                    queries: IndexMap::new(),
+                    environments: IndexMap::new()
+                }),
+                source: None,
             }
         )
     }
+
+    #[test]
+    fn split_dotted_paths() {
+        let s = r#"
+            type = "http"
+            [query."v1.health"]
+            path = "/health"
+            method = "GET"
+            [group.users]
+            type = "http"
+            [group.users.query."me.get"]
+            path = "/me"
+            method = "GET"
+        "#;
+        let g: Group = toml::from_str(s).unwrap();
+        let split = |endpoint: &[&str]| {
+            g.split_dotted(endpoint.iter().map(|key| key.to_string()).collect())
+        };
+        assert_eq!(split(&["users.create"]), ["users", "create"]);
+        assert_eq!(split(&["v1.health"]), ["v1.health"]);
+        assert_eq!(split(&["users", "me.get"]), ["users", "me.get"]);
+        assert!(g
+            .find(&split(&["v1.health"]))
+            .is_some_and(|found| found.query.is_some()));
+    }
+
+    #[test]
+    fn suggest_close_paths() {
+        let s = r#"
+            type = "http"
+            [query.create_user]
+            path = "/users"
+            method = "POST"
+            [group.admin]
+            type = "generic"
+        "#;
+        let g: Group = toml::from_str(s).unwrap();
+        assert_eq!(g.suggest(&["create_usr"]), vec![vec!["create_user"]]);
+        assert_eq!(g.suggest(&["adm"]), vec![vec!["admin"]]);
+        assert!(g.suggest(&["delete"]).is_empty());
+    }
+
+    #[test]
+    fn query_paths_in_file_order() {
+        let s = r#"
+            type = "http"
+            [query.zeta]
+            path = "/z"
+            method = "GET"
+            [query.alpha]
+            path = "/a"
+            method = "GET"
+            [query.secret]
+            path = "/s"
+            method = "GET"
+            hidden = true
+            [group.b]
+            type = "http"
+            [group.b.query.one]
+            path = "/one"
+            method = "POST"
+            [group.a]
+            type = "http"
+            [group.a.query.two]
+            path = "/two"
+            method = "GET"
+        "#;
+        let g: Group = toml::from_str(s).unwrap();
+        let paths = g
+            .find(&[] as &[&str])
+            .unwrap()
+            .group
+            .unwrap()
+            .query_paths(&[]);
+        assert_eq!(
+            paths,
+            vec![
+                vec!["zeta"],
+                vec!["alpha"],
+                vec!["b", "one"],
+                vec!["a", "two"]
+            ]
+        );
+        let found = g.find(&[] as &[&str]).unwrap();
+        assert_eq!(
+            found.plain_lines(&[], &[]),
+            [
+                "a.two GET /two",
+                "alpha GET /a",
+                "b.one POST /one",
+                "zeta GET /z"
+            ]
+        );
+    }
+
+    #[test]
+    fn shadowed_groups() {
+        let directory = std::env::temp_dir().join(format!("qwicket-shadow-{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("users")).unwrap();
+        std::fs::write(
+            directory.join("index.toml"),
+            "type = \"generic\"\n[group.users]\ntype = \"generic\"",
+        )
+        .unwrap();
+        std::fs::write(directory.join("users.toml"), "type = \"generic\"").unwrap();
+
+        let err = Group::from_dir(&directory, &Default::default(), false).unwrap_err();
+        let message = err.to_string();
+        // index.toml is shadowed by users directory which is shadowed by users.toml
+        assert_eq!(message.matches("is shadowed by").count(), 2, "{message}");
+        assert!(message.contains("index.toml"), "{message}");
+        assert!(message.contains("users.toml"), "{message}");
+        assert!(Group::from_dir(&directory, &Default::default(), true).is_ok());
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn generic_environment_passed_down() {
+        let s = r#"
+            [group.services]
+            description = "Internal services"
+            [group.services.environment.staging]
+            host = "staging.internal"
+            store = { region = "eu" }
+            [group.services.group.users]
+            type = "http"
+            [group.services.group.users.environment.default]
+            scheme = "https"
+            [group.services.group.users.query.list]
+            path = "/users"
+            method = "GET"
+        "#;
+        let g: Group = toml::from_str(s).unwrap();
+        let Some(QuerySearchResult::Http(found)) =
+            g.find(&["services", "users", "list"]).unwrap().query
+        else {
+            panic!("query not found")
+        };
+        assert_eq!(
+            found.environments["staging"].to_row()[..2],
+            ["https", "staging.internal"]
+        );
+        let environment = g
+            .find_environment(&["services", "users"], "staging")
+            .unwrap();
+        assert_eq!(environment.to_row()[..2], ["https", "staging.internal"]);
+
+        let matches = g.search("internal");
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].path, ["services"]);
+        assert_eq!(matches[0].field, "group description");
+    }
+
+    #[test]
+    fn file_order_preserved() {
+        let s = r#"
+            type = "http"
+            [query.zeta]
+            path = "/z"
+            method = "GET"
+            [query.alpha]
+            path = "/a"
+            method = "GET"
+            headers = { x-second = "2", a-first = "1" }
+        "#;
+        let g: Group = toml::from_str(s).unwrap();
+        let GroupContent::Http(Content { queries, .. }) = &g.info else {
+            panic!("expected http group")
+        };
+        assert_eq!(queries.keys().collect::<Vec<_>>(), ["zeta", "alpha"]);
+        let json = serde_json::to_string(&queries["alpha"]).unwrap();
+        assert!(json.find("x-second") < json.find("a-first"));
+    }
+
+    #[test]
+    fn default_environment_fills_missing() {
+        let s = r#"
+            type = "http"
+            [environment.staging]
+            host = "staging.example.com"
+            port = 8443
+            [group.billing]
+            type = "http"
+            [group.billing.environment.default]
+            scheme = "https"
+            host = "billing.example.com"
+            [group.billing.query.invoices]
+            path = "/invoices"
+            method = "GET"
+        "#;
+        let g: Group = toml::from_str(s).unwrap();
+        let Some(QuerySearchResult::Http(found)) = g.find(&["billing", "invoices"]).unwrap().query
+        else {
+            panic!("query not found")
+        };
+        assert_eq!(
+            found.environments["staging"].to_row(),
+            vec!["https", "billing.example.com", "8443"]
+        );
+    }
+
+    #[test]
+    fn describe_merged_query() {
+        let s = r#"
+            type = "http"
+            [environment.staging]
+            host = "staging.example.com"
+            headers = { x-team = "billing" }
+            [group.billing]
+            type = "http"
+            [group.billing.environment.default]
+            scheme = "https"
+            host = "billing.example.com"
+            [group.billing.query.invoice]
+            path = "/invoices/${id}"
+            method = "GET"
+            variables.id = { type = "int", description = "invoice id", required = true }
+            expect = { max_duration = "2s", headers_present = ["etag"] }
+        "#;
+        let g: Group = toml::from_str(s).unwrap();
+        let query = g.find(&["billing", "invoice"]).unwrap().query.unwrap();
+        let description = query.describe("billing.invoice", "staging").unwrap();
+        assert_eq!(description["name"], "billing.invoice");
+        assert_eq!(description["environments"], serde_json::json!(["staging"]));
+        // environment is merged from the parent groups, values are not substituted
+        let environment = &description["environment"];
+        assert_eq!(environment["name"], "staging");
+        assert_eq!(environment["scheme"], "https");
+        assert_eq!(environment["host"], "billing.example.com");
+        assert_eq!(environment["headers"]["x-team"], "billing");
+        assert_eq!(description["query"]["path"], "/invoices/${id}");
+        assert_eq!(
+            description["query"]["variables"]["id"],
+            serde_json::json!({
+                "type": "int",
+                "description": "invoice id",
+                "default": null,
+                "required": true,
+                "values": [],
+            })
+        );
+        assert_eq!(
+            description["query"]["expect"],
+            serde_json::json!({ "max_duration": "2s", "headers_present": ["etag"] })
+        );
+        // unknown environment falls back to default like while executing
+        let description = query.describe("billing.invoice", "prod").unwrap();
+        assert_eq!(description["environment"]["name"], "default");
+        assert_eq!(description["environment"]["headers"], serde_json::json!({}));
+    }
 }
 
 /*
@@ -0,0 +1,702 @@
+//! record/replay proxy
+//! forwards requests to an environment and records the exchanges as cassettes, which can be served back later
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use miette::{Context, IntoDiagnostic};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tracing::{debug, error, info, trace, warn};
+
+/// maximum number of headers accepted in a proxied request
+const MAX_HEADERS: usize = 64;
+
+/// these are connection specific and not forwarded
+const HOP_BY_HOP_HEADERS: &[&str] = &[
+    "host",
+    "connection",
+    "content-length",
+    "transfer-encoding",
+    "keep-alive",
+    "upgrade",
+];
+
+pub enum Mode {
+    /// forward requests to the environment, optionally recording exchanges to the directory
+    Forward { record: Option<PathBuf> },
+    /// serve exchanges recorded in the directory
+    Replay(PathBuf),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+enum CassetteBody {
+    Utf8(String),
+    Raw(Vec<u8>),
+}
+
+impl From<Vec<u8>> for CassetteBody {
+    fn from(value: Vec<u8>) -> Self {
+        match String::from_utf8(value) {
+            Ok(s) => Self::Utf8(s),
+            Err(e) => Self::Raw(e.into_bytes()),
+        }
+    }
+}
+
+impl From<CassetteBody> for Vec<u8> {
+    fn from(value: CassetteBody) -> Self {
+        match value {
+            CassetteBody::Utf8(s) => s.into_bytes(),
+            CassetteBody::Raw(vec) => vec,
+        }
+    }
+}
+
+/// single recorded request and its response
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+struct Exchange {
+    method: String,
+    /// path along with query string, relative to environment prefix
+    path: String,
+    #[serde(default)]
+    request_headers: Vec<(String, String)>,
+    request_body: Option<CassetteBody>,
+    status: u16,
+    #[serde(default)]
+    headers: Vec<(String, String)>,
+    body: Option<CassetteBody>,
+}
+
+impl Exchange {
+    /// copy with sensitive headers, query params and json body fields masked as configured in `redact`
+    /// so that cassettes, which are usually committed, don't keep the secrets
+    fn redacted(&self, redact: &crate::redact::Redact) -> Self {
+        let headers = |headers: &[(String, String)]| {
+            headers
+                .iter()
+                .map(|(key, val)| (key.clone(), redact.value(key, val).to_string()))
+                .collect()
+        };
+        Self {
+            method: self.method.clone(),
+            path: redact_path(&self.path, redact),
+            request_headers: headers(&self.request_headers),
+            request_body: self
+                .request_body
+                .clone()
+                .map(|body| redact_body(body, redact)),
+            status: self.status,
+            headers: headers(&self.headers),
+            body: self.body.clone().map(|body| redact_body(body, redact)),
+        }
+    }
+}
+
+/// path with the values of sensitive query params masked
+fn redact_path(path: &str, redact: &crate::redact::Redact) -> String {
+    if !path.contains('?') {
+        return path.to_string();
+    }
+    // only path and query of the url are kept, base is just to parse it
+    let base = reqwest::Url::parse("http://cassette").expect("base url is valid");
+    match base.join(path) {
+        Ok(url) => {
+            let url = redact.url(&url);
+            url.strip_prefix(base.as_str().trim_end_matches('/'))
+                .unwrap_or(&url)
+                .to_string()
+        }
+        Err(_) => path.to_string(),
+    }
+}
+
+fn redact_body(body: CassetteBody, redact: &crate::redact::Redact) -> CassetteBody {
+    match body {
+        CassetteBody::Utf8(text) => CassetteBody::Utf8(redact.text(&text).into_owned()),
+        raw => raw,
+    }
+}
+
+/// request read from the proxy client
+struct IncomingRequest {
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+struct Recorder {
+    directory: PathBuf,
+    next_index: AtomicUsize,
+}
+
+impl Recorder {
+    /// recording continues after the highest index of the existing cassettes
+    fn new(directory: PathBuf) -> miette::Result<Self> {
+        std::fs::create_dir_all(&directory)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Couldn't create cassette directory {directory:?}"))?;
+        let last = std::fs::read_dir(&directory)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Couldn't read cassette directory {directory:?}"))?
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                if path.extension()? != "json" {
+                    return None;
+                }
+                path.file_stem()?.to_str()?.parse::<usize>().ok()
+            })
+            .max();
+        Ok(Self {
+            directory,
+            next_index: AtomicUsize::new(last.map_or(0, |last| last + 1)),
+        })
+    }
+
+    /// existing cassettes are never overwritten, index is skipped if its cassette is created meanwhile
+    fn record(&self, exchange: &Exchange) -> miette::Result<()> {
+        let content = serde_json::to_vec_pretty(exchange)
+            .into_diagnostic()
+            .wrap_err("Couldn't serialize exchange")?;
+        loop {
+            let index = self.next_index.fetch_add(1, Ordering::SeqCst);
+            let path = self.directory.join(format!("{index:05}.json"));
+            let mut file = match std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(file) => file,
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    warn!("cassette {path:?} already exists, skipping it");
+                    continue;
+                }
+                Err(e) => {
+                    return Err(e)
+                        .into_diagnostic()
+                        .wrap_err_with(|| format!("Couldn't create cassette {path:?}"))
+                }
+            };
+            std::io::Write::write_all(&mut file, &content)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Couldn't write cassette {path:?}"))?;
+            debug!("recorded {} {} to {path:?}", exchange.method, exchange.path);
+            return Ok(());
+        }
+    }
+}
+
+/// recorded exchanges along with whether they are already served
+struct Cassettes {
+    exchanges: Mutex<Vec<(Exchange, bool)>>,
+    /// recorded requests are redacted, so incoming requests are redacted the same way to match them
+    redact: crate::redact::Redact,
+}
+
+impl Cassettes {
+    fn load(directory: &Path, redact: crate::redact::Redact) -> miette::Result<Self> {
+        let mut files = std::fs::read_dir(directory)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Couldn't read cassette directory {directory:?}"))?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Invalid file entry in {directory:?}"))?;
+        files.retain(|path| path.extension().is_some_and(|ext| ext == "json"));
+        files.sort_unstable();
+        let exchanges = files
+            .iter()
+            .map(|path| {
+                let content = std::fs::read(path)
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Couldn't read cassette {path:?}"))?;
+                let exchange: Exchange = serde_json::from_slice(&content)
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Couldn't deserialize cassette {path:?}"))?;
+                Ok((exchange, false))
+            })
+            .collect::<miette::Result<Vec<_>>>()?;
+        info!("loaded {} exchanges from {directory:?}", exchanges.len());
+        Ok(Self {
+            exchanges: Mutex::new(exchanges),
+            redact,
+        })
+    }
+
+    /// first exchange which is not served yet matching the request,
+    /// if all of them are served then last matching exchange is served again
+    fn find(&self, request: &IncomingRequest) -> Option<Exchange> {
+        let mut exchanges = self.exchanges.lock().expect("cassette lock is poisoned");
+        let path = redact_path(&request.path, &self.redact);
+        let request_body = (!request.body.is_empty())
+            .then(|| redact_body(request.body.clone().into(), &self.redact));
+        let mut matching = exchanges.iter_mut().filter(|(exchange, _)| {
+            exchange.method == request.method
+                && exchange.path == path
+                && exchange.request_body == request_body
+        });
+        let mut last = None;
+        for (exchange, served) in &mut matching {
+            if !*served {
+                *served = true;
+                return Some(exchange.clone());
+            }
+            last = Some(exchange.clone());
+        }
+        last
+    }
+}
+
+enum Handler {
+    Forward {
+        environment: Box<crate::agent::http::Environment>,
+        /// headers(ex: auth) of the environment, sent unless the request has them
+        headers: Vec<(String, String)>,
+        client: reqwest::Client,
+        recorder: Option<Recorder>,
+        /// applied to the exchanges before recording them
+        redact: crate::redact::Redact,
+    },
+    Replay(Cassettes),
+}
+
+impl Handler {
+    async fn handle(&self, request: IncomingRequest) -> miette::Result<Exchange> {
+        match self {
+            Handler::Forward {
+                environment,
+                headers: env_headers,
+                client,
+                recorder,
+                redact,
+            } => {
+                let url = environment.url_for(&request.path)?;
+                let method = reqwest::Method::from_bytes(request.method.as_bytes())
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("invalid method: {}", request.method))?;
+                debug!("forwarding {method} {url}");
+                let missing_env_headers = env_headers
+                    .iter()
+                    .filter(|(key, _)| request.headers.iter().all(|(name, _)| name != key));
+                let builder = request
+                    .headers
+                    .iter()
+                    .chain(missing_env_headers)
+                    .fold(client.request(method, url), |builder, (key, val)| {
+                        builder.header(key, val)
+                    });
+                let response = builder
+                    .body(request.body.clone())
+                    .send()
+                    .await
+                    .into_diagnostic()
+                    .wrap_err("Request failed")?;
+                let status = response.status().as_u16();
+                let headers = response
+                    .headers()
+                    .iter()
+                    .map(|(key, val)| {
+                        (
+                            key.to_string(),
+                            String::from_utf8_lossy(val.as_bytes()).into_owned(),
+                        )
+                    })
+                    .collect();
+                let body = response
+                    .bytes()
+                    .await
+                    .into_diagnostic()
+                    .wrap_err("Couldn't read response body")?
+                    .to_vec();
+                let exchange = Exchange {
+                    method: request.method,
+                    path: request.path,
+                    request_headers: request.headers,
+                    request_body: (!request.body.is_empty()).then(|| request.body.into()),
+                    status,
+                    headers,
+                    body: (!body.is_empty()).then(|| body.into()),
+                };
+                if let Some(recorder) = recorder {
+                    recorder.record(&exchange.redacted(redact))?;
+                }
+                Ok(exchange)
+            }
+            Handler::Replay(cassettes) => cassettes.find(&request).ok_or_else(|| {
+                miette::miette!(
+                    "no recorded exchange for {} {}",
+                    request.method,
+                    request.path
+                )
+            }),
+        }
+    }
+}
+
+/// listen on given port of localhost and serve requests till the process is killed
+/// forwarded requests get the headers of the environment substituted with the store
+pub async fn serve(
+    port: u16,
+    environment: crate::agent::http::Environment,
+    store: &std::collections::HashMap<String, String>,
+    config: &crate::parser::Config,
+    mode: Mode,
+) -> miette::Result<()> {
+    let handler = handler(environment, store, config, mode)?;
+    let listener = TcpListener::bind(("127.0.0.1", port))
+        .await
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Couldn't listen on port {port}"))?;
+    info!("proxy listening on 127.0.0.1:{port}");
+    accept(listener, Arc::new(handler)).await
+}
+
+fn handler(
+    environment: crate::agent::http::Environment,
+    store: &std::collections::HashMap<String, String>,
+    config: &crate::parser::Config,
+    mode: Mode,
+) -> miette::Result<Handler> {
+    Ok(match mode {
+        Mode::Forward { record } => Handler::Forward {
+            headers: environment.substituted_headers(store, config)?,
+            client: environment
+                .ip_family()
+                .configure(
+                    reqwest::Client::builder().user_agent(crate::agent::http::APP_USER_AGENT),
+                )
+                .build()
+                .into_diagnostic()
+                .wrap_err("Couldn't build client")?,
+            recorder: record.map(Recorder::new).transpose()?,
+            redact: config.redact.clone(),
+            environment: Box::new(environment),
+        },
+        Mode::Replay(directory) => {
+            Handler::Replay(Cassettes::load(&directory, config.redact.clone())?)
+        }
+    })
+}
+
+async fn accept(listener: TcpListener, handler: Arc<Handler>) -> miette::Result<()> {
+    loop {
+        let (stream, address) = listener
+            .accept()
+            .await
+            .into_diagnostic()
+            .wrap_err("Couldn't accept connection")?;
+        trace!("connection from {address}");
+        let handler = Arc::clone(&handler);
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, &handler).await {
+                error!("proxy request from {address} failed: {e:?}");
+            }
+        });
+    }
+}
+
+async fn handle_connection(mut stream: TcpStream, handler: &Handler) -> miette::Result<()> {
+    let request = match read_request(&mut stream).await {
+        Ok(request) => request,
+        Err(e) => {
+            write_error(&mut stream, 400, &e.to_string()).await?;
+            return Err(e);
+        }
+    };
+    info!("{} {}", request.method, request.path);
+    match handler.handle(request).await {
+        Ok(exchange) => write_response(&mut stream, &exchange).await,
+        Err(e) => {
+            warn!("{e}");
+            write_error(&mut stream, 502, &format!("{e:?}")).await
+        }
+    }
+}
+
+async fn read_request(stream: &mut TcpStream) -> miette::Result<IncomingRequest> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0; 4096];
+    loop {
+        let read = stream
+            .read(&mut chunk)
+            .await
+            .into_diagnostic()
+            .wrap_err("Couldn't read request")?;
+        if read == 0 {
+            miette::bail!("connection closed before request was complete")
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+
+        let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let mut parsed = httparse::Request::new(&mut headers);
+        let httparse::Status::Complete(header_len) = parsed
+            .parse(&buffer)
+            .into_diagnostic()
+            .wrap_err("Invalid http request")?
+        else {
+            continue;
+        };
+
+        let headers: Vec<_> = parsed
+            .headers
+            .iter()
+            .map(|h| {
+                (
+                    h.name.to_ascii_lowercase(),
+                    String::from_utf8_lossy(h.value).into_owned(),
+                )
+            })
+            .collect();
+        if headers
+            .iter()
+            .any(|(key, val)| key == "transfer-encoding" && val.contains("chunked"))
+        {
+            miette::bail!("chunked request bodies are not supported")
+        }
+        let content_length = headers
+            .iter()
+            .find(|(key, _)| key == "content-length")
+            .map(|(_, val)| val.trim().parse::<usize>())
+            .transpose()
+            .into_diagnostic()
+            .wrap_err("Invalid content-length")?
+            .unwrap_or(0);
+        let method = parsed.method.unwrap_or_default().to_string();
+        let path = parsed.path.unwrap_or("/").to_string();
+
+        let mut body = buffer.split_off(header_len);
+        while body.len() < content_length {
+            let read = stream
+                .read(&mut chunk)
+                .await
+                .into_diagnostic()
+                .wrap_err("Couldn't read request body")?;
+            if read == 0 {
+                miette::bail!("connection closed before body was complete")
+            }
+            body.extend_from_slice(&chunk[..read]);
+        }
+        body.truncate(content_length);
+
+        return Ok(IncomingRequest {
+            method,
+            path,
+            headers: headers
+                .into_iter()
+                .filter(|(key, _)| !HOP_BY_HOP_HEADERS.contains(&key.as_str()))
+                .collect(),
+            body,
+        });
+    }
+}
+
+async fn write_response(stream: &mut TcpStream, exchange: &Exchange) -> miette::Result<()> {
+    let body: Vec<u8> = exchange.body.clone().map(Into::into).unwrap_or_default();
+    let reason = http::StatusCode::from_u16(exchange.status)
+        .ok()
+        .and_then(|status| status.canonical_reason())
+        .unwrap_or_default();
+    let mut response = format!("HTTP/1.1 {} {reason}\r\n", exchange.status);
+    exchange
+        .headers
+        .iter()
+        .filter(|(key, _)| !HOP_BY_HOP_HEADERS.contains(&key.to_ascii_lowercase().as_str()))
+        .for_each(|(key, val)| response.push_str(&format!("{key}: {val}\r\n")));
+    response.push_str(&format!(
+        "content-length: {}\r\nconnection: close\r\n\r\n",
+        body.len()
+    ));
+    let mut response = response.into_bytes();
+    response.extend(body);
+    stream
+        .write_all(&response)
+        .await
+        .into_diagnostic()
+        .wrap_err("Couldn't write response")
+}
+
+async fn write_error(stream: &mut TcpStream, status: u16, message: &str) -> miette::Result<()> {
+    let exchange = Exchange {
+        method: String::new(),
+        path: String::new(),
+        request_headers: Vec::new(),
+        request_body: None,
+        status,
+        headers: vec![("content-type".to_string(), "text/plain".to_string())],
+        body: Some(CassetteBody::Utf8(message.to_string())),
+    };
+    write_response(stream, &exchange).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// upstream which answers every request with its authorization header
+    async fn upstream() -> u16 {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let request = read_request(&mut stream).await.unwrap();
+                let authorization = request
+                    .headers
+                    .into_iter()
+                    .find(|(key, _)| key == "authorization")
+                    .map(|(_, val)| val)
+                    .unwrap_or_default();
+                let exchange = Exchange {
+                    method: request.method,
+                    path: request.path,
+                    request_headers: Vec::new(),
+                    request_body: None,
+                    status: 200,
+                    headers: Vec::new(),
+                    body: Some(CassetteBody::Utf8(authorization)),
+                };
+                write_response(&mut stream, &exchange).await.unwrap();
+            }
+        });
+        port
+    }
+
+    async fn proxy(handler: Handler) -> u16 {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(accept(listener, Arc::new(handler)));
+        port
+    }
+
+    async fn get(port: u16, path: &str) -> (u16, String) {
+        let response = reqwest::Client::new()
+            .get(format!("http://127.0.0.1:{port}{path}"))
+            .header("x-api-key", "secret")
+            .send()
+            .await
+            .unwrap();
+        (response.status().as_u16(), response.text().await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn forward_and_replay() {
+        let cassettes =
+            std::env::temp_dir().join(format!("qwicket-cassettes-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&cassettes);
+        let config: crate::parser::Config = toml::from_str(
+            "version = \"0.0.0\"\nproject = \"test\"\napi_directory = \"api\"\nredact = [\"x-api-key\", \"token\"]\n[defaults.headers]\nx-client = \"qwicket\"",
+        )
+        .unwrap();
+        let environment: crate::agent::http::Environment = toml::from_str(&format!(
+            "scheme = \"http\"\nhost = \"127.0.0.1\"\nport = {}\nprefix = \"/api\"\nheaders = {{ Authorization = \"Bearer ${{token}}\" }}",
+            upstream().await
+        ))
+        .unwrap();
+        let store = std::collections::HashMap::from([("token".to_string(), "abc".to_string())]);
+        let forward = Mode::Forward {
+            record: Some(cassettes.clone()),
+        };
+        let port = proxy(handler(environment.clone(), &store, &config, forward).unwrap()).await;
+        assert_eq!(get(port, "/users").await, (200, "Bearer abc".to_string()));
+        assert_eq!(
+            get(port, "/users?token=abc&page=2").await,
+            (200, "Bearer abc".to_string())
+        );
+
+        let recorded = Cassettes::load(&cassettes, Default::default()).unwrap();
+        let mut exchanges = recorded.exchanges.into_inner().unwrap().into_iter();
+        let exchange = exchanges.next().unwrap().0;
+        assert_eq!(exchange.path, "/users");
+        assert!(exchange
+            .request_headers
+            .iter()
+            .all(|(key, _)| key != "authorization"));
+        // secrets are not written to the cassettes
+        assert!(exchange
+            .request_headers
+            .contains(&("x-api-key".to_string(), crate::redact::MASK.to_string())));
+        assert_eq!(exchanges.next().unwrap().0.path, "/users?token=***&page=2");
+
+        let replay = Mode::Replay(cassettes.clone());
+        let port = proxy(handler(environment, &store, &config, replay).unwrap()).await;
+        assert_eq!(get(port, "/users").await, (200, "Bearer abc".to_string()));
+        // requests are redacted like the recorded ones to match them
+        assert_eq!(
+            get(port, "/users?token=abc&page=2").await,
+            (200, "Bearer abc".to_string())
+        );
+        assert_eq!(get(port, "/orders").await.0, 502);
+        std::fs::remove_dir_all(cassettes).unwrap();
+    }
+
+    #[test]
+    fn redacted_bodies_and_headers() {
+        let redact: crate::redact::Redact =
+            serde_json::from_str(r#"["set-cookie", "password"]"#).unwrap();
+        let exchange = Exchange {
+            method: "POST".to_string(),
+            path: "/login".to_string(),
+            request_headers: Vec::new(),
+            request_body: Some(CassetteBody::Utf8(
+                r#"{"user":"admin","password":"hunter2"}"#.to_string(),
+            )),
+            status: 200,
+            headers: vec![("set-cookie".to_string(), "session=abc".to_string())],
+            body: Some(CassetteBody::Raw(vec![0xff, 0xfe])),
+        }
+        .redacted(&redact);
+        assert_eq!(
+            exchange.request_body,
+            Some(CassetteBody::Utf8(
+                r#"{"password":"***","user":"admin"}"#.to_string()
+            ))
+        );
+        assert_eq!(exchange.headers[0].1, "***");
+        assert_eq!(exchange.body, Some(CassetteBody::Raw(vec![0xff, 0xfe])));
+    }
+
+    #[test]
+    fn recording_continues_after_last_cassette() {
+        let cassettes =
+            std::env::temp_dir().join(format!("qwicket-recorder-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&cassettes);
+        std::fs::create_dir_all(&cassettes).unwrap();
+        for file in ["00001.json", "00005.json", "notes.md"] {
+            std::fs::write(cassettes.join(file), "existing").unwrap();
+        }
+        let recorder = Recorder::new(cassettes.clone()).unwrap();
+        // cassette created by someone else meanwhile is skipped
+        std::fs::write(cassettes.join("00006.json"), "existing").unwrap();
+        let exchange = Exchange {
+            method: "GET".to_string(),
+            path: "/".to_string(),
+            request_headers: Vec::new(),
+            request_body: None,
+            status: 204,
+            headers: Vec::new(),
+            body: None,
+        };
+        recorder.record(&exchange).unwrap();
+        for file in ["00001.json", "00005.json", "00006.json", "notes.md"] {
+            assert_eq!(
+                std::fs::read_to_string(cassettes.join(file)).unwrap(),
+                "existing"
+            );
+        }
+        assert!(std::fs::read_to_string(cassettes.join("00007.json"))
+            .unwrap()
+            .contains("\"status\": 204"));
+        std::fs::remove_dir_all(cassettes).unwrap();
+    }
+}
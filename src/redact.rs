@@ -0,0 +1,152 @@
+//! masks sensitive values in logs and inspected payloads
+
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// replacement of the sensitive values
+pub const MASK: &str = "***";
+
+/// case insensitive patterns of sensitive header, param and json field names, `*` matches any characters
+/// json fields are matched with their dotted path(ex: `*.token` matches `auth.token`),
+/// patterns without `.` match the field name at any depth
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct Redact(Vec<String>);
+
+impl Redact {
+    pub fn is_sensitive(&self, name: &str) -> bool {
+        self.0.iter().any(|pattern| glob_match(pattern, name))
+    }
+
+    fn is_sensitive_field(&self, path: &str, field: &str) -> bool {
+        self.0.iter().any(|pattern| {
+            glob_match(pattern, path) || (!pattern.contains('.') && glob_match(pattern, field))
+        })
+    }
+
+    /// value of the header/param, masked if the name is sensitive
+    pub fn value<'v>(&self, name: &str, value: &'v str) -> &'v str {
+        if self.is_sensitive(name) {
+            MASK
+        } else {
+            value
+        }
+    }
+
+    /// mask sensitive fields of the json value in place
+    pub fn json(&self, value: &mut Value) {
+        if !self.0.is_empty() {
+            self.mask_fields(value, "");
+        }
+    }
+
+    fn mask_fields(&self, value: &mut Value, prefix: &str) {
+        match value {
+            Value::Object(map) => {
+                for (field, value) in map {
+                    let path = if prefix.is_empty() {
+                        field.clone()
+                    } else {
+                        format!("{prefix}.{field}")
+                    };
+                    if self.is_sensitive_field(&path, field) {
+                        *value = Value::String(MASK.to_string());
+                    } else {
+                        self.mask_fields(value, &path);
+                    }
+                }
+            }
+            Value::Array(list) => list
+                .iter_mut()
+                .for_each(|value| self.mask_fields(value, prefix)),
+            _ => {}
+        }
+    }
+
+    /// mask sensitive fields if text is json, other text is returned as it is
+    pub fn text<'t>(&self, text: &'t str) -> Cow<'t, str> {
+        if self.0.is_empty() {
+            return Cow::Borrowed(text);
+        }
+        match serde_json::from_str::<Value>(text) {
+            Ok(mut value) => {
+                self.json(&mut value);
+                Cow::Owned(value.to_string())
+            }
+            Err(_) => Cow::Borrowed(text),
+        }
+    }
+
+    /// url with the values of sensitive query params masked
+    pub fn url(&self, url: &reqwest::Url) -> String {
+        if url.query().is_none() || self.0.is_empty() {
+            return url.to_string();
+        }
+        let mut masked = url.clone();
+        masked.query_pairs_mut().clear().extend_pairs(
+            url.query_pairs()
+                .map(|(name, value)| (self.value(&name, &value).to_string(), name))
+                .map(|(value, name)| (name, value)),
+        );
+        masked.to_string()
+    }
+}
+
+/// case insensitive match where `*` matches any number of characters
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.to_lowercase();
+    let name = name.to_lowercase();
+    let Some((prefix, rest)) = pattern.split_once('*') else {
+        return pattern == name;
+    };
+    let Some(mut remaining) = name.strip_prefix(prefix) else {
+        return false;
+    };
+    let mut parts = rest.split('*').peekable();
+    while let Some(part) = parts.next() {
+        if parts.peek().is_none() {
+            return remaining.len() >= part.len() && remaining.ends_with(part);
+        }
+        match remaining.find(part) {
+            Some(index) => remaining = &remaining[index + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob() {
+        assert!(glob_match("Authorization", "authorization"));
+        assert!(glob_match("*.token", "auth.token"));
+        assert!(!glob_match("*.token", "token"));
+        assert!(glob_match("x-*-key", "X-Api-Key"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("a*a", "a"));
+    }
+
+    #[test]
+    fn mask_json_fields() {
+        let redact = Redact(vec!["password".to_string(), "*.token".to_string()]);
+        let mut value = serde_json::json!({
+            "token": "a",
+            "user": {"password": "b", "token": "c"},
+            "sessions": [{"token": "d"}]
+        });
+        redact.json(&mut value);
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "token": "a",
+                "user": {"password": "***", "token": "***"},
+                "sessions": [{"token": "***"}]
+            })
+        );
+    }
+}
@@ -0,0 +1,129 @@
+//! junit xml report of group and health check runs, so that ci systems can show the results
+
+use std::fmt::Write;
+
+use miette::{Context, IntoDiagnostic};
+use tracing::debug;
+
+use crate::markup::escape;
+
+#[derive(Debug)]
+struct TestCase {
+    query: String,
+    env: String,
+    duration: std::time::Duration,
+    /// reason of the failure
+    failure: Option<String>,
+}
+
+/// one test case per query and environment
+#[derive(Debug, Default)]
+pub struct Report {
+    cases: Vec<TestCase>,
+}
+
+impl Report {
+    pub fn record(
+        &mut self,
+        query: &str,
+        env: &str,
+        duration: std::time::Duration,
+        failure: Option<String>,
+    ) {
+        self.cases.push(TestCase {
+            query: query.to_string(),
+            env: env.to_string(),
+            duration,
+            failure,
+        });
+    }
+
+    fn to_xml(&self, suite: &str) -> String {
+        crate::markup::render(|xml| self.write_xml(suite, xml))
+    }
+
+    fn write_xml(&self, suite: &str, xml: &mut String) -> std::fmt::Result {
+        let tests = self.cases.len();
+        let failures = self
+            .cases
+            .iter()
+            .filter(|case| case.failure.is_some())
+            .count();
+        let time: f64 = self
+            .cases
+            .iter()
+            .map(|case| case.duration.as_secs_f64())
+            .sum();
+        writeln!(xml, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            xml,
+            r#"<testsuites tests="{tests}" failures="{failures}" time="{time:.3}">"#
+        )?;
+        writeln!(
+            xml,
+            r#"  <testsuite name="{}" tests="{tests}" failures="{failures}" time="{time:.3}">"#,
+            escape(suite)
+        )?;
+        for case in &self.cases {
+            let opening = format!(
+                r#"    <testcase name="{}" classname="{}" time="{:.3}""#,
+                escape(&case.query),
+                escape(&case.env),
+                case.duration.as_secs_f64()
+            );
+            match &case.failure {
+                Some(failure) => {
+                    writeln!(xml, "{opening}>")?;
+                    writeln!(xml, r#"      <failure message="{}"/>"#, escape(failure))?;
+                    writeln!(xml, "    </testcase>")?;
+                }
+                None => writeln!(xml, "{opening}/>")?,
+            }
+        }
+        writeln!(xml, "  </testsuite>")?;
+        writeln!(xml, "</testsuites>")
+    }
+
+    /// write junit xml report to given file, suite is named after the project
+    pub fn write(&self, path: &std::path::Path, suite: &str) -> miette::Result<()> {
+        debug!("writing junit report to {path:?}");
+        std::fs::write(path, self.to_xml(suite))
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Couldn't write report to {path:?}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn junit_xml() {
+        let mut report = Report::default();
+        report.record(
+            "users.list",
+            "dev",
+            std::time::Duration::from_millis(120),
+            None,
+        );
+        report.record(
+            "users.create",
+            "dev",
+            std::time::Duration::from_millis(80),
+            Some("status 500 < 400".to_string()),
+        );
+        assert_eq!(
+            report.to_xml("api"),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuites tests="2" failures="1" time="0.200">
+  <testsuite name="api" tests="2" failures="1" time="0.200">
+    <testcase name="users.list" classname="dev" time="0.120"/>
+    <testcase name="users.create" classname="dev" time="0.080">
+      <failure message="status 500 &lt; 400"/>
+    </testcase>
+  </testsuite>
+</testsuites>
+"#
+        );
+    }
+}
@@ -0,0 +1,455 @@
+//! secrets which are read from external stores at the time of query execution instead of keeping them in plain text
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    path::Path,
+    sync::{LazyLock, Mutex},
+};
+
+use miette::{Context, IntoDiagnostic};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, trace};
+
+/// where to read the secret from
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum Secret {
+    /// os keychain, secret service on linux, keychain on macos and credential manager on windows
+    Keyring { service: String, user: String },
+    /// hashicorp vault over http, address and token are taken from `VAULT_ADDR` and `VAULT_TOKEN`
+    Vault { path: String, key: String },
+    /// 1password secret reference read with `op` cli, ex: `op://vault/item/field`
+    Op(String),
+    /// any other secret store, stdout of the command is the secret
+    Command(Vec<String>),
+}
+
+/// command registered in config file whose stdout is used as value of the variable
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Provider {
+    /// script without any arguments
+    Script(String),
+    /// program with its arguments
+    Command(Vec<String>),
+}
+
+impl Provider {
+    /// program given as a relative path(ex: `scripts/token.sh`) is relative to the directory of the config file
+    /// programs given by name are looked up in PATH
+    pub fn relative_to(&mut self, directory: &Path) {
+        let program = match self {
+            Provider::Script(script) => script,
+            Provider::Command(command) => match command.first_mut() {
+                Some(program) => program,
+                None => return,
+            },
+        };
+        let path = Path::new(program.as_str());
+        if path.is_relative() && path.components().count() > 1 {
+            *program = directory.join(path).to_string_lossy().into_owned();
+        }
+    }
+}
+
+impl From<Provider> for Secret {
+    fn from(value: Provider) -> Self {
+        match value {
+            Provider::Script(script) => Secret::Command(vec![script]),
+            Provider::Command(command) => Secret::Command(command),
+        }
+    }
+}
+
+/// kinds of `${<kind>:<reference>}` variables, these are read from the store named by the kind instead of the variables
+const REFERENCE_KINDS: [&str; 3] = ["vault", "op", "provider"];
+
+/// secrets resolved in this run, so that each secret is read only once even when used by many queries
+static RESOLVED: LazyLock<Mutex<HashMap<Secret, String>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+impl Secret {
+    /// read the secret, secret is read from store only the first time
+    pub fn resolve(&self) -> miette::Result<String> {
+        if let Some(value) = RESOLVED.lock().expect("secret cache poisoned").get(self) {
+            trace!("using cached secret");
+            return Ok(value.clone());
+        }
+        let value = match self {
+            Secret::Keyring { service, user } => read_keyring(service, user),
+            Secret::Vault { path, key } => read_vault(path, key),
+            Secret::Op(reference) => run_command("op", &["read", "--no-newline", reference]),
+            Secret::Command(command) => {
+                let Some((program, args)) = command.split_first() else {
+                    miette::bail!("secret command is empty")
+                };
+                run_command(program, args)
+            }
+        }?;
+        RESOLVED
+            .lock()
+            .expect("secret cache poisoned")
+            .insert(self.clone(), value.clone());
+        Ok(value)
+    }
+}
+
+/// variables used for substitution, secrets and providers are resolved only when they are used in the query
+pub struct Variables<'s> {
+    values: HashMap<String, String>,
+    secrets: &'s HashMap<String, Secret>,
+    providers: &'s HashMap<String, Provider>,
+    /// expiry of the values, to warn when an expired value is used
+    expiry: Option<&'s crate::cache::Cache<crate::store::Expiry>>,
+}
+
+impl<'s> Variables<'s> {
+    /// priority is values, secrets and then providers
+    pub fn new(
+        values: HashMap<String, String>,
+        secrets: &'s HashMap<String, Secret>,
+        providers: &'s HashMap<String, Provider>,
+    ) -> Self {
+        Self {
+            values,
+            secrets,
+            providers,
+            expiry: None,
+        }
+    }
+
+    pub fn with_expiry(mut self, expiry: &'s crate::cache::Cache<crate::store::Expiry>) -> Self {
+        self.expiry = Some(expiry);
+        self
+    }
+
+    /// replace `${vault:<path>#<key>}`, `${op:<reference>}` and `${provider:<name>}` with their values
+    /// text is escaped for subst, so backslash escapes are skipped and values are escaped
+    pub fn references<'t>(&self, text: &'t str) -> Result<Cow<'t, str>, subst::Error> {
+        if !text.contains("${") {
+            return Ok(text.into());
+        }
+        let mut substituted = String::with_capacity(text.len());
+        let mut position = 0;
+        while let Some(offset) = text[position..].find(['\\', '$']) {
+            let start = position + offset;
+            substituted.push_str(&text[position..start]);
+            let rest = &text[start..];
+            let len = if rest.starts_with('\\') {
+                let len = rest.chars().nth(1).map_or(1, |next| 1 + next.len_utf8());
+                substituted.push_str(&rest[..len]);
+                len
+            } else if let Some((kind, reference, len)) = parse_reference(rest) {
+                let value = self.reference(kind, reference).ok_or_else(|| {
+                    subst::Error::NoSuchVariable(subst::error::NoSuchVariable {
+                        position: start + 2,
+                        name: format!("{kind}:{reference}"),
+                    })
+                })?;
+                for c in value.chars() {
+                    if matches!(c, '\\' | '$' | '{' | '}' | ':') {
+                        substituted.push('\\');
+                    }
+                    substituted.push(c);
+                }
+                len
+            } else {
+                substituted.push('$');
+                1
+            };
+            position = start + len;
+        }
+        substituted.push_str(&text[position..]);
+        Ok(substituted.into())
+    }
+
+    fn reference(&self, kind: &str, reference: &str) -> Option<String> {
+        let secret = match kind {
+            "vault" => {
+                let Some((path, key)) = reference.rsplit_once('#') else {
+                    error!("vault reference {reference} should be <path>#<key>");
+                    return None;
+                };
+                Secret::Vault {
+                    path: path.to_string(),
+                    key: key.to_string(),
+                }
+            }
+            "op" => Secret::Op(reference.to_string()),
+            _ => {
+                let Some(provider) = self.providers.get(reference) else {
+                    error!("no provider named {reference} in config");
+                    return None;
+                };
+                Secret::from(provider.clone())
+            }
+        };
+        debug!("resolving {kind} reference {reference}");
+        secret
+            .resolve()
+            .inspect_err(|e| error!("Couldn't resolve {kind}:{reference}: {e:?}"))
+            .ok()
+    }
+}
+
+/// `${<kind>:<reference>}` at the start of the text and its length
+fn parse_reference(text: &str) -> Option<(&str, &str, usize)> {
+    let (kind, rest) = text.strip_prefix("${")?.split_once(':')?;
+    if !REFERENCE_KINDS.contains(&kind) {
+        return None;
+    }
+    let end = rest.find('}')?;
+    Some((kind, &rest[..end], kind.len() + end + 4))
+}
+
+impl<'a> subst::VariableMap<'a> for Variables<'_> {
+    type Value = String;
+
+    fn get(&'a self, key: &str) -> Option<Self::Value> {
+        if let Some(value) = self.values.get(key) {
+            if let Some(expiry) = self.expiry {
+                crate::store::warn_if_expired(expiry, key);
+            }
+            return Some(value.clone());
+        }
+        let secret = match self.secrets.get(key) {
+            Some(secret) => secret.clone(),
+            None => Secret::from(self.providers.get(key)?.clone()),
+        };
+        debug!("resolving secret {key}");
+        // substitution can only say that variable is missing, so log the actual reason
+        secret
+            .resolve()
+            .inspect_err(|e| error!("Couldn't resolve secret {key}: {e:?}"))
+            .ok()
+    }
+}
+
+fn read_keyring(service: &str, user: &str) -> miette::Result<String> {
+    keyring::Entry::new(service, user)
+        .and_then(|entry| entry.get_password())
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Couldn't read {user} of {service} from keyring"))
+}
+
+fn read_vault(path: &str, key: &str) -> miette::Result<String> {
+    let address = std::env::var("VAULT_ADDR")
+        .into_diagnostic()
+        .wrap_err("VAULT_ADDR is not set")?;
+    let token = std::env::var("VAULT_TOKEN")
+        .into_diagnostic()
+        .wrap_err("VAULT_TOKEN is not set")?;
+    let url = format!(
+        "{}/v1/{}",
+        address.trim_end_matches('/'),
+        path.trim_start_matches('/')
+    );
+    debug!("reading secret from vault {url}");
+    // substitution is synchronous and can happen on any runtime or none at all
+    // so the request runs on its own thread with its own runtime
+    let request = || {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .into_diagnostic()
+            .wrap_err("Couldn't start runtime for vault request")?
+            .block_on(async {
+                reqwest::Client::builder()
+                    .user_agent(crate::agent::http::APP_USER_AGENT)
+                    .build()
+                    .into_diagnostic()?
+                    .get(&url)
+                    .header("X-Vault-Token", token)
+                    .send()
+                    .await
+                    .and_then(reqwest::Response::error_for_status)
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Couldn't read {path} from vault"))?
+                    .bytes()
+                    .await
+                    .into_diagnostic()
+                    .wrap_err("Couldn't read response from vault")
+            })
+    };
+    let body = std::thread::scope(|scope| {
+        scope
+            .spawn(request)
+            .join()
+            .unwrap_or_else(|_| Err(miette::miette!("vault request panicked")))
+    })?;
+    let body = serde_json::from_slice::<serde_json::Value>(&body)
+        .into_diagnostic()
+        .wrap_err("Invalid response from vault")?;
+    vault_field(&body, key).ok_or_else(|| miette::miette!("{key} is not present in {path}"))
+}
+
+/// kv version 2 nests the secret under `data.data`, version 1 directly under `data`
+fn vault_field(body: &serde_json::Value, key: &str) -> Option<String> {
+    let data = &body["data"];
+    let value = data["data"].get(key).or_else(|| data.get(key))?;
+    match value {
+        serde_json::Value::String(value) => Some(value.clone()),
+        value => Some(value.to_string()),
+    }
+}
+
+fn run_command(program: &str, args: &[impl AsRef<std::ffi::OsStr>]) -> miette::Result<String> {
+    let output = std::process::Command::new(program)
+        .args(args)
+        .stderr(std::process::Stdio::inherit())
+        .output()
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Couldn't run {program}"))?;
+    if !output.status.success() {
+        miette::bail!("{program} exited with error: {}", output.status)
+    }
+    let mut secret = String::from_utf8(output.stdout)
+        .into_diagnostic()
+        .wrap_err("secret is not a valid utf8")?;
+    // most of the tools end the secret with new line
+    if secret.ends_with('\n') {
+        secret.pop();
+    }
+    Ok(secret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vault_kv_versions() {
+        let v1 = serde_json::json!({"data": {"token": "one"}});
+        let v2 = serde_json::json!({"data": {"data": {"token": "two"}, "metadata": {}}});
+        assert_eq!(vault_field(&v1, "token").as_deref(), Some("one"));
+        assert_eq!(vault_field(&v2, "token").as_deref(), Some("two"));
+        assert_eq!(vault_field(&v2, "missing"), None);
+    }
+
+    /// default tokio test runtime is current thread, like of library callers
+    #[tokio::test]
+    async fn vault_reference_on_current_thread_runtime() {
+        use std::io::{Read, Write};
+        let listener = std::net::TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 4096];
+            let len = stream.read(&mut request).unwrap();
+            let request = String::from_utf8_lossy(&request[..len]).to_lowercase();
+            let body = r#"{"data": {"data": {"token": "from vault"}}}"#;
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                body.len()
+            )
+            .unwrap();
+            request
+        });
+        std::env::set_var("VAULT_ADDR", format!("http://{address}"));
+        std::env::set_var("VAULT_TOKEN", "root");
+        let (secrets, providers) = (HashMap::new(), HashMap::new());
+        let vars = Variables::new(HashMap::new(), &secrets, &providers);
+        assert_eq!(
+            crate::variable::substitute("${vault:secret/data/app#token}", &vars).unwrap(),
+            "from vault"
+        );
+        let request = server.join().unwrap();
+        assert!(request.starts_with("get /v1/secret/data/app "));
+        assert!(request.contains("x-vault-token: root"));
+    }
+
+    #[test]
+    fn missing_keyring_entry() {
+        keyring::set_default_credential_builder(keyring::mock::default_credential_builder());
+        let error = read_keyring("qwicket", "nobody").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Couldn't read nobody of qwicket from keyring"
+        );
+    }
+
+    #[test]
+    fn secrets_resolved_lazily() {
+        let secrets = HashMap::from([
+            (
+                "used".to_string(),
+                Secret::Command(vec!["echo".to_string(), "secret".to_string()]),
+            ),
+            (
+                "unused".to_string(),
+                Secret::Command(vec!["false".to_string()]),
+            ),
+        ]);
+        let providers = HashMap::new();
+        let vars = Variables::new(HashMap::new(), &secrets, &providers);
+        assert_eq!(subst::substitute("${used}", &vars).unwrap(), "secret");
+        assert!(RESOLVED.lock().unwrap().contains_key(&secrets["used"]));
+        assert!(!RESOLVED.lock().unwrap().contains_key(&secrets["unused"]));
+    }
+
+    #[test]
+    fn secrets_before_providers() {
+        let secrets = HashMap::from([(
+            "token".to_string(),
+            Secret::Command(vec!["echo".to_string(), "secret".to_string()]),
+        )]);
+        let providers = HashMap::from([
+            (
+                "token".to_string(),
+                Provider::Command(vec!["echo".to_string(), "provided".to_string()]),
+            ),
+            (
+                "region".to_string(),
+                Provider::Command(vec!["echo".to_string(), "eu".to_string()]),
+            ),
+        ]);
+        let values = HashMap::from([("user".to_string(), "admin".to_string())]);
+        let vars = Variables::new(values, &secrets, &providers);
+        assert_eq!(
+            subst::substitute("${user}/${token}/${region}", &vars).unwrap(),
+            "admin/secret/eu"
+        );
+    }
+
+    #[test]
+    fn prefixed_references() {
+        let secrets = HashMap::new();
+        let providers = HashMap::from([
+            (
+                "token".to_string(),
+                Provider::Command(vec!["echo".to_string(), "a:{b}$c".to_string()]),
+            ),
+            (
+                "region".to_string(),
+                Provider::Command(vec!["echo".to_string(), "eu".to_string()]),
+            ),
+        ]);
+        let values = HashMap::from([("user".to_string(), "admin".to_string())]);
+        let vars = Variables::new(values, &secrets, &providers);
+        let substitute = |text| crate::variable::substitute(text, &vars);
+        assert_eq!(
+            substitute("${user}/${provider:token}/${region}").unwrap(),
+            "admin/a:{b}$c/eu"
+        );
+        assert_eq!(
+            substitute("$${provider:token}").unwrap(),
+            "${provider:token}"
+        );
+        // unknown kinds are variables with default values
+        assert_eq!(substitute("${mode:fast}").unwrap(), "fast");
+        assert!(substitute("${provider:missing}").is_err());
+        assert!(substitute("${vault:secret/data/app}").is_err());
+
+        let mut script = Provider::Script("scripts/token.sh".to_string());
+        script.relative_to(Path::new("/project"));
+        assert_eq!(
+            script,
+            Provider::Script("/project/scripts/token.sh".to_string())
+        );
+        let mut command = Provider::Command(vec!["aws".to_string()]);
+        command.relative_to(Path::new("/project"));
+        assert_eq!(command, Provider::Command(vec!["aws".to_string()]));
+    }
+}
@@ -0,0 +1,166 @@
+//! summary of every sent request and its response written to the configured sinks, for auditing what was run
+//! failing sinks only warn, they never fail the query, summaries are written in background without holding the query
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use miette::{Context, IntoDiagnostic};
+use serde::{Deserialize, Serialize};
+use tokio::{io::AsyncWriteExt, task::JoinHandle};
+use tracing::{debug, warn};
+
+/// http sinks are given this much time, so that an unreachable sink doesn't hold the query
+const HTTP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// summaries being written, waited for by `flush` before exiting
+static PENDING: Mutex<Vec<JoinHandle<()>>> = Mutex::new(Vec::new());
+
+/// where summaries are written, each summary is a single line of json
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Sink {
+    /// appended to the file
+    File(PathBuf),
+    /// written to the unix stream socket
+    Socket(PathBuf),
+    /// posted to the url
+    Url(String),
+}
+
+/// details of an executed query, headers and body are not included
+#[derive(Debug, Serialize)]
+pub struct Summary<'a> {
+    /// rfc3339 timestamp of the response
+    pub time: String,
+    /// user running the query, from `USER` environment variable
+    pub user: Option<String>,
+    pub project: &'a str,
+    pub query: &'a str,
+    pub env: &'a str,
+    pub method: &'a str,
+    /// sensitive params are redacted
+    pub url: &'a str,
+    pub status: u16,
+    pub duration_ms: u128,
+}
+
+impl Sink {
+    async fn write(&self, line: Arc<[u8]>) -> miette::Result<()> {
+        match self {
+            Sink::File(path) => {
+                let file_path = path.clone();
+                let line = Arc::clone(&line);
+                tokio::task::spawn_blocking(move || {
+                    use std::io::Write;
+                    std::fs::OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(file_path)
+                        .and_then(|mut file| file.write_all(&line))
+                })
+                .await
+                .into_diagnostic()
+                .and_then(IntoDiagnostic::into_diagnostic)
+                .wrap_err_with(|| format!("Couldn't write to {path:?}"))
+            }
+            Sink::Socket(path) => {
+                let written = async {
+                    let mut socket = tokio::net::UnixStream::connect(path).await?;
+                    socket.write_all(&line).await
+                };
+                written
+                    .await
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Couldn't write to socket {path:?}"))
+            }
+            Sink::Url(url) => {
+                reqwest::Client::new()
+                    .post(url)
+                    .header(reqwest::header::CONTENT_TYPE, "application/json")
+                    .body(line.to_vec())
+                    .timeout(HTTP_TIMEOUT)
+                    .send()
+                    .await
+                    .and_then(reqwest::Response::error_for_status)
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Couldn't post to {url}"))?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// write the summary to all the sinks in background
+pub fn publish(sinks: &[Sink], summary: &Summary<'_>) {
+    if sinks.is_empty() {
+        return;
+    }
+    let mut line = match serde_json::to_vec(summary) {
+        Ok(line) => line,
+        Err(e) => {
+            warn!("Couldn't serialize summary for sinks: {e}");
+            return;
+        }
+    };
+    line.push(b'\n');
+    let line: Arc<[u8]> = line.into();
+    let mut pending = PENDING.lock().expect("pending sinks lock is poisoned");
+    for sink in sinks {
+        debug!("writing summary to {sink:?}");
+        let sink = sink.clone();
+        let line = Arc::clone(&line);
+        pending.push(tokio::spawn(async move {
+            if let Err(e) = sink.write(line).await {
+                warn!("sink failed: {e:?}");
+            }
+        }));
+    }
+}
+
+/// wait for the summaries being written, call before exiting so that they are not lost
+pub async fn flush() {
+    let pending = std::mem::take(&mut *PENDING.lock().expect("pending sinks lock is poisoned"));
+    for handle in pending {
+        if let Err(e) = handle.await {
+            warn!("sink failed: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn file_sink_appends_lines() {
+        let path = std::env::temp_dir().join(format!("qwicket-sink-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let sinks: Vec<Sink> = toml::from_str::<toml::Table>(&format!(
+            "sinks = [{{ file = {path:?} }}, {{ socket = \"/nonexistent/audit.sock\" }}]"
+        ))
+        .unwrap()["sinks"]
+            .clone()
+            .try_into()
+            .unwrap();
+        let summary = Summary {
+            time: "2024-01-01T00:00:00.000Z".to_string(),
+            user: None,
+            project: "demo",
+            query: "users.get",
+            env: "dev",
+            method: "GET",
+            url: "http://localhost/users",
+            status: 200,
+            duration_ms: 12,
+        };
+        publish(&sinks, &summary);
+        publish(&sinks, &summary);
+        flush().await;
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(content.lines().count(), 2);
+        let line: serde_json::Value =
+            serde_json::from_str(content.lines().next().unwrap()).unwrap();
+        assert_eq!(line["query"], "users.get");
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -6,7 +6,8 @@ use std::{
     ops::{Deref, DerefMut},
 };
 
-use miette::Diagnostic;
+use miette::{Context, Diagnostic, IntoDiagnostic};
+use serde::{Deserialize, Serialize};
 use tracing::{debug, error, instrument, trace, warn};
 
 /// per environment config store
@@ -36,6 +37,133 @@ pub struct Store {
     persistent: bool,
     package: std::path::PathBuf,
     used_with_env: bool,
+    /// expiry of the values, kept next to the store file
+    expiry: crate::cache::Cache<Expiry>,
+    /// values before the transaction began, restored on rollback
+    snapshot: Option<Snapshot>,
+    /// who started the current transaction, recorded in audit log
+    source: String,
+    /// values filled from seed for the keys missing in store, these are not written back unless changed
+    seeded: HashMap<String, String>,
+}
+
+/// encrypted file committed with the project, containing default store values for every environment
+/// decrypted content should be json map of environment to key value pairs
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Seed {
+    file: std::path::PathBuf,
+    /// command which writes decrypted content of the file to stdout, file path is appended as last argument
+    #[serde(default = "default_seed_decrypt")]
+    decrypt: Vec<String>,
+}
+
+fn default_seed_decrypt() -> Vec<String> {
+    [
+        "sops",
+        "--decrypt",
+        "--input-type",
+        "json",
+        "--output-type",
+        "json",
+    ]
+    .map(str::to_string)
+    .to_vec()
+}
+
+impl Seed {
+    /// decrypt the seed file and get the values of given environment
+    pub fn read(&self, env: &str) -> miette::Result<HashMap<String, String>> {
+        let Some((program, args)) = self.decrypt.split_first() else {
+            miette::bail!("seed decrypt command is empty")
+        };
+        debug!("decrypting seed {:?} with {program}", self.file);
+        let output = std::process::Command::new(program)
+            .args(args)
+            .arg(&self.file)
+            .stderr(std::process::Stdio::inherit())
+            .output()
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Couldn't run {program}"))?;
+        if !output.status.success() {
+            miette::bail!("{program} exited with error: {}", output.status)
+        }
+        let mut seeds = serde_json::from_slice::<EnvStore>(&output.stdout)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Invalid content in seed {:?}", self.file))?;
+        Ok(seeds.remove(env).unwrap_or_default())
+    }
+}
+
+/// when the value of a key expires and the query which refreshes it
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Expiry {
+    /// rfc3339 timestamp
+    expires_at: String,
+    refreshed_by: Option<String>,
+}
+
+/// state of the store before the transaction began
+#[derive(Debug)]
+struct Snapshot {
+    config: HashMap<String, String>,
+    expiry: crate::cache::Cache<Expiry>,
+}
+
+/// warn if the value of the key has expired
+pub fn warn_if_expired(expiry: &crate::cache::Cache<Expiry>, key: &str) {
+    let Some(Expiry {
+        expires_at,
+        refreshed_by,
+    }) = expiry.get(key)
+    else {
+        return;
+    };
+    let Ok(expires_at_time) = humantime::parse_rfc3339(expires_at) else {
+        warn!("invalid expiry time {expires_at} for {key}");
+        return;
+    };
+    if expires_at_time > std::time::SystemTime::now() {
+        return;
+    }
+    match refreshed_by {
+        Some(query) => warn!("{key} has expired at {expires_at}, run `{query}` to refresh it"),
+        None => warn!("{key} has expired at {expires_at}"),
+    }
+}
+
+/// single change of a store value, appended to the audit log of the project
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AuditEntry {
+    /// rfc3339 timestamp of the change
+    pub time: String,
+    pub env: String,
+    /// query or command which changed the value
+    pub source: String,
+    pub key: String,
+    /// None if the key is newly inserted
+    pub old: Option<String>,
+    /// None if the key is removed
+    pub new: Option<String>,
+}
+
+/// changes between two states of the store, sorted by key
+fn diff(
+    old: &HashMap<String, String>,
+    new: &HashMap<String, String>,
+) -> Vec<(String, Option<String>, Option<String>)> {
+    let mut changes: Vec<_> = old
+        .iter()
+        .filter(|(key, value)| new.get(*key) != Some(value))
+        .map(|(key, value)| (key.clone(), Some(value.clone()), new.get(key).cloned()))
+        .chain(
+            new.iter()
+                .filter(|(key, _)| !old.contains_key(*key))
+                .map(|(key, value)| (key.clone(), None, Some(value.clone()))),
+        )
+        .collect();
+    changes.sort();
+    changes
 }
 
 #[derive(Debug, thiserror::Error, Diagnostic)]
@@ -77,12 +205,18 @@ impl Store {
         config_path.push(package);
         debug!("config store path: {config_path:?}");
         let mut pairs = read_env_store(&config_path)?;
+        let mut expiry_path = config_path.clone().into_os_string();
+        expiry_path.push(".expiry");
         Ok(Self {
             config: pairs.remove(&current_env).unwrap_or_default(),
+            expiry: crate::cache::Cache::open(expiry_path.into(), &current_env),
             current_env,
             persistent: true,
             package: config_path,
             used_with_env: false,
+            snapshot: None,
+            source: String::new(),
+            seeded: HashMap::new(),
         })
     }
 
@@ -113,6 +247,175 @@ impl Store {
         );
         self.persistent = is_persistent;
     }
+
+    pub fn is_persistent(&self) -> bool {
+        self.persistent
+    }
+
+    /// use seed values for the keys which are not present in store
+    pub fn seed(&mut self, seeds: HashMap<String, String>) {
+        for (key, value) in seeds {
+            if !self.config.contains_key(&key) {
+                self.config.insert(key.clone(), value.clone());
+                self.seeded.insert(key, value);
+            }
+        }
+    }
+
+    /// explicitly set value is written back even when it is same as the seed
+    pub fn set(&mut self, key: String, value: String) {
+        self.seeded.remove(&key);
+        self.config.insert(key, value);
+    }
+
+    /// mark the value of the key to expire after given duration
+    /// source of the current transaction is saved as the query which refreshes it
+    pub fn set_expiry(&mut self, key: &str, expires_in: std::time::Duration) {
+        let expires_at = std::time::SystemTime::now() + expires_in;
+        let expires_at = humantime::format_rfc3339_seconds(expires_at).to_string();
+        debug!("{key} expires at {expires_at}");
+        let refreshed_by = Some(self.source.clone()).filter(|source| !source.is_empty());
+        self.expiry.insert(
+            key.to_string(),
+            Expiry {
+                expires_at,
+                refreshed_by,
+            },
+        );
+    }
+
+    /// value of the key doesn't expire anymore
+    pub fn clear_expiry(&mut self, key: &str) {
+        self.expiry.remove(key);
+    }
+
+    /// expiry of the values, used to warn when an expired value is substituted
+    pub fn expiry(&self) -> &crate::cache::Cache<Expiry> {
+        &self.expiry
+    }
+
+    /// start a transaction, changes after this are either applied together with commit or discarded with rollback
+    /// source is the query or command making the changes, used in audit log
+    pub fn begin(&mut self, source: impl Into<String>) {
+        if self.snapshot.is_some() {
+            warn!("transaction is already in progress, changes are merged to it");
+            return;
+        }
+        self.source = source.into();
+        trace!(source = self.source, "beginning store transaction");
+        self.snapshot = Some(Snapshot {
+            config: self.config.clone(),
+            expiry: self.expiry.clone(),
+        });
+    }
+
+    /// keep all the changes done in the transaction and record them in audit log
+    pub fn commit(&mut self) {
+        trace!("committing store transaction");
+        let Some(snapshot) = self.snapshot.take() else {
+            return;
+        };
+        let time = humantime::format_rfc3339_seconds(std::time::SystemTime::now()).to_string();
+        let entries: Vec<_> = diff(&snapshot.config, &self.config)
+            .into_iter()
+            .map(|(key, old, new)| AuditEntry {
+                time: time.clone(),
+                env: self.current_env.clone(),
+                source: self.source.clone(),
+                key,
+                old,
+                new,
+            })
+            .collect();
+        if let Err(e) = self.append_audit_log(&entries) {
+            warn!("Couldn't write store audit log: {e}");
+        }
+    }
+
+    /// name of the environment the store is opened with
+    pub fn environment(&self) -> &str {
+        &self.current_env
+    }
+
+    /// path next to the store file with given extension
+    fn sibling(&self, extension: &str) -> std::path::PathBuf {
+        let mut path = self.package.clone().into_os_string();
+        path.push(".");
+        path.push(extension);
+        path.into()
+    }
+
+    /// directory of the archived responses, kept next to the store file
+    pub fn archive_dir(&self) -> std::path::PathBuf {
+        self.sibling("archive")
+    }
+
+    /// internal values of the queries of the current environment, kept next to the store file
+    pub fn cache<T>(&self, name: &str) -> crate::cache::Cache<T>
+    where
+        T: Serialize + serde::de::DeserializeOwned + Clone,
+    {
+        crate::cache::Cache::open(self.sibling(name), &self.current_env)
+    }
+
+    /// file of the latency baselines, kept next to the store file
+    pub fn baseline_path(&self) -> std::path::PathBuf {
+        self.sibling("baseline")
+    }
+
+    /// path of the append only log of store changes, kept next to the store file
+    fn audit_log_path(&self) -> std::path::PathBuf {
+        self.sibling("log")
+    }
+
+    fn append_audit_log(&self, entries: &[AuditEntry]) -> std::io::Result<()> {
+        use std::io::Write;
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let mut log = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.audit_log_path())?;
+        for entry in entries {
+            let mut line = serde_json::to_vec(entry)?;
+            line.push(b'\n');
+            log.write_all(&line)?;
+        }
+        Ok(())
+    }
+
+    /// all the recorded changes of the project store, oldest first
+    pub fn audit_log(&self) -> Result<Vec<AuditEntry>, StoreError> {
+        let path = self.audit_log_path();
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                warn!("Couldn't read audit log {path:?}: {e}");
+                return Err(StoreError::InvalidPath);
+            }
+        };
+        content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                serde_json::from_str(line).map_err(|e| {
+                    error!("Invalid entry in audit log {path:?}: {e}");
+                    StoreError::CorruptedPackage
+                })
+            })
+            .collect()
+    }
+
+    /// discard all the changes done in the transaction
+    pub fn rollback(&mut self) {
+        if let Some(snapshot) = self.snapshot.take() {
+            debug!("rolling back store transaction");
+            self.config = snapshot.config;
+            self.expiry = snapshot.expiry;
+        }
+    }
 }
 
 impl Deref for Store {
@@ -131,7 +434,14 @@ impl DerefMut for Store {
 
 impl Drop for Store {
     fn drop(&mut self) {
+        // unfinished transaction is not written back
+        self.rollback();
         trace!("writing configurations back to file: {:?}", self.package);
+        for (key, seed_val) in &self.seeded {
+            if self.config.get(key).is_some_and(|val| val == seed_val) {
+                self.config.remove(key);
+            }
+        }
         if self.used_with_env {
             std::env::vars().for_each(|(key, env_val)| {
                 if self.config.get(&key).is_some_and(|val| val == &env_val) {
@@ -140,6 +450,7 @@ impl Drop for Store {
             })
         }
         let env_store = self.config.drain().collect();
+        self.expiry.save();
 
         let mut store = match read_env_store(&self.package) {
             Ok(store) => store,
@@ -195,4 +506,118 @@ mod tests {
         new_store.persistent(false);
         assert_eq!(new_store.get(&key), Some(&value));
     }
+
+    #[traced_test]
+    #[test]
+    fn transaction_rollback_and_commit() {
+        let mut store = Store::open(&"test_package_transaction", "dev".to_string()).unwrap();
+        store.persistent(false);
+        store.insert("key".to_string(), "old".to_string());
+
+        store.begin("test");
+        store.insert("key".to_string(), "new".to_string());
+        store.insert("other".to_string(), "value".to_string());
+        store.rollback();
+        assert_eq!(store.get("key").map(String::as_str), Some("old"));
+        assert_eq!(store.get("other"), None);
+
+        store.begin("test");
+        store.insert("key".to_string(), "new".to_string());
+        store.commit();
+        store.rollback();
+        assert_eq!(store.get("key").map(String::as_str), Some("new"));
+    }
+
+    #[test]
+    fn diff_changes() {
+        let old = HashMap::from([
+            ("same".to_string(), "1".to_string()),
+            ("changed".to_string(), "old".to_string()),
+            ("removed".to_string(), "gone".to_string()),
+        ]);
+        let new = HashMap::from([
+            ("same".to_string(), "1".to_string()),
+            ("changed".to_string(), "new".to_string()),
+            ("added".to_string(), "here".to_string()),
+        ]);
+        assert_eq!(
+            diff(&old, &new),
+            vec![
+                ("added".to_string(), None, Some("here".to_string())),
+                (
+                    "changed".to_string(),
+                    Some("old".to_string()),
+                    Some("new".to_string())
+                ),
+                ("removed".to_string(), Some("gone".to_string()), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn seed_doesnt_overwrite() {
+        let mut store = Store::open(&"test_package_seed", "dev".to_string()).unwrap();
+        store.persistent(false);
+        store.insert("token".to_string(), "personal".to_string());
+        store.seed(HashMap::from([
+            ("token".to_string(), "shared".to_string()),
+            ("base".to_string(), "shared".to_string()),
+        ]));
+        assert_eq!(store.get("token").map(String::as_str), Some("personal"));
+        assert_eq!(store.get("base").map(String::as_str), Some("shared"));
+        assert_eq!(
+            store.seeded,
+            HashMap::from([("base".to_string(), "shared".to_string())])
+        );
+        store.set("base".to_string(), "shared".to_string());
+        assert!(store.seeded.is_empty());
+        store.remove("base");
+    }
+
+    #[test]
+    fn expiry_is_saved() {
+        let mut store = Store::open(&"test_package_expiry", "dev".to_string()).unwrap();
+        store.persistent(false);
+        store.begin("auth.login");
+        store.set_expiry("token", std::time::Duration::from_secs(60));
+        store.commit();
+        assert!(store.keys().all(|key| key != "expires_at:token"));
+        assert_eq!(
+            store
+                .expiry()
+                .get("token")
+                .and_then(|expiry| expiry.refreshed_by.as_deref()),
+            Some("auth.login")
+        );
+        store.begin("auth.logout");
+        store.clear_expiry("token");
+        store.rollback();
+        assert!(store.expiry().get("token").is_some());
+    }
+
+    #[traced_test]
+    #[test]
+    fn expired_value_warns() {
+        let path = std::env::temp_dir().join(format!("qwicket-expiry-{}", std::process::id()));
+        let mut expiry = crate::cache::Cache::open(path, "dev");
+        let expiry_at = |at: std::time::SystemTime| Expiry {
+            expires_at: humantime::format_rfc3339_seconds(at).to_string(),
+            refreshed_by: Some("auth.login".to_string()),
+        };
+        let now = std::time::SystemTime::now();
+        expiry.insert(
+            "token".to_string(),
+            expiry_at(now - std::time::Duration::from_secs(60)),
+        );
+        expiry.insert(
+            "refresh".to_string(),
+            expiry_at(now + std::time::Duration::from_secs(60)),
+        );
+        warn_if_expired(&expiry, "refresh");
+        warn_if_expired(&expiry, "user");
+        assert!(!logs_contain("has expired"));
+        warn_if_expired(&expiry, "token");
+        assert!(logs_contain("token has expired"));
+        assert!(logs_contain("run `auth.login` to refresh it"));
+    }
 }
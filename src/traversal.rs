@@ -0,0 +1,203 @@
+//! rules for reading the api directory, which entries are skipped and how symlinked directories are followed
+
+use std::path::{Path, PathBuf};
+
+use miette::{Context, IntoDiagnostic};
+use serde::Deserialize;
+use tracing::{debug, trace, warn};
+
+fn default_follow_symlinks() -> bool {
+    true
+}
+
+/// project level traversal settings
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    /// read symlinked directories, symlinks pointing to one of their parents are skipped
+    #[serde(default = "default_follow_symlinks")]
+    pub follow_symlinks: bool,
+    /// read files and directories whose name starts with `.`
+    #[serde(default)]
+    pub hidden: bool,
+    /// glob patterns of paths relative to api directory which are not read, ex: "drafts/**"
+    #[serde(default)]
+    pub ignore: Vec<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            follow_symlinks: default_follow_symlinks(),
+            hidden: false,
+            ignore: Vec::new(),
+        }
+    }
+}
+
+/// state of reading the api directory
+pub struct Traversal<'s> {
+    root: PathBuf,
+    settings: &'s Settings,
+    ignore: Vec<wildmatch::WildMatch>,
+    /// canonical paths of the directories being read, to detect symlink cycles
+    ancestors: Vec<PathBuf>,
+}
+
+impl<'s> Traversal<'s> {
+    pub fn new(root: &Path, settings: &'s Settings) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            settings,
+            ignore: settings
+                .ignore
+                .iter()
+                .map(|pattern| wildmatch::WildMatch::new(pattern))
+                .collect(),
+            ancestors: Vec::new(),
+        }
+    }
+
+    /// directory is being read, its entries can't link back to it
+    pub fn enter(&mut self, directory: &Path) -> miette::Result<()> {
+        let canonical = directory
+            .canonicalize()
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Couldn't resolve {directory:?}"))?;
+        self.ancestors.push(canonical);
+        Ok(())
+    }
+
+    /// all the entries of the last entered directory are read
+    pub fn leave(&mut self) {
+        self.ancestors.pop();
+    }
+
+    /// whether the entry should be read as a group
+    pub fn include(&self, entry: &std::fs::DirEntry) -> bool {
+        let path = entry.path();
+        if !self.settings.hidden && entry.file_name().to_string_lossy().starts_with('.') {
+            trace!("skipping hidden entry {path:?}");
+            return false;
+        }
+        let is_dir = path.is_dir();
+        if self.is_ignored(&path, is_dir) {
+            debug!("skipping ignored entry {path:?}");
+            return false;
+        }
+        if !is_dir {
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "toml")
+            {
+                return true;
+            }
+            warn!("ignoring non toml file: {path:?}");
+            return false;
+        }
+        if !entry
+            .file_type()
+            .is_ok_and(|file_type| file_type.is_symlink())
+        {
+            return true;
+        }
+        if !self.settings.follow_symlinks {
+            debug!("not following symlinked directory {path:?}");
+            return false;
+        }
+        match path.canonicalize() {
+            Ok(target) if self.ancestors.contains(&target) => {
+                warn!("skipping {path:?}, it links to its parent {target:?}");
+                false
+            }
+            Ok(_) => true,
+            Err(e) => {
+                warn!("skipping {path:?}, couldn't resolve symlink: {e}");
+                false
+            }
+        }
+    }
+
+    /// path relative to the root is matched with `/` as separator, directories are also matched with trailing `/`
+    fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        if self.ignore.is_empty() {
+            return false;
+        }
+        let relative = path
+            .strip_prefix(&self.root)
+            .unwrap_or(path)
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        self.ignore.iter().any(|pattern| {
+            pattern.matches(&relative) || (is_dir && pattern.matches(&format!("{relative}/")))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ignore_patterns() {
+        let settings = Settings {
+            ignore: vec!["drafts/**".to_string(), "*.local.toml".to_string()],
+            ..Settings::default()
+        };
+        let traversal = Traversal::new(Path::new("services"), &settings);
+        assert!(traversal.is_ignored(Path::new("services/drafts"), true));
+        assert!(traversal.is_ignored(Path::new("services/drafts/users.toml"), false));
+        assert!(traversal.is_ignored(Path::new("services/users.local.toml"), false));
+        assert!(!traversal.is_ignored(Path::new("services/drafts.toml"), false));
+        assert!(!traversal.is_ignored(Path::new("services/users.toml"), false));
+    }
+
+    /// names of the entries of the directory which are included, directory is entered before reading it
+    fn included(traversal: &mut Traversal, directory: &Path) -> Vec<String> {
+        traversal.enter(directory).unwrap();
+        let mut names: Vec<_> = std::fs::read_dir(directory)
+            .unwrap()
+            .map(Result::unwrap)
+            .filter(|entry| traversal.include(entry))
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort_unstable();
+        names
+    }
+
+    #[test]
+    fn hidden_entries_and_symlinks() {
+        let root = std::env::temp_dir().join(format!("qwicket-traversal-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("users")).unwrap();
+        std::fs::create_dir_all(root.join(".drafts")).unwrap();
+        for file in ["index.toml", ".secret.toml", "notes.txt", "users/me.toml"] {
+            std::fs::write(root.join(file), "").unwrap();
+        }
+        // link to the parent is a cycle, link to a sibling is not
+        std::os::unix::fs::symlink(&root, root.join("users/root")).unwrap();
+        std::os::unix::fs::symlink(root.join("users"), root.join("people")).unwrap();
+
+        let settings = Settings::default();
+        let mut traversal = Traversal::new(&root, &settings);
+        assert_eq!(
+            included(&mut traversal, &root),
+            ["index.toml", "people", "users"]
+        );
+        assert_eq!(included(&mut traversal, &root.join("users")), ["me.toml"]);
+
+        let settings = Settings {
+            follow_symlinks: false,
+            hidden: true,
+            ignore: Vec::new(),
+        };
+        let mut traversal = Traversal::new(&root, &settings);
+        assert_eq!(
+            included(&mut traversal, &root),
+            [".drafts", ".secret.toml", "index.toml", "users"]
+        );
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,275 @@
+//! rewrites config and group files written for older versions to the current schema, run with `--upgrade-config`
+//! original files are kept as `<file>.<old version>.bak`
+
+use std::path::{Path, PathBuf};
+
+use miette::{Context, IntoDiagnostic};
+use tracing::{debug, info};
+
+/// schema change introduced in a version
+struct Migration {
+    /// version in which the schema changed, applied to files of older versions
+    version: semver::Version,
+    description: &'static str,
+    /// rewrite of the project config file
+    config: fn(&mut toml_edit::DocumentMut),
+    /// rewrite of each group file
+    group: fn(&mut toml_edit::DocumentMut),
+}
+
+/// schema changes in the order of versions, add a step whenever a field is renamed or restructured
+const MIGRATIONS: &[Migration] = &[Migration {
+    version: semver::Version::new(0, 5, 0),
+    description: "health.max_latency_ms is replaced by max_latency duration",
+    config: |_| {},
+    group: |document| health_latency(document.as_item_mut()),
+}];
+
+/// `health = { max_latency_ms = 500 }` of the queries as `health = { max_latency = "500ms" }`
+fn health_latency(item: &mut toml_edit::Item) {
+    let Some(table) = item.as_table_like_mut() else {
+        return;
+    };
+    if let Some(health) = table
+        .get_mut("health")
+        .and_then(toml_edit::Item::as_table_like_mut)
+    {
+        if let Some(latency) = health
+            .get("max_latency_ms")
+            .and_then(toml_edit::Item::as_integer)
+        {
+            health.remove("max_latency_ms");
+            health.insert("max_latency", toml_edit::value(format!("{latency}ms")));
+        }
+    }
+    for (_, child) in table.iter_mut() {
+        health_latency(child);
+    }
+}
+
+/// migrations needed for files of version `from` to be read by version `to`
+fn steps<'m>(
+    migrations: &'m [Migration],
+    from: &'m semver::Version,
+    to: &'m semver::Version,
+) -> impl Iterator<Item = &'m Migration> {
+    migrations
+        .iter()
+        .filter(move |migration| from < &migration.version && &migration.version <= to)
+}
+
+/// same check as while opening the config, 0 major versions break at minor versions
+fn is_supported(config: &semver::Version, binary: &semver::Version) -> bool {
+    config.major == binary.major && (binary.major != 0 || config.minor == binary.minor)
+}
+
+/// upgrade the config and all the group files of its api directory to the version of the binary
+pub fn config(path: &Path) -> miette::Result<()> {
+    let current =
+        semver::Version::parse(env!("CARGO_PKG_VERSION")).expect("cargo pkg is not semver?");
+    let mut document = read(path)?;
+    let version = document
+        .get("version")
+        .and_then(toml_edit::Item::as_str)
+        .ok_or_else(|| miette::miette!("Couldn't find version in {path:?}"))?;
+    let version = semver::Version::parse(version)
+        .into_diagnostic()
+        .wrap_err("Invalid config version")?;
+    if version > current {
+        miette::bail!(
+            help = "update the binary instead",
+            "config is written for {version}, which is newer than {current}"
+        )
+    }
+    if is_supported(&version, &current) {
+        info!("config version {version} is already supported by {current}");
+        return Ok(());
+    }
+
+    let steps: Vec<_> = steps(MIGRATIONS, &version, &current).collect();
+    let api_directory = document
+        .get("api_directory")
+        .and_then(toml_edit::Item::as_str)
+        .map(PathBuf::from)
+        .ok_or_else(|| miette::miette!("Couldn't find api_directory in {path:?}"))?;
+    // group files are found the same way as while reading the api directory
+    let traversal: crate::traversal::Settings =
+        toml::from_str::<toml::Table>(&document.to_string())
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Couldn't parse {path:?}"))?
+            .remove("traversal")
+            .map(toml::Value::try_into)
+            .transpose()
+            .into_diagnostic()
+            .wrap_err("Invalid traversal settings")?
+            .unwrap_or_default();
+    let mut group_files = Vec::new();
+    collect_toml_files(
+        &api_directory,
+        &mut crate::traversal::Traversal::new(&api_directory, &traversal),
+        &mut group_files,
+    )?;
+
+    for step in &steps {
+        info!("upgrading to {}: {}", step.version, step.description);
+        (step.config)(&mut document);
+    }
+    document["version"] = toml_edit::value(current.to_string());
+    write(path, &document, &version)?;
+    for file in &group_files {
+        let mut group = read(file)?;
+        let original = group.to_string();
+        for step in &steps {
+            (step.group)(&mut group);
+        }
+        if group.to_string() != original {
+            write(file, &group, &version)?;
+        }
+    }
+    eprintln!(
+        "upgraded config from {version} to {current} with {} migrations, {} group files checked",
+        steps.len(),
+        group_files.len()
+    );
+    Ok(())
+}
+
+fn read(path: &Path) -> miette::Result<toml_edit::DocumentMut> {
+    std::fs::read_to_string(path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Couldn't read {path:?}"))?
+        .parse()
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Couldn't parse {path:?}"))
+}
+
+/// keep the original file as backup and write the upgraded one
+fn write(
+    path: &Path,
+    document: &toml_edit::DocumentMut,
+    version: &semver::Version,
+) -> miette::Result<()> {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(format!(".{version}.bak"));
+    std::fs::copy(path, &backup)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Couldn't backup {path:?}"))?;
+    debug!("backup of {path:?} is saved in {backup:?}");
+    std::fs::write(path, document.to_string())
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Couldn't write {path:?}"))
+}
+
+/// toml files in the directory and its sub directories which are read as groups
+fn collect_toml_files(
+    directory: &Path,
+    traversal: &mut crate::traversal::Traversal,
+    files: &mut Vec<PathBuf>,
+) -> miette::Result<()> {
+    traversal.enter(directory)?;
+    let mut entries = std::fs::read_dir(directory)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Couldn't read {directory:?}"))?
+        .collect::<Result<Vec<_>, _>>()
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Invalid file entry in {directory:?}"))?;
+    entries.sort_by_key(std::fs::DirEntry::file_name);
+    entries.retain(|entry| traversal.include(entry));
+    for entry in entries {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_toml_files(&path, traversal, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    traversal.leave();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rename_output(document: &mut toml_edit::DocumentMut) {
+        if let Some(output) = document.remove("outputs") {
+            document.insert("output", output);
+        }
+    }
+
+    #[test]
+    fn migration_steps() {
+        let migrations = [
+            Migration {
+                version: semver::Version::new(0, 4, 0),
+                description: "old",
+                config: |_| {},
+                group: |_| {},
+            },
+            Migration {
+                version: semver::Version::new(0, 5, 0),
+                description: "rename outputs",
+                config: rename_output,
+                group: |_| {},
+            },
+        ];
+        let from = semver::Version::new(0, 4, 2);
+        let to = semver::Version::new(0, 5, 1);
+        let steps: Vec<_> = steps(&migrations, &from, &to).collect();
+        assert_eq!(steps.len(), 1);
+
+        let mut document: toml_edit::DocumentMut =
+            "version = \"0.4.2\"\n[outputs]\n\"text/plain\" = \"raw\"\n"
+                .parse()
+                .unwrap();
+        (steps[0].config)(&mut document);
+        assert!(document.contains_key("output"));
+        assert!(!is_supported(&from, &to));
+        assert!(is_supported(&semver::Version::new(0, 5, 0), &to));
+    }
+
+    #[test]
+    fn upgrade_health_latency() {
+        let dir = std::env::temp_dir().join(format!("qwicket-upgrade-{}", std::process::id()));
+        let services = dir.join("services");
+        std::fs::create_dir_all(services.join("drafts")).unwrap();
+        let config_file = dir.join("qwicket.toml");
+        std::fs::write(
+            &config_file,
+            format!(
+                "version = \"0.4.0\"\nproject = \"test\"\napi_directory = {services:?}\n\n[traversal]\nignore = [\"drafts/**\"]\n"
+            ),
+        )
+        .unwrap();
+        let group = r#"type = "http"
+
+[query.ping]
+method = "GET"
+path = "/ping" # liveness
+health = { status = [200], max_latency_ms = 500 }
+
+[query.ready.health]
+max_latency_ms = 1000
+"#;
+        for file in ["users.toml", ".hidden.toml", "drafts/old.toml"] {
+            std::fs::write(services.join(file), group).unwrap();
+        }
+
+        config(&config_file).unwrap();
+        let upgraded = std::fs::read_to_string(services.join("users.toml")).unwrap();
+        assert_eq!(
+            upgraded,
+            group
+                .replace("max_latency_ms = 500", "max_latency = \"500ms\"")
+                .replace("max_latency_ms = 1000", "max_latency = \"1000ms\"")
+        );
+        assert!(services.join("users.toml.0.4.0.bak").exists());
+        // files which are not read as groups are not touched
+        for file in [".hidden.toml", "drafts/old.toml"] {
+            assert_eq!(std::fs::read_to_string(services.join(file)).unwrap(), group);
+        }
+        let config = std::fs::read_to_string(&config_file).unwrap();
+        assert!(config.starts_with(&format!("version = \"{}\"", env!("CARGO_PKG_VERSION"))));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,344 @@
+//! declaration of variables used by a query, used for validation, listing and prompting missing values
+
+use std::{collections::HashMap, io::IsTerminal};
+
+use indexmap::IndexMap;
+use miette::{Context, IntoDiagnostic};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+/// type of the variable value
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    #[default]
+    String,
+    Int,
+    Bool,
+    Enum,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(from = "Declaration")]
+pub struct Variable {
+    #[serde(rename = "type")]
+    kind: Kind,
+    description: Option<String>,
+    /// used when value is not given
+    default: Option<String>,
+    /// ask the value if it is not given(on terminal) else fail
+    required: bool,
+    /// allowed values of enum
+    values: Vec<String>,
+}
+
+/// variable can be declared as a table or just as list of enum values
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Declaration {
+    /// `region = ["us", "eu"]`
+    Choices(Vec<String>),
+    Table(Table),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Table {
+    #[serde(rename = "type", default)]
+    kind: Kind,
+    description: Option<String>,
+    default: Option<String>,
+    #[serde(default)]
+    required: bool,
+    #[serde(default)]
+    values: Vec<String>,
+}
+
+impl From<Declaration> for Variable {
+    fn from(value: Declaration) -> Self {
+        match value {
+            Declaration::Choices(values) => Self {
+                kind: Kind::Enum,
+                description: None,
+                default: None,
+                required: false,
+                values,
+            },
+            Declaration::Table(Table {
+                kind,
+                description,
+                default,
+                required,
+                values,
+            }) => Self {
+                kind,
+                description,
+                default,
+                required,
+                values,
+            },
+        }
+    }
+}
+
+impl Variable {
+    fn validate(&self, value: &str) -> Result<(), String> {
+        match self.kind {
+            Kind::String => Ok(()),
+            Kind::Int => value
+                .parse::<i64>()
+                .map(|_| ())
+                .map_err(|e| format!("expected int: {e}")),
+            Kind::Bool => value
+                .parse::<bool>()
+                .map(|_| ())
+                .map_err(|_| "expected true or false".to_string()),
+            Kind::Enum if self.values.iter().any(|allowed| allowed == value) => Ok(()),
+            Kind::Enum => Err(format!("expected one of {}", self.values.join(", "))),
+        }
+    }
+}
+
+impl std::fmt::Display for Variable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            Kind::Enum => write!(f, "{}", self.values.join("|"))?,
+            ref kind => write!(f, "{}", format!("{kind:?}").to_lowercase())?,
+        }
+        if let Some(default) = &self.default {
+            write!(f, ", default: {default}")?;
+        } else if self.required {
+            write!(f, ", required")?;
+        }
+        if let Some(description) = &self.description {
+            write!(f, ", {description}")?;
+        }
+        Ok(())
+    }
+}
+
+/// validate declared variables against the values, missing values are filled from default or asked on terminal
+/// `external` are the names which are resolved later(secrets, providers), these are not validated
+pub fn resolve(
+    declared: &IndexMap<String, Variable>,
+    values: &mut HashMap<String, String>,
+    external: impl Fn(&str) -> bool,
+) -> miette::Result<()> {
+    for (name, variable) in declared {
+        if let Some(value) = values.get(name) {
+            variable.validate(value).map_err(|reason| {
+                miette::miette!("invalid value \"{value}\" for variable {name}: {reason}")
+            })?;
+            continue;
+        }
+        if external(name) {
+            continue;
+        }
+        let interactive = std::io::stdin().is_terminal();
+        if let Some(default) = &variable.default {
+            debug!("using default value for {name}");
+            values.insert(name.clone(), default.clone());
+        } else if interactive && variable.kind == Kind::Enum {
+            values.insert(name.clone(), pick(name, variable)?);
+        } else if variable.required {
+            if !interactive {
+                miette::bail!(
+                    help = format!("set it with --var {name}=<value>"),
+                    "variable {name}({variable}) is required"
+                )
+            }
+            values.insert(name.clone(), prompt(name, variable)?);
+        }
+    }
+    Ok(())
+}
+
+/// substitute variables in the text, `$$` is written as literal `$`(ex: `$${name}` gives `${name}`)
+/// references like `${vault:<path>#<key>}` are read from their store before the variables
+pub fn substitute(
+    text: &str,
+    variables: &crate::secret::Variables,
+) -> Result<String, subst::Error> {
+    let escaped = escape(text);
+    subst::substitute(&variables.references(&escaped)?, variables)
+}
+
+/// `$$` as backslash escaped `\$` of subst, read from left to right so that `$$$name` is `$` followed by `$name`
+/// `$` at the end is literal, backslash escapes are kept as they are
+fn escape(text: &str) -> std::borrow::Cow<'_, str> {
+    if !text.contains('$') {
+        return text.into();
+    }
+    let mut escaped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                escaped.push(c);
+                escaped.extend(chars.next());
+            }
+            '$' if matches!(chars.peek(), Some('$') | None) => {
+                chars.next();
+                escaped.push_str("\\$");
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped.into()
+}
+
+/// check if the text substitutes given variable as `$name` or `${name}`
+pub fn is_referenced(text: &str, name: &str) -> bool {
+    text.match_indices('$').any(|(position, _)| {
+        let rest = &text[position + 1..];
+        let rest = rest.strip_prefix('{').unwrap_or(rest);
+        rest.strip_prefix(name).is_some_and(|after| {
+            !after.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_')
+        })
+    })
+}
+
+/// `${name}` at the start of the text
+fn pattern(text: &str) -> Option<&str> {
+    let name_len = text
+        .strip_prefix("${")?
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == ':' || c == '-'))?;
+    let end = 2 + name_len;
+    text[end..].starts_with('}').then(|| &text[..=end])
+}
+
+/// `${...}` patterns left in the text after substitution
+pub fn unresolved(text: &str) -> Vec<&str> {
+    text.match_indices("${")
+        .filter_map(|(start, _)| pattern(&text[start..]))
+        .collect()
+}
+
+/// `${...}` patterns which are written as `$${...}` in the text, these are literals after substitution
+pub fn escaped(text: &str) -> Vec<&str> {
+    let mut escaped = Vec::new();
+    let mut position = 0;
+    while let Some(offset) = text[position..].find(['\\', '$']) {
+        let start = position + offset;
+        let rest = &text[start..];
+        if rest.starts_with("$$") {
+            escaped.extend(pattern(&rest[1..]));
+            position = start + 2;
+        } else if rest.starts_with('\\') {
+            // backslash escapes the next character
+            position = start + rest.chars().nth(1).map_or(1, |next| 1 + next.len_utf8());
+        } else {
+            position = start + 1;
+        }
+    }
+    escaped
+}
+
+/// ask value on terminal until a valid one is given
+fn prompt(name: &str, variable: &Variable) -> miette::Result<String> {
+    dialoguer::Input::<String>::new()
+        .with_prompt(format!("{name}({variable})"))
+        .validate_with(|value: &String| variable.validate(value))
+        .interact_text_on(&dialoguer::console::Term::stderr())
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Couldn't read value of {name}"))
+}
+
+/// select one of the enum values on terminal
+fn pick(name: &str, variable: &Variable) -> miette::Result<String> {
+    let selected = dialoguer::Select::new()
+        .with_prompt(match &variable.description {
+            Some(description) => format!("{name}({description})"),
+            None => name.to_string(),
+        })
+        .items(&variable.values)
+        .default(0)
+        .interact_on(&dialoguer::console::Term::stderr())
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Couldn't read value of {name}"))?;
+    Ok(variable.values[selected].clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_and_default() {
+        let declared: IndexMap<String, Variable> = toml::from_str(
+            r#"
+            count = { type = "int", default = "10" }
+            region = ["us", "eu"]
+            verbose = { type = "bool" }
+            "#,
+        )
+        .unwrap();
+        let mut values = HashMap::from([("region".to_string(), "eu".to_string())]);
+        resolve(&declared, &mut values, |_| false).unwrap();
+        assert_eq!(values.get("count").map(String::as_str), Some("10"));
+        assert_eq!(values.get("verbose"), None);
+
+        values.insert("region".to_string(), "ap".to_string());
+        assert!(resolve(&declared, &mut values, |_| false).is_err());
+        values.insert("region".to_string(), "us".to_string());
+        values.insert("count".to_string(), "ten".to_string());
+        assert!(resolve(&declared, &mut values, |_| false).is_err());
+    }
+
+    #[test]
+    fn declaration_order_kept() {
+        // variables are listed and prompted in the order they are declared
+        let declared: IndexMap<String, Variable> = toml::from_str(
+            r#"
+            user = {}
+            region = ["us", "eu"]
+            after = { type = "int" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            declared.keys().collect::<Vec<_>>(),
+            ["user", "region", "after"]
+        );
+    }
+
+    #[test]
+    fn variable_is_referenced() {
+        assert!(is_referenced("/users/${user}/posts", "user"));
+        assert!(is_referenced("/users/$user", "user"));
+        assert!(!is_referenced("/users/$username", "user"));
+        assert!(!is_referenced("/users/user", "user"));
+    }
+
+    #[test]
+    fn escaped_dollar() {
+        let (secrets, providers) = (HashMap::new(), HashMap::new());
+        let values = crate::secret::Variables::new(
+            HashMap::from([("user".to_string(), "admin".to_string())]),
+            &secrets,
+            &providers,
+        );
+        assert_eq!(
+            substitute("echo $${HOME} as ${user}, costs $$5", &values).unwrap(),
+            "echo ${HOME} as admin, costs $5"
+        );
+        assert_eq!(substitute("$$$user", &values).unwrap(), "$admin");
+        assert_eq!(substitute("$$$", &values).unwrap(), "$$");
+        assert_eq!(substitute("$$$${user}", &values).unwrap(), "$${user}");
+        assert_eq!(substitute(r"\$$user", &values).unwrap(), "$admin");
+    }
+
+    #[test]
+    fn unresolved_patterns() {
+        assert_eq!(
+            unresolved(r#"{"token": "${TOKEN}", "id": 4, "user": "${user"}"#),
+            ["${TOKEN}"]
+        );
+        assert!(unresolved("/users/4").is_empty());
+        assert_eq!(
+            escaped(r"$${HOME} $$$${PATH} $${TOKEN"),
+            ["${HOME}", "${PATH}"]
+        );
+        assert!(escaped(r"$$${user} \$${user}").is_empty());
+    }
+}
@@ -0,0 +1,161 @@
+//! when each query of a group run started and how long it took, to see where a flow spends its time
+//! printed as a table at the end of the run and optionally written as html
+
+use std::fmt::Write;
+use std::time::Duration;
+
+use miette::{Context, IntoDiagnostic};
+use tracing::debug;
+
+use crate::markup::escape;
+
+/// number of characters of the timeline column
+const WIDTH: usize = 40;
+
+#[derive(Debug)]
+struct Step {
+    query: String,
+    /// from the start of the run
+    start: Duration,
+    duration: Duration,
+    status: String,
+    failed: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct Waterfall {
+    steps: Vec<Step>,
+}
+
+impl Waterfall {
+    pub fn record(
+        &mut self,
+        query: &str,
+        start: Duration,
+        duration: Duration,
+        status: String,
+        failed: bool,
+    ) {
+        self.steps.push(Step {
+            query: query.to_string(),
+            start,
+            duration,
+            status,
+            failed,
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    fn total(&self) -> Duration {
+        self.steps
+            .iter()
+            .map(|step| step.start + step.duration)
+            .max()
+            .unwrap_or_default()
+    }
+
+    pub fn table(&self) -> comfy_table::Table {
+        let total = self.total();
+        let mut table = crate::parser::default_table_structure();
+        table.set_header(["query", "start", "duration", "status", "timeline"]);
+        for step in &self.steps {
+            let color = if step.failed {
+                comfy_table::Color::Red
+            } else {
+                comfy_table::Color::Green
+            };
+            table.add_row([
+                comfy_table::Cell::new(&step.query),
+                comfy_table::Cell::new(format!("+{} ms", step.start.as_millis())),
+                comfy_table::Cell::new(format!("{} ms", step.duration.as_millis())),
+                comfy_table::Cell::new(&step.status).fg(color),
+                comfy_table::Cell::new(bar(step.start, step.duration, total)).fg(color),
+            ]);
+        }
+        table
+    }
+
+    fn to_html(&self, title: &str) -> String {
+        crate::markup::render(|html| self.write_html(title, html))
+    }
+
+    fn write_html(&self, title: &str, html: &mut String) -> std::fmt::Result {
+        let total = self.total().as_secs_f64().max(f64::EPSILON);
+        let title = escape(title);
+        writeln!(html, "<!DOCTYPE html>")?;
+        writeln!(
+            html,
+            "<html><head><meta charset=\"utf-8\"><title>{title}</title>"
+        )?;
+        writeln!(
+            html,
+            "<style>body{{font-family:sans-serif}}td{{padding:2px 8px}}.lane{{width:600px;background:#eee}}\
+             .bar{{height:14px;background:#4caf50}}.failed .bar{{background:#e53935}}</style>"
+        )?;
+        writeln!(html, "</head><body><h1>{title}</h1><table>")?;
+        writeln!(
+            html,
+            "<tr><th>query</th><th>start</th><th>duration</th><th>status</th><th>timeline</th></tr>"
+        )?;
+        for step in &self.steps {
+            let left = step.start.as_secs_f64() / total * 100.0;
+            let width = (step.duration.as_secs_f64() / total * 100.0).max(0.5);
+            writeln!(
+                html,
+                "<tr class=\"{}\"><td>{}</td><td>+{} ms</td><td>{} ms</td><td>{}</td>\
+                 <td class=\"lane\"><div class=\"bar\" style=\"margin-left:{left:.2}%;width:{width:.2}%\"></div></td></tr>",
+                if step.failed { "failed" } else { "passed" },
+                escape(&step.query),
+                step.start.as_millis(),
+                step.duration.as_millis(),
+                escape(&step.status),
+            )?;
+        }
+        writeln!(html, "</table></body></html>")
+    }
+
+    /// write html waterfall to given file, titled after the project
+    pub fn write(&self, path: &std::path::Path, title: &str) -> miette::Result<()> {
+        debug!("writing waterfall to {path:?}");
+        std::fs::write(path, self.to_html(title))
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Couldn't write waterfall to {path:?}"))
+    }
+}
+
+/// position and length of the step in the timeline, every step gets atleast one character
+fn bar(start: Duration, duration: Duration, total: Duration) -> String {
+    let total = total.as_secs_f64().max(f64::EPSILON);
+    let scale =
+        |duration: Duration| (duration.as_secs_f64() / total * WIDTH as f64).round() as usize;
+    let offset = scale(start).min(WIDTH - 1);
+    let length = scale(duration).clamp(1, WIDTH - offset);
+    format!("{}{}", " ".repeat(offset), "█".repeat(length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timeline_bars() {
+        let ms = Duration::from_millis;
+        assert_eq!(bar(ms(0), ms(100), ms(400)), "█".repeat(10));
+        assert_eq!(
+            bar(ms(300), ms(100), ms(400)),
+            format!("{}{}", " ".repeat(30), "█".repeat(10))
+        );
+        // too short to be seen
+        assert_eq!(bar(ms(400), ms(0), ms(400)).trim_start(), "█");
+
+        let mut waterfall = Waterfall::default();
+        waterfall.record("users.login", ms(0), ms(100), "200".to_string(), false);
+        waterfall.record("users.<create>", ms(100), ms(300), "500".to_string(), true);
+        let html = waterfall.to_html("api");
+        assert!(html.contains("users.&lt;create&gt;"));
+        assert!(html.contains("margin-left:25.00%;width:75.00%"));
+    }
+}
@@ -0,0 +1,351 @@
+//! create a http query by answering prompts, `--new-query <group>.<name>`
+//! query is appended to the file of the group keeping the rest of the file as it is
+
+use std::path::PathBuf;
+
+use miette::{Context, IntoDiagnostic};
+use tracing::info;
+
+const METHODS: [&str; 7] = ["GET", "POST", "PUT", "PATCH", "DELETE", "HEAD", "OPTIONS"];
+
+#[derive(Debug, Default)]
+enum Auth {
+    #[default]
+    None,
+    Basic {
+        user_name: String,
+        password: Option<String>,
+    },
+    Bearer(String),
+}
+
+#[derive(Debug, Default)]
+enum Body {
+    #[default]
+    None,
+    Json(String),
+    JsonFile(PathBuf),
+    Text {
+        content_type: String,
+        text: String,
+    },
+    /// content type is guessed from the file
+    File(PathBuf),
+    Form(Vec<(String, String)>),
+}
+
+/// answers of the prompts
+#[derive(Debug, Default)]
+struct Answers {
+    description: Option<String>,
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+    auth: Auth,
+    body: Body,
+}
+
+impl Answers {
+    fn ask() -> miette::Result<Self> {
+        let description = input("description(optional)", true, |_| Ok(()))?;
+        let method = METHODS[select("method", &METHODS)?].to_string();
+        let path = input("path", false, |path| {
+            if path.starts_with('/') {
+                Ok(())
+            } else {
+                Err("path should start with /".to_string())
+            }
+        })?;
+        let headers = pairs("header(name: value, empty to finish)", ':', |name| {
+            reqwest::header::HeaderName::from_bytes(name.as_bytes())
+                .map(drop)
+                .map_err(|e| format!("invalid header name: {e}"))
+        })?;
+        let auth = match select("auth", &["none", "basic", "bearer"])? {
+            0 => Auth::None,
+            1 => Auth::Basic {
+                user_name: input("user name", false, |_| Ok(()))?,
+                password: Some(input("password(optional)", true, |_| Ok(()))?)
+                    .filter(|password| !password.is_empty()),
+            },
+            _ => Auth::Bearer(input("token", false, |_| Ok(()))?),
+        };
+        let body = match select(
+            "body",
+            &["none", "json", "json file", "text", "file", "form"],
+        )? {
+            0 => Body::None,
+            1 => Body::Json(input("json", false, |json| {
+                serde_json::from_str::<serde_json::Value>(json)
+                    .map(drop)
+                    .map_err(|e| format!("invalid json: {e}"))
+            })?),
+            2 => Body::JsonFile(input("file", false, file_exists)?.into()),
+            3 => Body::Text {
+                content_type: input("content type", false, |content_type| {
+                    content_type
+                        .parse::<mime::Mime>()
+                        .map(drop)
+                        .map_err(|e| format!("invalid content type: {e}"))
+                })?,
+                text: input("text", false, |_| Ok(()))?,
+            },
+            4 => Body::File(input("file", false, file_exists)?.into()),
+            _ => Body::Form(pairs(
+                "field(name=value, empty to finish)",
+                '=',
+                |_| Ok(()),
+            )?),
+        };
+        Ok(Self {
+            description: Some(description).filter(|description| !description.is_empty()),
+            method,
+            path,
+            headers,
+            auth,
+            body,
+        })
+    }
+
+    /// toml table of the query
+    fn table(&self) -> toml_edit::Table {
+        use toml_edit::{value, InlineTable};
+        let mut table = toml_edit::Table::new();
+        if let Some(description) = &self.description {
+            table["description"] = value(description);
+        }
+        table["method"] = value(&self.method);
+        table["path"] = value(&self.path);
+        if !self.headers.is_empty() {
+            let headers: InlineTable = self
+                .headers
+                .iter()
+                .map(|(name, header)| (name.as_str(), header.as_str()))
+                .collect();
+            table["headers"] = value(headers);
+        }
+        match &self.auth {
+            Auth::None => {}
+            Auth::Basic {
+                user_name,
+                password,
+            } => {
+                let mut auth = InlineTable::new();
+                auth.insert("user_name", user_name.into());
+                if let Some(password) = password {
+                    auth.insert("password", password.into());
+                }
+                table["basic_auth"] = value(auth);
+            }
+            Auth::Bearer(token) => table["bearer_auth"] = value(token),
+        }
+        let body = |kind: &str, fields: &[(&str, &str)]| {
+            let mut body = InlineTable::new();
+            let fields: InlineTable = fields.iter().copied().collect();
+            body.insert(kind, fields.into());
+            value(body)
+        };
+        match &self.body {
+            Body::None => {}
+            Body::Json(json) => {
+                table["body"] = body("application/json", &[("inline", json)]);
+            }
+            Body::JsonFile(file) => {
+                table["body"] = body("application/json", &[("file", &file.display().to_string())]);
+            }
+            Body::Text { content_type, text } => {
+                table["body"] = body(
+                    "raw_text",
+                    &[("content_type", content_type), ("inline", text)],
+                );
+            }
+            Body::File(file) => {
+                table["body"] = body("raw", &[("file", &file.display().to_string())]);
+            }
+            Body::Form(fields) => {
+                let form: InlineTable = fields
+                    .iter()
+                    .map(|(name, field)| (name.as_str(), field.as_str()))
+                    .collect();
+                table["form"] = value(form);
+            }
+        }
+        table
+    }
+}
+
+fn term() -> dialoguer::console::Term {
+    dialoguer::console::Term::stderr()
+}
+
+fn input(
+    prompt: &str,
+    allow_empty: bool,
+    validate: impl Fn(&str) -> Result<(), String>,
+) -> miette::Result<String> {
+    dialoguer::Input::<String>::new()
+        .with_prompt(prompt)
+        .allow_empty(allow_empty)
+        .validate_with(|value: &String| validate(value))
+        .interact_text_on(&term())
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Couldn't read {prompt}"))
+}
+
+fn select(prompt: &str, items: &[&str]) -> miette::Result<usize> {
+    dialoguer::Select::new()
+        .with_prompt(prompt)
+        .items(items)
+        .default(0)
+        .interact_on(&term())
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Couldn't read {prompt}"))
+}
+
+/// ask `name<separator>value` pairs until an empty one is given
+fn pairs(
+    prompt: &str,
+    separator: char,
+    validate_name: impl Fn(&str) -> Result<(), String>,
+) -> miette::Result<Vec<(String, String)>> {
+    let mut pairs = Vec::new();
+    loop {
+        let pair = input(prompt, true, |pair| {
+            if pair.is_empty() {
+                return Ok(());
+            }
+            let Some((name, _)) = pair.split_once(separator) else {
+                return Err(format!("expected name{separator}value"));
+            };
+            validate_name(name.trim())
+        })?;
+        let Some((name, value)) = pair.split_once(separator) else {
+            return Ok(pairs);
+        };
+        pairs.push((name.trim().to_string(), value.trim().to_string()));
+    }
+}
+
+fn file_exists(path: &str) -> Result<(), String> {
+    if std::path::Path::new(path).is_file() {
+        Ok(())
+    } else {
+        Err(format!("{path} is not a file"))
+    }
+}
+
+/// ask the query details and add it to the group file
+/// returns whether the query should be executed once
+pub fn new_query(groups: &crate::parser::Group, endpoint: &[String]) -> miette::Result<bool> {
+    let Some((name, group_path)) = endpoint.split_last() else {
+        miette::bail!(
+            help = "ex: --new-query users.create",
+            "name of the query is missing"
+        )
+    };
+    if groups
+        .find(endpoint)
+        .is_some_and(|found| found.query.is_some())
+    {
+        miette::bail!("query {} already exists", endpoint.join("."))
+    }
+    let (file, key) = groups.http_location(group_path)?;
+    if !std::io::IsTerminal::is_terminal(&std::io::stderr()) {
+        miette::bail!("--new-query needs a terminal to ask the details")
+    }
+    let answers = Answers::ask()?;
+    add(&file, &key, name, answers.table())?;
+    eprintln!("added {} to {}", endpoint.join("."), file.display());
+    dialoguer::Confirm::new()
+        .with_prompt("run it now?")
+        .default(false)
+        .interact_on(&term())
+        .into_diagnostic()
+        .wrap_err("Couldn't read answer")
+}
+
+/// add the query table at `<key>.query.<name>` of the file
+fn add(
+    file: &std::path::Path,
+    key: &[String],
+    name: &str,
+    query: toml_edit::Table,
+) -> miette::Result<()> {
+    let content = std::fs::read_to_string(file)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Couldn't read {file:?}"))?;
+    let mut document: toml_edit::DocumentMut = content
+        .parse()
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Couldn't parse {file:?}"))?;
+    let mut table = document.as_table_mut();
+    for part in key.iter().map(String::as_str).chain(["query"]) {
+        let entry = table.entry(part).or_insert_with(|| {
+            let mut table = toml_edit::Table::new();
+            table.set_implicit(true);
+            toml_edit::Item::Table(table)
+        });
+        let Some(inner) = entry.as_table_mut() else {
+            miette::bail!("{part} of {file:?} is not a table")
+        };
+        table = inner;
+    }
+    if table.contains_key(name) {
+        miette::bail!("query {name} already exists in {file:?}")
+    }
+    table.insert(name, toml_edit::Item::Table(query));
+    info!("writing query {name} to {file:?}");
+    std::fs::write(file, document.to_string())
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Couldn't write {file:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_is_added_to_group_file() {
+        let file = std::env::temp_dir().join(format!("qwicket-wizard-{}.toml", std::process::id()));
+        std::fs::write(
+            &file,
+            "type = \"http\"\n# kept\n[query.list]\nmethod = \"GET\"\npath = \"/users\"\n",
+        )
+        .unwrap();
+        let answers = Answers {
+            description: Some("create an user".to_string()),
+            method: "POST".to_string(),
+            path: "/users".to_string(),
+            headers: vec![("x-team".to_string(), "qa".to_string())],
+            auth: Auth::Bearer("${TOKEN}".to_string()),
+            body: Body::Json(r#"{"name": "a"}"#.to_string()),
+        };
+        add(&file, &[], "create", answers.table()).unwrap();
+        assert!(add(&file, &[], "create", Answers::default().table()).is_err());
+
+        let content = std::fs::read_to_string(&file).unwrap();
+        assert!(content.contains("# kept"));
+        let group: toml::Table = toml::from_str(&content).unwrap();
+        let query: crate::agent::http::Query = group["query"]["create"].clone().try_into().unwrap();
+        assert_eq!(
+            query,
+            toml::from_str(
+                r#"
+                method = "POST"
+                path = "/users"
+                headers = { x-team = "qa" }
+                "#
+            )
+            .unwrap()
+        );
+        // fields which are not compared by the query equality
+        let create = group["query"]["create"].as_table().unwrap();
+        assert_eq!(create["description"].as_str(), Some("create an user"));
+        assert_eq!(create["bearer_auth"].as_str(), Some("${TOKEN}"));
+        assert_eq!(
+            create["body"]["application/json"]["inline"].as_str(),
+            Some(r#"{"name": "a"}"#)
+        );
+        std::fs::remove_file(file).unwrap();
+    }
+}
@@ -0,0 +1,84 @@
+//! temporary directory of a query execution shared by its hooks and body commands
+//! path is given to them in `QWICKET_WORKSPACE` and the directory is removed after the query unless `--keep-temp`
+//! directory is created only when a hook or body command needs it
+
+use std::path::Path;
+use std::sync::OnceLock;
+
+use miette::{Context, IntoDiagnostic};
+use tracing::{debug, info, warn};
+
+use crate::constants::KEY_WORKSPACE;
+
+#[derive(Debug)]
+pub struct Workspace {
+    dir: OnceLock<tempfile::TempDir>,
+    keep: bool,
+}
+
+impl Workspace {
+    pub fn new(keep: bool) -> Self {
+        Self {
+            dir: OnceLock::new(),
+            keep,
+        }
+    }
+
+    /// create the directory on first use, name has a random suffix so queries of the same process(ex: while racing)
+    /// and left over directories don't collide
+    pub fn path(&self) -> miette::Result<&Path> {
+        if let Some(dir) = self.dir.get() {
+            return Ok(dir.path());
+        }
+        let dir = tempfile::Builder::new()
+            .prefix(concat!(env!("CARGO_PKG_NAME"), "-"))
+            .tempdir()
+            .into_diagnostic()
+            .wrap_err("Couldn't create workspace")?;
+        debug!("created workspace {:?}", dir.path());
+        Ok(self.dir.get_or_init(|| dir).path())
+    }
+
+    /// give the workspace to a process run by the query
+    pub fn configure(path: &Path, command: &mut std::process::Command) {
+        command.env(KEY_WORKSPACE, path);
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        let Some(dir) = self.dir.take() else {
+            return;
+        };
+        if self.keep {
+            info!("workspace is kept at {:?}", dir.keep());
+            return;
+        }
+        let path = dir.path().to_path_buf();
+        if let Err(e) = dir.close() {
+            warn!("Couldn't remove workspace {path:?}: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removed_unless_kept() {
+        let workspace = Workspace::new(false);
+        let path = workspace.path().unwrap().to_path_buf();
+        assert_eq!(workspace.path().unwrap(), path);
+        std::fs::write(path.join("body.bin"), b"large").unwrap();
+        drop(workspace);
+        assert!(!path.exists());
+
+        let kept = Workspace::new(true);
+        let path = kept.path().unwrap().to_path_buf();
+        assert_ne!(path, Workspace::new(false).path().unwrap());
+        drop(kept);
+        assert!(path.exists());
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}